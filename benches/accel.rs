@@ -0,0 +1,66 @@
+//! Compares acceleration structures over the same scene: build and trace
+//! times, and the work a ray takes in each.
+//!
+//! Run with `cargo bench --bench accel`.
+
+mod common;
+
+use common::*;
+
+use pbrt::accel::*;
+use pbrt::material::NullMaterial;
+use pbrt::prelude::*;
+
+const SIZE: (usize, usize) = (640, 360);
+
+/// Traces one primary ray per pixel, sums up the work done.
+fn frame_stats(scene: &dyn Aggregate) -> TraversalStats {
+    let (nx, ny) = SIZE;
+    let camera = camera(nx as Float / ny as Float);
+    let mut stats = TraversalStats::default();
+    for j in 0..ny {
+        for i in 0..nx {
+            let u = (i as Float + 0.5) / nx as Float;
+            let v = (j as Float + 0.5) / ny as Float;
+            let ray = camera.get_ray(u, v);
+            scene.hit_with_stats(&ray, 1.0e-4, Float::INFINITY, &mut stats);
+        }
+    }
+    stats
+}
+
+fn report<A: Aggregate>(name: &str, build: impl FnOnce() -> A) {
+    let (scene, build_time) = time(build);
+    let trace_time = best_of(5, || {
+        trace_frame(&scene, SIZE);
+    });
+    let stats = frame_stats(&scene);
+    println!(
+        "{:<8} {:>10.1} {:>10.1} {:>10.2} {:>10.1} {:>10.1}",
+        name,
+        build_time.as_secs_f64() * 1.0e3,
+        trace_time.as_secs_f64() * 1.0e3,
+        mrays_per_second(SIZE.0 * SIZE.1, trace_time),
+        stats.nodes_per_ray(),
+        stats.primitives_per_ray()
+    );
+}
+
+fn main() {
+    println!(
+        "{} threads, {}x{} primary rays",
+        rayon::current_num_threads(),
+        SIZE.0,
+        SIZE.1
+    );
+    println!(
+        "{:<8} {:>10} {:>10} {:>10} {:>10} {:>10}",
+        "accel", "build ms", "trace ms", "Mrays/s", "nodes/ray", "prims/ray"
+    );
+
+    report("bvh", || Bvh::new(spheres_scene(&NullMaterial)));
+    report("bvh4", || Bvh4::new(spheres_scene(&NullMaterial)));
+    report("bvh8", || Bvh8::new(spheres_scene(&NullMaterial)));
+    report("kdtree", || KdTree::new(spheres_scene(&NullMaterial)));
+    report("grid", || Grid::new(spheres_scene(&NullMaterial)));
+}
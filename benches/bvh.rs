@@ -0,0 +1,49 @@
+//! Renders primary rays over a scene of 100k triangles using a BVH.
+//!
+//! Run with `cargo bench --bench bvh`.
+
+mod common;
+
+use common::*;
+
+use pbrt::accel::Bvh;
+use pbrt::material::NullMaterial;
+
+fn main() {
+    let triangles = spheres_scene(&NullMaterial);
+    let n_triangles = triangles.len();
+
+    let size = (32, 18);
+    let linear_time = best_of(1, || {
+        trace_frame(&Linear(&triangles), size);
+    });
+    println!(
+        "Linear search over {} triangles, {}x{} primary rays: {:.4} Mrays/s",
+        n_triangles,
+        size.0,
+        size.1,
+        mrays_per_second(size.0 * size.1, linear_time)
+    );
+
+    let (bvh, build_time) = time(|| Bvh::new(triangles));
+    println!(
+        "BVH over {} triangles: {} nodes, built in {:.1} ms",
+        n_triangles,
+        bvh.node_count(),
+        build_time.as_secs_f64() * 1.0e3
+    );
+
+    for &(nx, ny) in &[(320, 180), (640, 360), (1280, 720)] {
+        let frame_time = best_of(5, || {
+            trace_frame(&bvh, (nx, ny));
+        });
+        println!(
+            "{:>4}x{:<4} primary rays: {:7.2} ms/frame, {:6.1} fps, {:6.2} Mrays/s",
+            nx,
+            ny,
+            frame_time.as_secs_f64() * 1.0e3,
+            1.0 / frame_time.as_secs_f64(),
+            mrays_per_second(nx * ny, frame_time)
+        );
+    }
+}
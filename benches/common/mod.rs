@@ -0,0 +1,127 @@
+//! Scenes and timing helpers shared by the benchmarks.
+
+#![allow(dead_code)]
+
+use std::time::{Duration, Instant};
+
+use pbrt::camera::*;
+use pbrt::geo::*;
+use pbrt::hit::{Hit, HitStruct};
+use pbrt::material::Material;
+use pbrt::prelude::*;
+use pbrt::shape::triangle::Triangle;
+
+use std::f32::consts::PI;
+
+/// Triangulated UV sphere, `2 * n_theta * n_phi` triangles.
+pub fn sphere_mesh<'a>(
+    center: Point3f,
+    radius: Float,
+    (n_theta, n_phi): (usize, usize),
+    material: &'a dyn Material,
+) -> Vec<Triangle<'a>> {
+    let vertex = |i: usize, j: usize| {
+        let theta = i as Float / n_theta as Float * PI;
+        let phi = j as Float / n_phi as Float * 2.0 * PI;
+        let d = vec3(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        center + d * radius
+    };
+    let mut triangles = Vec::with_capacity(2 * n_theta * n_phi);
+    for i in 0..n_theta {
+        for j in 0..n_phi {
+            let (p00, p01) = (vertex(i, j), vertex(i, j + 1));
+            let (p10, p11) = (vertex(i + 1, j), vertex(i + 1, j + 1));
+            for &positions in &[[p00, p01, p11], [p00, p11, p10]] {
+                triangles.push(Triangle {
+                    positions,
+                    material,
+                    backface_culling: false,
+                    uvs: None,
+                    tangents: None,
+                });
+            }
+        }
+    }
+    triangles
+}
+
+/// Five spheres, a bit over 100k triangles in total.
+pub fn spheres_scene(material: &dyn Material) -> Vec<Triangle<'_>> {
+    let mut triangles = Vec::new();
+    for i in 0..5 {
+        let center = point3(
+            i as Float * 1.2 - 2.4,
+            (i % 2) as Float * 0.3,
+            -(i as Float) * 0.5,
+        );
+        triangles.extend(sphere_mesh(center, 0.5, (64, 160), material));
+    }
+    triangles
+}
+
+/// Tests every primitive for every ray, for comparison.
+pub struct Linear<'a, P>(pub &'a [P]);
+
+impl<P: Hit> Hit for Linear<'_, P> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        let mut closest = t_max;
+        let mut result = None;
+        for p in self.0 {
+            if let Some(hit) = p.hit(ray, t_min, closest) {
+                closest = hit.t;
+                result = Some(hit);
+            }
+        }
+        result
+    }
+}
+
+pub fn camera(aspect: Float) -> Camera {
+    Camera::from_spec(CameraSpec {
+        vfov: 60.0,
+        aspect,
+        look_from: point3(0.0, 1.0, 4.0),
+        look_at: point3(0.0, 0.0, -1.0),
+        up: vec3(0.0, 1.0, 0.0),
+    })
+}
+
+/// Traces one primary ray per pixel, returns the number of hits.
+pub fn trace_frame(scene: &dyn Hit, (nx, ny): (usize, usize)) -> usize {
+    use rayon::prelude::*;
+
+    let camera = camera(nx as Float / ny as Float);
+    (0..ny)
+        .into_par_iter()
+        .map(|j| {
+            (0..nx)
+                .filter(|&i| {
+                    let u = (i as Float + 0.5) / nx as Float;
+                    let v = (j as Float + 0.5) / ny as Float;
+                    let ray = camera.get_ray(u, v);
+                    scene.hit(&ray, 1.0e-4, Float::INFINITY).is_some()
+                })
+                .count()
+        })
+        .sum()
+}
+
+/// Runs `f` once, returns its result and how long it took.
+pub fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+    let start = Instant::now();
+    let result = f();
+    (result, start.elapsed())
+}
+
+/// Best of `n` runs of `f`.
+pub fn best_of(n: usize, mut f: impl FnMut()) -> Duration {
+    (0..n).map(|_| time(&mut f).1).min().unwrap_or_default()
+}
+
+pub fn mrays_per_second(rays: usize, duration: Duration) -> f64 {
+    rays as f64 / duration.as_secs_f64() / 1.0e6
+}
@@ -0,0 +1,71 @@
+//! Compares build and trace times of BVH construction algorithms.
+//!
+//! Run with `cargo bench --bench lbvh`.
+
+mod common;
+
+use common::*;
+
+use pbrt::accel::{Bvh, BvhSpec, SplitMethod};
+use pbrt::material::NullMaterial;
+
+fn main() {
+    let size = (640, 360);
+    println!(
+        "{} threads, {}x{} primary rays",
+        rayon::current_num_threads(),
+        size.0,
+        size.1
+    );
+    println!(
+        "{:<16} {:>10} {:>10} {:>10} {:>10}",
+        "builder", "build ms", "SAH cost", "trace ms", "Mrays/s"
+    );
+
+    let methods = [
+        ("SAH", SplitMethod::Sah),
+        (
+            "LBVH",
+            SplitMethod::Lbvh {
+                optimize_treelets: false,
+            },
+        ),
+        (
+            "LBVH + treelets",
+            SplitMethod::Lbvh {
+                optimize_treelets: true,
+            },
+        ),
+        (
+            "SBVH",
+            SplitMethod::Sbvh {
+                max_duplication: 0.3,
+            },
+        ),
+    ];
+
+    for &(name, split_method) in &methods {
+        let spec = BvhSpec {
+            split_method,
+            ..Default::default()
+        };
+        let (bvh, build_time) = (0..3)
+            .map(|_| {
+                let triangles = spheres_scene(&NullMaterial);
+                time(|| Bvh::from_spec(triangles, spec))
+            })
+            .min_by_key(|(_, build_time)| *build_time)
+            .unwrap();
+        let trace_time = best_of(3, || {
+            trace_frame(&bvh, size);
+        });
+        println!(
+            "{:<16} {:>10.1} {:>10.1} {:>10.1} {:>10.2}",
+            name,
+            build_time.as_secs_f64() * 1.0e3,
+            bvh.sah_cost(),
+            trace_time.as_secs_f64() * 1.0e3,
+            mrays_per_second(size.0 * size.1, trace_time)
+        );
+    }
+}
@@ -0,0 +1,63 @@
+//! Compares trace times of binary and wide BVHs over the same tree.
+//!
+//! Run with `cargo bench --bench wide_bvh`.
+
+mod common;
+
+use common::*;
+
+use pbrt::accel::{Bvh, Bvh4, Bvh8};
+use pbrt::hit::Hit;
+use pbrt::material::NullMaterial;
+
+fn main() {
+    let size = (640, 360);
+    println!(
+        "{} threads, {}x{} primary rays",
+        rayon::current_num_threads(),
+        size.0,
+        size.1
+    );
+    println!(
+        "{:<8} {:>10} {:>10} {:>10} {:>10}",
+        "layout", "nodes", "build ms", "trace ms", "Mrays/s"
+    );
+
+    let report = |name: &str, scene: &dyn Hit, nodes: usize, build_ms: f64| {
+        let trace_time = best_of(5, || {
+            trace_frame(scene, size);
+        });
+        println!(
+            "{:<8} {:>10} {:>10.1} {:>10.1} {:>10.2}",
+            name,
+            nodes,
+            build_ms,
+            trace_time.as_secs_f64() * 1.0e3,
+            mrays_per_second(size.0 * size.1, trace_time)
+        );
+    };
+
+    let (bvh, build_time) = time(|| Bvh::new(spheres_scene(&NullMaterial)));
+    report(
+        "binary",
+        &bvh,
+        bvh.node_count(),
+        build_time.as_secs_f64() * 1.0e3,
+    );
+
+    let (bvh, build_time) = time(|| Bvh4::new(spheres_scene(&NullMaterial)));
+    report(
+        "4-wide",
+        &bvh,
+        bvh.node_count(),
+        build_time.as_secs_f64() * 1.0e3,
+    );
+
+    let (bvh, build_time) = time(|| Bvh8::new(spheres_scene(&NullMaterial)));
+    report(
+        "8-wide",
+        &bvh,
+        bvh.node_count(),
+        build_time.as_secs_f64() * 1.0e3,
+    );
+}
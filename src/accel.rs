@@ -0,0 +1,113 @@
+/// Bounding volume hierarchy.
+pub mod bvh;
+
+/// Parallel construction of linear BVHs.
+mod lbvh;
+
+/// Construction of BVHs with spatial splits.
+mod sbvh;
+
+/// BVHs with more than two children per node.
+pub mod wide;
+
+/// On-disk cache of built BVHs over triangle meshes.
+pub mod cache;
+
+/// kd-tree built with the surface area heuristic.
+pub mod kdtree;
+
+/// Uniform grid of voxels.
+pub mod grid;
+
+pub use bvh::*;
+pub use grid::{Grid, GridSpec};
+pub use kdtree::{KdTree, KdTreeSpec};
+pub use wide::{Bvh4, Bvh8, WideBvh};
+
+use crate::prelude::*;
+
+use crate::geo::Ray;
+use crate::hit::HitStruct;
+use crate::primitive::Primitive;
+
+/// A primitive made of other primitives, organized to find hits quickly.
+///
+/// Besides finding hits, it can count the work it took, to compare
+/// different acceleration structures.
+pub trait Aggregate: Primitive {
+    /// Same as `hit`, and adds up the work done to `stats`.
+    fn hit_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>>;
+
+    /// Same as `occluded`, and adds up the work done to `stats`.
+    fn occluded_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> bool;
+}
+
+/// Work done tracing rays through an acceleration structure.
+///
+/// Stats of different rays are summed up with `+`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TraversalStats {
+    /// Number of rays traced
+    pub rays: u64,
+    /// Number of nodes (or voxels) visited
+    pub nodes: u64,
+    /// Number of ray-primitive intersection tests
+    pub primitives: u64,
+}
+
+impl TraversalStats {
+    /// Average number of nodes visited by a ray.
+    pub fn nodes_per_ray(&self) -> f64 {
+        self.nodes as f64 / self.rays.max(1) as f64
+    }
+
+    /// Average number of primitives tested by a ray.
+    pub fn primitives_per_ray(&self) -> f64 {
+        self.primitives as f64 / self.rays.max(1) as f64
+    }
+}
+
+///
+/// Sum of stats.
+///
+impl std::ops::Add for TraversalStats {
+    type Output = TraversalStats;
+
+    fn add(self, rhs: TraversalStats) -> TraversalStats {
+        TraversalStats {
+            rays: self.rays + rhs.rays,
+            nodes: self.nodes + rhs.nodes,
+            primitives: self.primitives + rhs.primitives,
+        }
+    }
+}
+
+impl std::ops::AddAssign for TraversalStats {
+    fn add_assign(&mut self, rhs: TraversalStats) {
+        *self = *self + rhs;
+    }
+}
+
+impl std::fmt::Display for TraversalStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} rays, {:.1} nodes and {:.1} primitives per ray",
+            self.rays,
+            self.nodes_per_ray(),
+            self.primitives_per_ray()
+        )
+    }
+}
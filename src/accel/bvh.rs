@@ -0,0 +1,849 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::*;
+use crate::primitive::Primitive;
+
+use super::{lbvh, sbvh, Aggregate, TraversalStats};
+
+/// Parameters of BVH construction.
+#[derive(Copy, Clone, Debug)]
+pub struct BvhSpec {
+    /// Maximum number of primitives in a leaf node.
+    pub max_prims_in_node: usize,
+    /// How to build the hierarchy.
+    pub split_method: SplitMethod,
+}
+
+impl Default for BvhSpec {
+    fn default() -> BvhSpec {
+        BvhSpec {
+            max_prims_in_node: 4,
+            split_method: SplitMethod::Sah,
+        }
+    }
+}
+
+/// BVH construction algorithms.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SplitMethod {
+    /// Top-down, single-threaded, using the surface area heuristic. Slowest
+    /// to build, fastest to trace.
+    Sah,
+    /// Linear BVH: primitives are sorted along a Morton curve, and the tree
+    /// is built from the bits of their codes, in parallel. Fast to build, but
+    /// slower to trace, unless treelets are optimized afterwards.
+    Lbvh {
+        /// Restructure small treelets of the hierarchy to lower their SAH
+        /// cost, after Karras and Aila, "Fast Parallel Construction of
+        /// High-Quality Bounding Volume Hierarchies" (HPG 2013).
+        optimize_treelets: bool,
+    },
+    /// Top-down, single-threaded, like `Sah`, but also considers splitting
+    /// space instead of primitives, for primitives which overlap a lot, such
+    /// as long thin triangles. References to primitives crossing a split
+    /// plane are duplicated on both sides of it.
+    Sbvh {
+        /// How many more references than primitives can be made, e.g. `0.3`
+        /// allows 30% more.
+        max_duplication: Float,
+    },
+}
+
+/// Bounding volume hierarchy over a set of primitives, built with the
+/// surface area heuristic.
+///
+/// Nodes are stored flattened in depth-first order: the first child of an
+/// interior node immediately follows it, and the node keeps the index of the
+/// second child.
+///
+/// When primitives move, as in animations, the tree can be refitted to their
+/// new bounds instead of being rebuilt. See `refit`.
+pub struct Bvh<P> {
+    pub(crate) primitives: Vec<P>,
+    /// Indices into `primitives`, in the order leaf nodes refer to them.
+    pub(crate) indices: Vec<u32>,
+    pub(crate) nodes: Vec<Node>,
+    /// Parameters the tree was built with, to rebuild it the same way.
+    pub(crate) spec: BvhSpec,
+    /// SAH cost of the tree right after it was built.
+    pub(crate) built_cost: Float,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Node {
+    pub(crate) bounds: Bounds3f,
+    /// Leaf nodes: offset of the first primitive index. Interior nodes: index
+    /// of the second child.
+    pub(crate) offset: u32,
+    /// Number of primitives in a leaf node, zero for interior nodes.
+    pub(crate) n_primitives: u16,
+    /// Axis an interior node was split along.
+    pub(crate) axis: u8,
+}
+
+impl Node {
+    pub(crate) fn leaf(bounds: Bounds3f, offset: usize, n_primitives: usize) -> Node {
+        Node {
+            bounds,
+            offset: offset as u32,
+            n_primitives: n_primitives as u16,
+            axis: 0,
+        }
+    }
+
+    pub(crate) fn interior(bounds: Bounds3f, second_child: usize, axis: usize) -> Node {
+        Node {
+            bounds,
+            offset: second_child as u32,
+            n_primitives: 0,
+            axis: axis as u8,
+        }
+    }
+
+    pub(crate) fn is_leaf(&self) -> bool {
+        self.n_primitives > 0
+    }
+}
+
+/// Primitive information needed during construction.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct BuildPrimitive {
+    pub(crate) index: u32,
+    pub(crate) bounds: Bounds3f,
+    pub(crate) centroid: Point3f,
+}
+
+impl BuildPrimitive {
+    pub(crate) fn new(index: usize, bounds: Bounds3f) -> BuildPrimitive {
+        BuildPrimitive {
+            index: index as u32,
+            bounds,
+            centroid: bounds.centroid(),
+        }
+    }
+}
+
+/// Deepest a tree can get. Traversal keeps a stack of this size.
+pub(crate) const MAX_DEPTH: usize = 64;
+
+/// Past this depth, the builder stops looking for good splits and simply
+/// halves primitive counts, so that `MAX_DEPTH` is never exceeded.
+const MAX_SAH_DEPTH: usize = 32;
+
+/// Number of buckets to bin primitive centroids into.
+pub(crate) const N_BUCKETS: usize = 12;
+
+impl<P: Primitive> Bvh<P> {
+    /// Builds a BVH over given primitives, with default parameters.
+    pub fn new(primitives: Vec<P>) -> Bvh<P> {
+        Bvh::from_spec(primitives, BvhSpec::default())
+    }
+
+    /// Builds a BVH over given primitives.
+    pub fn from_spec(primitives: Vec<P>, spec: BvhSpec) -> Bvh<P> {
+        use rayon::prelude::*;
+
+        let refs: Vec<_> = primitives
+            .par_iter()
+            .enumerate()
+            .map(|(i, p)| BuildPrimitive::new(i, p.world_bound()))
+            .collect();
+        let max_prims_in_node = spec.max_prims_in_node.clamp(1, u16::MAX as usize);
+
+        let (nodes, indices) = match spec.split_method {
+            SplitMethod::Sah => build_sah(refs, max_prims_in_node),
+            SplitMethod::Lbvh { optimize_treelets } => {
+                lbvh::build(refs, max_prims_in_node, optimize_treelets)
+            }
+            SplitMethod::Sbvh { max_duplication } => {
+                sbvh::build(&primitives, refs, max_prims_in_node, max_duplication)
+            }
+        };
+
+        let mut bvh = Bvh {
+            primitives,
+            indices,
+            nodes,
+            spec,
+            built_cost: 0.0,
+        };
+        bvh.built_cost = bvh.sah_cost();
+        bvh
+    }
+
+    /// Updates bounds of all nodes to fit primitives, after they have been
+    /// changed with `primitives_mut`. The structure of the tree stays the
+    /// same, so it's much faster than a rebuild, but the tree gets worse as
+    /// primitives move away from where they were at build time.
+    ///
+    /// Trees built with spatial splits get looser bounds after a refit,
+    /// since leaves are fitted to whole primitives, not parts of them.
+    pub fn refit(&mut self) {
+        use rayon::prelude::*;
+
+        let Bvh {
+            primitives,
+            indices,
+            nodes,
+            ..
+        } = self;
+
+        nodes
+            .par_iter_mut()
+            .filter(|node| node.is_leaf())
+            .for_each(|node| {
+                let first = node.offset as usize;
+                node.bounds = indices[first..first + node.n_primitives as usize]
+                    .iter()
+                    .fold(Bounds3f::empty(), |b, &i| {
+                        Bounds3::union(&b, &primitives[i as usize].world_bound())
+                    });
+            });
+
+        // Children always follow their parents, so going backwards visits
+        // them first
+        for i in (0..nodes.len()).rev() {
+            if !nodes[i].is_leaf() {
+                let second = nodes[i].offset as usize;
+                nodes[i].bounds = Bounds3::union(&nodes[i + 1].bounds, &nodes[second].bounds);
+            }
+        }
+    }
+
+    /// Rebuilds the tree from scratch, with the same parameters, over the
+    /// current primitives.
+    pub fn rebuild(&mut self) {
+        let primitives = std::mem::take(&mut self.primitives);
+        *self = Bvh::from_spec(primitives, self.spec);
+    }
+
+    /// Refits the tree, and if that makes it more than `max_degradation`
+    /// times as costly to trace as it was right after the build, rebuilds it.
+    /// Returns true if the tree was rebuilt.
+    ///
+    /// Something like `1.5` is a fair trade-off for animations, where
+    /// primitives move a little every frame.
+    pub fn refit_or_rebuild(&mut self, max_degradation: Float) -> bool {
+        self.refit();
+        if self.degradation() > max_degradation {
+            self.rebuild();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl<P> Bvh<P> {
+    /// Primitives the BVH was built over, in their original order.
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Primitives the BVH was built over, in their original order, to update
+    /// them in place. Once they're updated, the tree must be refitted or
+    /// rebuilt before it's traced again, or hits will be missed.
+    pub fn primitives_mut(&mut self) -> &mut [P] {
+        &mut self.primitives
+    }
+
+    /// Number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Expected cost of tracing a ray through the tree, according to the
+    /// surface area heuristic, relative to the cost of a primitive test.
+    /// Lower is better.
+    pub fn sah_cost(&self) -> Float {
+        let root_area = match self.nodes.first() {
+            Some(root) if root.bounds.area() > 0.0 => root.bounds.area(),
+            _ => return 0.0,
+        };
+        self.nodes
+            .iter()
+            .map(|node| {
+                let cost = if node.is_leaf() {
+                    node.n_primitives as Float
+                } else {
+                    1.0
+                };
+                cost * node.bounds.area() / root_area
+            })
+            .sum()
+    }
+
+    /// SAH cost of the tree relative to what it was right after it was built.
+    /// It grows as the tree is refitted to moving primitives.
+    pub fn degradation(&self) -> Float {
+        if self.built_cost > 0.0 {
+            self.sah_cost() / self.built_cost
+        } else {
+            1.0
+        }
+    }
+}
+
+impl<P: Primitive> Hit for Bvh<P> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.hit_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.occluded_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+}
+
+impl<P: Primitive> Aggregate for Bvh<P> {
+    fn hit_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>> {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let d = ray.direction();
+        let dir_is_neg = [d.x < 0.0, d.y < 0.0, d.z < 0.0];
+
+        let mut closest = t_max;
+        let mut result = None;
+
+        // Nodes yet to be visited
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            stats.nodes += 1;
+            if node.bounds.hit(ray, (t_min, closest)) {
+                if node.is_leaf() {
+                    let first = node.offset as usize;
+                    let last = first + node.n_primitives as usize;
+                    stats.primitives += node.n_primitives as u64;
+                    for &i in &self.indices[first..last] {
+                        if let Some(hit) = self.primitives[i as usize].hit(ray, t_min, closest) {
+                            closest = hit.t;
+                            result = Some(hit);
+                        }
+                    }
+                } else {
+                    // Visit the nearer child first, and put the farther one
+                    // on the stack
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+
+        result
+    }
+
+    fn occluded_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> bool {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            return false;
+        }
+
+        let d = ray.direction();
+        let dir_is_neg = [d.x < 0.0, d.y < 0.0, d.z < 0.0];
+
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            stats.nodes += 1;
+            if node.bounds.hit(ray, (t_min, t_max)) {
+                if node.is_leaf() {
+                    let first = node.offset as usize;
+                    let last = first + node.n_primitives as usize;
+                    // Any hit will do, no need to look for the closest one
+                    for &i in &self.indices[first..last] {
+                        stats.primitives += 1;
+                        if self.primitives[i as usize].occluded(ray, t_min, t_max) {
+                            return true;
+                        }
+                    }
+                } else {
+                    // The nearer child is still more likely to block the ray
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    current = near;
+                    continue;
+                }
+            }
+            if stack_len == 0 {
+                return false;
+            }
+            stack_len -= 1;
+            current = stack[stack_len];
+        }
+    }
+}
+
+impl<P: Primitive> Primitive for Bvh<P> {
+    fn world_bound(&self) -> Bounds3f {
+        self.nodes
+            .first()
+            .map(|node| node.bounds)
+            .unwrap_or_else(Bounds3f::empty)
+    }
+}
+
+fn build_sah(mut refs: Vec<BuildPrimitive>, max_prims_in_node: usize) -> (Vec<Node>, Vec<u32>) {
+    let mut builder = SahBuilder {
+        max_prims_in_node,
+        nodes: Vec::with_capacity(2 * refs.len()),
+        indices: Vec::with_capacity(refs.len()),
+    };
+    if !refs.is_empty() {
+        builder.build(&mut refs, 0);
+    }
+    (builder.nodes, builder.indices)
+}
+
+/// Top-down BVH builder, using the surface area heuristic over binned
+/// primitive centroids.
+struct SahBuilder {
+    max_prims_in_node: usize,
+    nodes: Vec<Node>,
+    indices: Vec<u32>,
+}
+
+impl SahBuilder {
+    /// Builds a subtree over `refs`, returns the index of its root node.
+    fn build(&mut self, refs: &mut [BuildPrimitive], depth: usize) -> usize {
+        let bounds = refs
+            .iter()
+            .fold(Bounds3f::empty(), |b, r| Bounds3::union(&b, &r.bounds));
+        let n = refs.len();
+
+        if n == 1 {
+            return self.leaf(bounds, refs);
+        }
+
+        let centroid_bounds = refs.iter().fold(Bounds3f::empty(), |b, r| {
+            Bounds3::union_point(&b, r.centroid)
+        });
+        let axis = centroid_bounds.maximum_extent();
+
+        let mid = if centroid_bounds.max[axis] == centroid_bounds.min[axis] {
+            // All centroids coincide, there's no way to tell primitives apart
+            if n <= self.max_prims_in_node {
+                return self.leaf(bounds, refs);
+            }
+            n / 2
+        } else if depth >= MAX_SAH_DEPTH {
+            split_equal_counts(refs, axis)
+        } else {
+            match self.split_sah(refs, &bounds, &centroid_bounds, axis) {
+                Some(mid) => mid,
+                None => return self.leaf(bounds, refs),
+            }
+        };
+
+        let node = self.nodes.len();
+        self.nodes.push(Node::interior(bounds, 0, axis));
+        let (left, right) = refs.split_at_mut(mid);
+        self.build(left, depth + 1);
+        let second_child = self.build(right, depth + 1);
+        self.nodes[node] = Node::interior(bounds, second_child, axis);
+        node
+    }
+
+    /// Partitions `refs` along the cheapest bucket boundary. Returns `None`
+    /// if making a leaf is cheaper than any split.
+    fn split_sah(
+        &self,
+        refs: &mut [BuildPrimitive],
+        bounds: &Bounds3f,
+        centroid_bounds: &Bounds3f,
+        axis: usize,
+    ) -> Option<usize> {
+        let bucket = |r: &BuildPrimitive| {
+            let b = (N_BUCKETS as Float * centroid_bounds.offset(r.centroid)[axis]) as usize;
+            b.min(N_BUCKETS - 1)
+        };
+
+        let mut counts = [0usize; N_BUCKETS];
+        let mut bucket_bounds = [Bounds3f::empty(); N_BUCKETS];
+        for r in refs.iter() {
+            let b = bucket(r);
+            counts[b] += 1;
+            bucket_bounds[b] = Bounds3::union(&bucket_bounds[b], &r.bounds);
+        }
+
+        let costs = sah_costs(&counts, &bucket_bounds, bounds.area());
+        let (split, &min_cost) = costs
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))?;
+
+        let n = refs.len();
+        if n > self.max_prims_in_node || min_cost < n as Float {
+            let mid = partition(refs, |r| bucket(r) <= split);
+            if mid == 0 || mid == n {
+                Some(split_equal_counts(refs, axis))
+            } else {
+                Some(mid)
+            }
+        } else {
+            None
+        }
+    }
+
+    fn leaf(&mut self, bounds: Bounds3f, refs: &[BuildPrimitive]) -> usize {
+        let node = self.nodes.len();
+        self.nodes
+            .push(Node::leaf(bounds, self.indices.len(), refs.len()));
+        self.indices.extend(refs.iter().map(|r| r.index));
+        node
+    }
+}
+
+/// Relative costs of splitting after each of the buckets but the last one,
+/// according to the surface area heuristic. The cost of intersecting a
+/// primitive is 1, and so is the cost of a traversal step.
+pub(crate) fn sah_costs(
+    counts: &[usize; N_BUCKETS],
+    bucket_bounds: &[Bounds3f; N_BUCKETS],
+    area: Float,
+) -> [Float; N_BUCKETS - 1] {
+    let inv_area = if area > 0.0 { area.recip() } else { 0.0 };
+
+    // Sweep from the right, to accumulate everything above each boundary
+    let mut above = [(0usize, 0.0 as Float); N_BUCKETS - 1];
+    let (mut count, mut b) = (0, Bounds3f::empty());
+    for i in (1..N_BUCKETS).rev() {
+        count += counts[i];
+        b = Bounds3::union(&b, &bucket_bounds[i]);
+        above[i - 1] = (count, if count > 0 { b.area() } else { 0.0 });
+    }
+
+    let mut costs = [0.0; N_BUCKETS - 1];
+    let (mut count, mut b) = (0, Bounds3f::empty());
+    for i in 0..N_BUCKETS - 1 {
+        count += counts[i];
+        b = Bounds3::union(&b, &bucket_bounds[i]);
+        let below = if count > 0 { b.area() } else { 0.0 };
+        let (count_above, area_above) = above[i];
+        costs[i] = 1.0 + (count as Float * below + count_above as Float * area_above) * inv_area;
+    }
+    costs
+}
+
+/// Splits primitives in two halves along the axis, by the position of their
+/// centroids.
+pub(crate) fn split_equal_counts(refs: &mut [BuildPrimitive], axis: usize) -> usize {
+    let mid = refs.len() / 2;
+    refs.select_nth_unstable_by(mid, |a, b| {
+        a.centroid[axis]
+            .partial_cmp(&b.centroid[axis])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    mid
+}
+
+/// Moves elements satisfying `pred` to the front, returns their count.
+pub(crate) fn partition<T>(items: &mut [T], pred: impl Fn(&T) -> bool) -> usize {
+    let mut first = 0;
+    for i in 0..items.len() {
+        if pred(&items[i]) {
+            items.swap(first, i);
+            first += 1;
+        }
+    }
+    first
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+
+    use super::*;
+    use crate::material::null::NullMaterial;
+    use crate::shape::sphere::Sphere;
+    use crate::shape::triangle::Triangle;
+
+    use rand::prelude::*;
+
+    pub(crate) fn random_triangles(n: usize, seed: u64) -> Vec<Triangle<'static>> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let point = |rng: &mut StdRng| {
+            point3(
+                rng.gen_range(-10.0, 10.0),
+                rng.gen_range(-10.0, 10.0),
+                rng.gen_range(-10.0, 10.0),
+            )
+        };
+        (0..n)
+            .map(|_| {
+                let p = point(&mut rng);
+                let size = rng.gen_range(0.1, 2.0);
+                let offset = |rng: &mut StdRng| {
+                    vec3(
+                        rng.gen_range(-size, size),
+                        rng.gen_range(-size, size),
+                        rng.gen_range(-size, size),
+                    )
+                };
+                let (a, b) = (offset(&mut rng), offset(&mut rng));
+                Triangle {
+                    positions: [p, p + a, p + b],
+                    material: &NullMaterial,
+                    backface_culling: false,
+                    uvs: None,
+                    tangents: None,
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn random_rays(n: usize, seed: u64) -> Vec<Ray> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        (0..n)
+            .map(|_| {
+                let o = point3(
+                    rng.gen_range(-15.0, 15.0),
+                    rng.gen_range(-15.0, 15.0),
+                    rng.gen_range(-15.0, 15.0),
+                );
+                let target = point3(
+                    rng.gen_range(-5.0, 5.0),
+                    rng.gen_range(-5.0, 5.0),
+                    rng.gen_range(-5.0, 5.0),
+                );
+                Ray::new(o, target - o)
+            })
+            .collect()
+    }
+
+    pub(crate) fn brute_force<P: Primitive>(primitives: &[P], ray: &Ray) -> Option<Float> {
+        let mut closest = Float::INFINITY;
+        let mut t = None;
+        for p in primitives {
+            if let Some(hit) = p.hit(ray, 0.0, closest) {
+                closest = hit.t;
+                t = Some(hit.t);
+            }
+        }
+        t
+    }
+
+    #[test]
+    fn bvh_finds_closest_hits() {
+        let triangles = random_triangles(2000, 1);
+        let expected: Vec<_> = random_rays(500, 2)
+            .iter()
+            .map(|r| brute_force(&triangles, r))
+            .collect();
+
+        assert!(expected.iter().any(|t| t.is_some()));
+
+        let bvh = Bvh::new(triangles);
+        for (ray, expected) in random_rays(500, 2).iter().zip(expected) {
+            let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, expected);
+        }
+    }
+
+    #[test]
+    fn bvh_occlusion_agrees_with_hits() {
+        let triangles = random_triangles(2000, 7);
+        let spheres: Vec<_> = (0..20)
+            .map(|i| Sphere {
+                center: point3(i as Float - 10.0, 2.0, 0.0),
+                radius: 0.5,
+                material: &NullMaterial,
+            })
+            .collect();
+        let mut primitives: Vec<&dyn Primitive> = Vec::new();
+        triangles.iter().for_each(|t| primitives.push(t));
+        spheres.iter().for_each(|s| primitives.push(s));
+        let bvh = Bvh::new(primitives.clone());
+
+        let mut n_occluded = 0;
+        for ray in random_rays(500, 8).iter() {
+            // Shadow rays end before the target, some of them are blocked
+            let t_max = 0.5;
+            let expected = primitives
+                .iter()
+                .any(|p| p.hit(ray, 1.0e-4, t_max).is_some());
+            assert_eq!(bvh.occluded(ray, 1.0e-4, t_max), expected);
+            assert_eq!(
+                bvh.occluded(ray, 0.0, Float::INFINITY),
+                bvh.hit(ray, 0.0, Float::INFINITY).is_some()
+            );
+            for p in &primitives {
+                assert_eq!(
+                    p.occluded(ray, 1.0e-4, t_max),
+                    p.hit(ray, 1.0e-4, t_max).is_some()
+                );
+            }
+            n_occluded += expected as usize;
+        }
+        assert!(n_occluded > 0 && n_occluded < 500);
+    }
+
+    #[test]
+    fn bvh_leaf_size() {
+        let triangles = random_triangles(1000, 3);
+        for &max_prims_in_node in &[1, 2, 8] {
+            let spec = BvhSpec {
+                max_prims_in_node,
+                ..Default::default()
+            };
+            let bvh = Bvh::from_spec(random_triangles(1000, 3), spec);
+            let mut seen = vec![false; triangles.len()];
+            for node in bvh.nodes.iter().filter(|n| n.is_leaf()) {
+                assert!(node.n_primitives as usize <= max_prims_in_node);
+                let first = node.offset as usize;
+                for &i in &bvh.indices[first..first + node.n_primitives as usize] {
+                    // Every primitive is inside of its leaf bounds
+                    let b = Bounds3::union(&node.bounds, &triangles[i as usize].world_bound());
+                    assert_eq!(b.min, node.bounds.min);
+                    assert_eq!(b.max, node.bounds.max);
+                    seen[i as usize] = true;
+                }
+            }
+            assert!(seen.iter().all(|&s| s));
+        }
+    }
+
+    #[test]
+    fn bvh_over_mixed_primitives() {
+        let spheres: Vec<_> = (0..10)
+            .map(|i| Sphere {
+                center: point3(i as Float * 3.0, 0.0, -5.0),
+                radius: 1.0,
+                material: &NullMaterial,
+            })
+            .collect();
+        let triangles = random_triangles(100, 4);
+
+        let mut primitives: Vec<&dyn Primitive> = Vec::new();
+        spheres.iter().for_each(|s| primitives.push(s));
+        triangles.iter().for_each(|t| primitives.push(t));
+
+        let bvh = Bvh::new(primitives.clone());
+        for ray in random_rays(200, 5).iter() {
+            let expected = brute_force(&primitives, ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+        }
+    }
+
+    #[test]
+    fn bvh_over_coincident_primitives() {
+        let triangles: Vec<_> = (0..100).map(|_| random_triangles(1, 6).remove(0)).collect();
+        let bvh = Bvh::from_spec(
+            triangles,
+            BvhSpec {
+                max_prims_in_node: 4,
+                ..Default::default()
+            },
+        );
+        assert!(bvh.nodes.iter().all(|n| n.n_primitives <= 4));
+    }
+
+    /// Moves every triangle of `bvh` by `offset` times its index.
+    fn scatter(bvh: &mut Bvh<Triangle>, offset: Vec3f) {
+        for (i, t) in bvh.primitives_mut().iter_mut().enumerate() {
+            for p in t.positions.iter_mut() {
+                *p = *p + offset * i as Float;
+            }
+        }
+    }
+
+    #[test]
+    fn refitted_bvh_finds_closest_hits() {
+        let mut bvh = Bvh::new(random_triangles(2000, 9));
+        scatter(&mut bvh, vec3(0.001, -0.002, 0.0005));
+        bvh.refit();
+
+        let rays = random_rays(500, 10);
+        for ray in rays.iter() {
+            let expected = brute_force(bvh.primitives(), ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+            assert_eq!(bvh.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+        }
+
+        // Root bounds fit the primitives exactly
+        let bounds = bvh.primitives().iter().fold(Bounds3f::empty(), |b, t| {
+            Bounds3::union(&b, &t.world_bound())
+        });
+        assert_eq!(bvh.world_bound().min, bounds.min);
+        assert_eq!(bvh.world_bound().max, bounds.max);
+    }
+
+    #[test]
+    fn refit_rebuilds_degraded_bvh() {
+        let mut bvh = Bvh::new(random_triangles(2000, 11));
+        assert_eq!(bvh.degradation(), 1.0);
+
+        // Small motion: refitting is good enough
+        scatter(&mut bvh, vec3(1.0e-6, 0.0, 0.0));
+        assert!(!bvh.refit_or_rebuild(1.5));
+        assert!(bvh.degradation() < 1.5);
+
+        // Primitives swapped all around the scene: the tree is useless
+        let n = bvh.primitives().len();
+        bvh.primitives_mut().reverse();
+        bvh.primitives_mut()[..n / 2].rotate_left(n / 4);
+        bvh.refit();
+        let degradation = bvh.degradation();
+        assert!(degradation > 1.5, "{}", degradation);
+
+        let cost = bvh.sah_cost();
+        assert!(bvh.refit_or_rebuild(1.5));
+        assert_eq!(bvh.degradation(), 1.0);
+        assert!(bvh.sah_cost() < cost);
+
+        for ray in random_rays(200, 12).iter() {
+            let expected = brute_force(bvh.primitives(), ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+        }
+    }
+
+    #[test]
+    fn empty_bvh() {
+        let bvh: Bvh<Triangle> = Bvh::new(Vec::new());
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(bvh.hit(&ray, 0.0, Float::INFINITY).is_none());
+        assert!(!bvh.occluded(&ray, 0.0, Float::INFINITY));
+
+        let mut bvh = bvh;
+        bvh.refit();
+        assert!(!bvh.refit_or_rebuild(1.5));
+        assert_eq!(bvh.node_count(), 0);
+    }
+}
@@ -0,0 +1,510 @@
+//! Binary format of a BVH over a triangle mesh:
+//!
+//! | Field            | Type                                         |
+//! |------------------|----------------------------------------------|
+//! | magic            | `b"PBRTBVH\0"`                               |
+//! | version          | `u32`                                        |
+//! | source checksum  | `u64`                                        |
+//! | spec             | `u32` max prims, `u8` method, `f32` argument |
+//! | counts           | `u64` triangles, indices, nodes              |
+//! | triangles        | 9 `f32` positions, `u8` flags, 6 `f32` uvs,  |
+//! |                  | 9 `f32` tangents                             |
+//! | indices          | `u32` each                                   |
+//! | nodes            | 6 `f32` bounds, `u32`, `u16`, `u8`, padding  |
+//! | built SAH cost   | `f32`                                        |
+//! | checksum         | `u64` of everything above                    |
+//!
+//! All numbers are little-endian.
+
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::material::Material;
+use crate::shape::triangle::Triangle;
+
+use super::bvh::{Bvh, BvhSpec, Node, SplitMethod, MAX_DEPTH};
+
+const MAGIC: &[u8; 8] = b"PBRTBVH\0";
+
+/// Bumped whenever the format changes, to invalidate old caches.
+const VERSION: u32 = 2;
+
+const BACKFACE_CULLING: u8 = 1;
+const HAS_UVS: u8 = 2;
+const HAS_TANGENTS: u8 = 4;
+
+/// 64-bit FNV-1a hash of bytes, to tell if a source mesh has changed.
+///
+/// ```
+/// use pbrt::accel::cache::checksum;
+///
+/// assert_eq!(checksum(b""), 0xcbf29ce484222325);
+/// assert_ne!(checksum(b"mesh"), checksum(b"mush"));
+/// ```
+pub fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Writes a BVH over triangles to a file. `source_checksum` identifies the
+/// data the triangles came from, see `checksum`.
+///
+/// Materials of triangles are not saved.
+pub fn save(path: &Path, bvh: &Bvh<Triangle>, source_checksum: u64) -> Result<()> {
+    let mut w = Vec::new();
+    w.extend_from_slice(MAGIC);
+    put_u32(&mut w, VERSION);
+    put_u64(&mut w, source_checksum);
+    put_spec(&mut w, &bvh.spec);
+
+    put_u64(&mut w, bvh.primitives.len() as u64);
+    put_u64(&mut w, bvh.indices.len() as u64);
+    put_u64(&mut w, bvh.nodes.len() as u64);
+
+    for t in &bvh.primitives {
+        for p in &t.positions {
+            put_point3(&mut w, *p);
+        }
+        let mut flags = 0;
+        if t.backface_culling {
+            flags |= BACKFACE_CULLING;
+        }
+        if t.uvs.is_some() {
+            flags |= HAS_UVS;
+        }
+        if t.tangents.is_some() {
+            flags |= HAS_TANGENTS;
+        }
+        w.push(flags);
+        for uv in &t.uvs.unwrap_or_default() {
+            put_f32(&mut w, uv.x);
+            put_f32(&mut w, uv.y);
+        }
+        for tangent in &t.tangents.unwrap_or_default() {
+            put_f32(&mut w, tangent.x);
+            put_f32(&mut w, tangent.y);
+            put_f32(&mut w, tangent.z);
+        }
+    }
+    for &i in &bvh.indices {
+        put_u32(&mut w, i);
+    }
+    for node in &bvh.nodes {
+        put_point3(&mut w, node.bounds.min);
+        put_point3(&mut w, node.bounds.max);
+        put_u32(&mut w, node.offset);
+        w.extend_from_slice(&node.n_primitives.to_le_bytes());
+        w.push(node.axis);
+        w.push(0);
+    }
+    put_f32(&mut w, bvh.built_cost);
+
+    let sum = checksum(&w);
+    put_u64(&mut w, sum);
+
+    // Write to a temporary file first, so that a render interrupted midway
+    // doesn't leave a broken cache behind. Renders writing the same cache at
+    // the same time each get their own.
+    let tmp = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&tmp, &w)?;
+    let renamed = std::fs::rename(&tmp, path);
+    if renamed.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    renamed
+}
+
+/// Reads a BVH over triangles from a file written by `save`, and assigns
+/// `material` to all of them.
+///
+/// Fails with `ErrorKind::InvalidData` if the file is corrupt, was written by
+/// another version, was built with another `spec`, or `source_checksum`
+/// doesn't match: the source has changed since the cache was written.
+pub fn load<'a>(
+    path: &Path,
+    source_checksum: u64,
+    spec: BvhSpec,
+    material: &'a dyn Material,
+) -> Result<Bvh<Triangle<'a>>> {
+    let bytes = std::fs::read(path)?;
+
+    if bytes.len() < MAGIC.len() + 8 || &bytes[..MAGIC.len()] != MAGIC {
+        return Err(invalid("not a BVH cache"));
+    }
+    let (body, sum) = bytes.split_at(bytes.len() - 8);
+    if checksum(body) != u64::from_le_bytes(sum.try_into().unwrap()) {
+        return Err(invalid("corrupt BVH cache"));
+    }
+
+    let mut r = Reader {
+        bytes: body,
+        position: MAGIC.len(),
+    };
+    if r.u32()? != VERSION {
+        return Err(invalid("BVH cache of another version"));
+    }
+    if r.u64()? != source_checksum {
+        return Err(invalid("stale BVH cache"));
+    }
+    let mut expected_spec = Vec::new();
+    put_spec(&mut expected_spec, &spec);
+    if r.bytes(expected_spec.len())? != &expected_spec[..] {
+        return Err(invalid("BVH cache built with another spec"));
+    }
+
+    let n_triangles = r.u64()? as usize;
+    let n_indices = r.u64()? as usize;
+    let n_nodes = r.u64()? as usize;
+    // Checks that the counts are sane before allocating anything
+    let size = n_triangles
+        .checked_mul(97)
+        .zip(n_indices.checked_mul(4))
+        .zip(n_nodes.checked_mul(32))
+        .and_then(|((t, i), n)| t.checked_add(i)?.checked_add(n)?.checked_add(4));
+    if size != Some(r.remaining()) {
+        return Err(invalid("truncated BVH cache"));
+    }
+
+    let mut primitives = Vec::with_capacity(n_triangles);
+    for _ in 0..n_triangles {
+        let positions = [r.point3()?, r.point3()?, r.point3()?];
+        let flags = r.u8()?;
+        let mut uvs = [Point2f::default(); 3];
+        for uv in uvs.iter_mut() {
+            *uv = Point2f::new(r.f32()?, r.f32()?);
+        }
+        let mut tangents = [Vec3f::default(); 3];
+        for tangent in tangents.iter_mut() {
+            *tangent = vec3(r.f32()?, r.f32()?, r.f32()?);
+        }
+        primitives.push(Triangle {
+            positions,
+            material,
+            backface_culling: flags & BACKFACE_CULLING != 0,
+            uvs: if flags & HAS_UVS != 0 {
+                Some(uvs)
+            } else {
+                None
+            },
+            tangents: if flags & HAS_TANGENTS != 0 {
+                Some(tangents)
+            } else {
+                None
+            },
+        });
+    }
+
+    let mut indices = Vec::with_capacity(n_indices);
+    for _ in 0..n_indices {
+        let i = r.u32()?;
+        if i as usize >= n_triangles {
+            return Err(invalid("BVH cache refers to a missing triangle"));
+        }
+        indices.push(i);
+    }
+
+    let mut nodes = Vec::with_capacity(n_nodes);
+    // Depth of each node, known before it's read since parents come first
+    let mut depths = vec![0; n_nodes];
+    for i in 0..n_nodes {
+        let bounds = Bounds3 {
+            min: r.point3()?,
+            max: r.point3()?,
+        };
+        let offset = r.u32()?;
+        let n_primitives = u16::from_le_bytes(r.bytes(2)?.try_into().unwrap());
+        let axis = r.u8()?;
+        r.u8()?;
+
+        // Children follow their parents, and leaves don't reach past the
+        // indices, so traversal stays in bounds
+        let valid = if n_primitives > 0 {
+            offset as usize + n_primitives as usize <= n_indices
+        } else {
+            offset as usize > i + 1 && (offset as usize) < n_nodes && axis < 3
+        };
+        if !valid {
+            return Err(invalid("BVH cache with a broken node"));
+        }
+        // Traversal stacks the second child of every interior node on the
+        // way down, and has room for `MAX_DEPTH` of them
+        if n_primitives == 0 {
+            let depth = depths[i] + 1;
+            if depth > MAX_DEPTH {
+                return Err(invalid("BVH cache deeper than traversal allows"));
+            }
+            for &child in &[i + 1, offset as usize] {
+                depths[child] = depths[child].max(depth);
+            }
+        }
+        nodes.push(Node {
+            bounds,
+            offset,
+            n_primitives,
+            axis,
+        });
+    }
+    let built_cost = r.f32()?;
+
+    Ok(Bvh {
+        primitives,
+        indices,
+        nodes,
+        spec,
+        built_cost,
+    })
+}
+
+/// Loads a BVH from a cache file, if there is a valid one for the source
+/// with given checksum. Otherwise, builds one over the triangles made by
+/// `triangles`, and writes it to the cache for the next time.
+///
+/// The cache is only a shortcut: when it can't be read, the BVH is built,
+/// and when it can't be written, the next render builds it again.
+///
+/// All triangles get `material`, whichever way the BVH was made, since
+/// materials aren't cached.
+pub fn load_or_build<'a>(
+    path: &Path,
+    source_checksum: u64,
+    spec: BvhSpec,
+    material: &'a dyn Material,
+    triangles: impl FnOnce() -> Vec<Triangle<'a>>,
+) -> Bvh<Triangle<'a>> {
+    if let Ok(bvh) = load(path, source_checksum, spec, material) {
+        return bvh;
+    }
+    let triangles = triangles()
+        .into_iter()
+        .map(|t| Triangle { material, ..t })
+        .collect();
+    let bvh = Bvh::from_spec(triangles, spec);
+    let _ = save(path, &bvh, source_checksum);
+    bvh
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn put_u32(w: &mut Vec<u8>, v: u32) {
+    w.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u64(w: &mut Vec<u8>, v: u64) {
+    w.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_f32(w: &mut Vec<u8>, v: Float) {
+    w.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_point3(w: &mut Vec<u8>, p: Point3f) {
+    put_f32(w, p.x);
+    put_f32(w, p.y);
+    put_f32(w, p.z);
+}
+
+fn put_spec(w: &mut Vec<u8>, spec: &BvhSpec) {
+    put_u32(w, spec.max_prims_in_node as u32);
+    let (method, argument) = match spec.split_method {
+        SplitMethod::Sah => (0, 0.0),
+        SplitMethod::Lbvh { optimize_treelets } => (1, optimize_treelets as u8 as Float),
+        SplitMethod::Sbvh { max_duplication } => (2, max_duplication),
+    };
+    w.push(method);
+    put_f32(w, argument);
+}
+
+/// Reads little-endian numbers from bytes, one after another.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.position
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        if n > self.remaining() {
+            return Err(invalid("truncated BVH cache"));
+        }
+        let bytes = &self.bytes[self.position..self.position + n];
+        self.position += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<Float> {
+        Ok(Float::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn point3(&mut self) -> Result<Point3f> {
+        Ok(point3(self.f32()?, self.f32()?, self.f32()?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::hit::Hit;
+    use crate::material::null::NullMaterial;
+    use crate::primitive::Primitive;
+
+    /// Path to a cache file unique to a test.
+    fn cache_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("pbrt-{}-{}.bvh", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn cached_bvh_is_the_same() {
+        let path = cache_path("same");
+        let spec = BvhSpec::default();
+        let mut triangles = random_triangles(1000, 61);
+        for t in triangles.iter_mut().step_by(2) {
+            let [p0, p1, p2] = t.positions;
+            t.tangents = Some([p1 - p0, p2 - p1, p0 - p2]);
+        }
+        let bvh = Bvh::from_spec(triangles, spec);
+        save(&path, &bvh, 42).unwrap();
+
+        let loaded = load(&path, 42, spec, &NullMaterial).unwrap();
+        assert_eq!(loaded.node_count(), bvh.node_count());
+        assert_eq!(loaded.indices, bvh.indices);
+        assert_eq!(loaded.sah_cost(), bvh.sah_cost());
+        assert_eq!(loaded.degradation(), 1.0);
+        for (a, b) in loaded.primitives.iter().zip(&bvh.primitives) {
+            assert_eq!(a.tangents, b.tangents);
+        }
+        for ray in random_rays(200, 62).iter() {
+            assert_eq!(
+                loaded.hit(ray, 0.0, Float::INFINITY).map(|h| h.t),
+                bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t)
+            );
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stale_cache_is_rebuilt() {
+        let path = cache_path("stale");
+        let spec = BvhSpec::default();
+        let mut builds = 0;
+        let mut build = |seed| {
+            builds += 1;
+            random_triangles(100, seed)
+        };
+
+        let source = checksum(b"first");
+        load_or_build(&path, source, spec, &NullMaterial, || build(63));
+        load_or_build(&path, source, spec, &NullMaterial, || build(63));
+
+        // Another source, or another spec, can't use the cache
+        let source = checksum(b"second");
+        let bvh = load_or_build(&path, source, spec, &NullMaterial, || build(64));
+        let other_spec = BvhSpec {
+            max_prims_in_node: 1,
+            ..spec
+        };
+        assert!(load(&path, source, other_spec, &NullMaterial).is_err());
+        assert_eq!(builds, 2);
+
+        for ray in random_rays(100, 65).iter() {
+            let expected = brute_force(&random_triangles(100, 64), ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn corrupt_cache_is_rejected() {
+        let path = cache_path("corrupt");
+        let spec = BvhSpec::default();
+        let bvh = Bvh::from_spec(random_triangles(100, 66), spec);
+        save(&path, &bvh, 0).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        std::fs::write(&path, &bytes).unwrap();
+        let e = load(&path, 0, spec, &NullMaterial).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        std::fs::write(&path, &bytes[..middle]).unwrap();
+        let e = load(&path, 0, spec, &NullMaterial).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        std::fs::remove_file(&path).unwrap();
+        let e = load(&path, 0, spec, &NullMaterial).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn unwritable_cache_is_skipped() {
+        let dir = std::env::temp_dir().join(format!("pbrt-missing-{}", std::process::id()));
+        let path = dir.join("mesh.bvh");
+        let spec = BvhSpec::default();
+
+        let bvh = load_or_build(&path, 0, spec, &NullMaterial, || random_triangles(100, 69));
+        assert!(!dir.exists());
+        for ray in random_rays(100, 70).iter() {
+            let expected = brute_force(&random_triangles(100, 69), ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+        }
+    }
+
+    #[test]
+    fn too_deep_cache_is_rebuilt() {
+        let path = cache_path("deep");
+        let spec = BvhSpec::default();
+        let bounds = random_triangles(10, 67)[0].world_bound();
+
+        // A chain of interior nodes, each with the last leaf as its second
+        // child: valid nodes, but too many of them on the way down
+        let depth = MAX_DEPTH + 1;
+        let mut nodes: Vec<Node> = (0..depth)
+            .map(|_| Node::interior(bounds, depth + 1, 0))
+            .collect();
+        nodes.push(Node::leaf(bounds, 0, 1));
+        nodes.push(Node::leaf(bounds, 0, 1));
+        let chain = Bvh {
+            primitives: random_triangles(10, 67),
+            indices: vec![0],
+            nodes,
+            spec,
+            built_cost: 0.0,
+        };
+        save(&path, &chain, 0).unwrap();
+        let e = load(&path, 0, spec, &NullMaterial).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+
+        let bvh = load_or_build(&path, 0, spec, &NullMaterial, || random_triangles(10, 67));
+        for ray in random_rays(100, 68).iter() {
+            let expected = brute_force(&random_triangles(10, 67), ray);
+            assert_eq!(bvh.hit(ray, 0.0, Float::INFINITY).map(|h| h.t), expected);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,435 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::*;
+use crate::primitive::Primitive;
+
+use super::{Aggregate, TraversalStats};
+
+/// Parameters of grid construction.
+#[derive(Copy, Clone, Debug)]
+pub struct GridSpec {
+    /// Voxels along the longest side of the grid, per cube root of the number
+    /// of primitives.
+    pub density: Float,
+    /// Most voxels along any of the axes.
+    pub max_resolution: usize,
+}
+
+impl Default for GridSpec {
+    fn default() -> GridSpec {
+        GridSpec {
+            density: 3.0,
+            max_resolution: 128,
+        }
+    }
+}
+
+/// Uniform grid of voxels over a set of primitives.
+///
+/// Each voxel lists the primitives overlapping it. Rays step through the
+/// voxels they pass in order, with a 3D digital differential analyzer, so
+/// they can stop at the first voxel with a hit. Cheap to build, but it does
+/// poorly with scenes where primitives are unevenly spread.
+pub struct Grid<P> {
+    primitives: Vec<P>,
+    bounds: Bounds3f,
+    /// Number of voxels along each of the axes
+    resolution: [usize; 3],
+    voxel_size: Vec3f,
+    /// Primitives of voxel `v` are `indices[cells[v]..cells[v + 1]]`.
+    cells: Vec<u32>,
+    /// Indices into `primitives`, grouped by voxel.
+    indices: Vec<u32>,
+}
+
+impl<P: Primitive> Grid<P> {
+    /// Builds a grid over given primitives, with default parameters.
+    pub fn new(primitives: Vec<P>) -> Grid<P> {
+        Grid::from_spec(primitives, GridSpec::default())
+    }
+
+    /// Builds a grid over given primitives.
+    pub fn from_spec(primitives: Vec<P>, spec: GridSpec) -> Grid<P> {
+        use rayon::prelude::*;
+
+        let bounds: Vec<_> = primitives.par_iter().map(|p| p.world_bound()).collect();
+        let world_bound = bounds
+            .iter()
+            .fold(Bounds3f::empty(), |a, b| Bounds3::union(&a, b));
+
+        let mut grid = Grid {
+            primitives,
+            bounds: world_bound,
+            resolution: [1; 3],
+            voxel_size: vec3(0.0, 0.0, 0.0),
+            cells: vec![0; 2],
+            indices: Vec::new(),
+        };
+        if grid.primitives.is_empty() {
+            return grid;
+        }
+
+        let diagonal = world_bound.diagonal();
+        let max_extent = diagonal.max_component();
+        let voxels_per_unit = if max_extent > 0.0 {
+            spec.density * (grid.primitives.len() as Float).cbrt() / max_extent
+        } else {
+            0.0
+        };
+        for axis in 0..3 {
+            let voxels = (diagonal[axis] * voxels_per_unit).round() as usize;
+            grid.resolution[axis] = voxels.clamp(1, spec.max_resolution.max(1));
+        }
+        let [nx, ny, nz] = grid.resolution;
+        grid.voxel_size = vec3(
+            diagonal.x / nx as Float,
+            diagonal.y / ny as Float,
+            diagonal.z / nz as Float,
+        );
+
+        // Pairs of voxel and primitive overlapping it. Primitives spanning
+        // several voxels are clipped to each, to skip those they only
+        // overlap with their bounds.
+        let pairs: Vec<(u32, u32)> = bounds
+            .par_iter()
+            .enumerate()
+            .flat_map(|(i, b)| {
+                let grid = &grid;
+                let (lo, hi) = (grid.voxel_of(b.min), grid.voxel_of(b.max));
+                let single = lo == hi;
+                (lo[2]..=hi[2])
+                    .flat_map(move |z| {
+                        (lo[1]..=hi[1]).flat_map(move |y| {
+                            (lo[0]..=hi[0]).filter_map(move |x| {
+                                let voxel = [x, y, z];
+                                let overlaps = single
+                                    || !grid.primitives[i]
+                                        .clipped_bound(&grid.voxel_bounds(voxel))
+                                        .is_empty();
+                                if overlaps {
+                                    Some((grid.voxel_index(voxel) as u32, i as u32))
+                                } else {
+                                    None
+                                }
+                            })
+                        })
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        // Counting sort of the pairs by voxel
+        let n_voxels = nx * ny * nz;
+        let mut cells = vec![0u32; n_voxels + 1];
+        for &(voxel, _) in &pairs {
+            cells[voxel as usize + 1] += 1;
+        }
+        for v in 0..n_voxels {
+            cells[v + 1] += cells[v];
+        }
+        let mut next = cells.clone();
+        let mut indices = vec![0; pairs.len()];
+        for &(voxel, primitive) in &pairs {
+            indices[next[voxel as usize] as usize] = primitive;
+            next[voxel as usize] += 1;
+        }
+
+        grid.cells = cells;
+        grid.indices = indices;
+        grid
+    }
+}
+
+impl<P> Grid<P> {
+    /// Primitives the grid was built over, in their original order.
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Number of voxels along each of the axes.
+    pub fn resolution(&self) -> [usize; 3] {
+        self.resolution
+    }
+
+    /// Coordinate of the voxel containing `p` along an axis, clamped to the
+    /// grid.
+    fn voxel_coordinate(&self, p: Point3f, axis: usize) -> usize {
+        if self.voxel_size[axis] > 0.0 {
+            let v = (p[axis] - self.bounds.min[axis]) / self.voxel_size[axis];
+            (v.max(0.0) as usize).min(self.resolution[axis] - 1)
+        } else {
+            0
+        }
+    }
+
+    fn voxel_of(&self, p: Point3f) -> [usize; 3] {
+        [
+            self.voxel_coordinate(p, 0),
+            self.voxel_coordinate(p, 1),
+            self.voxel_coordinate(p, 2),
+        ]
+    }
+
+    fn voxel_index(&self, [x, y, z]: [usize; 3]) -> usize {
+        x + self.resolution[0] * (y + self.resolution[1] * z)
+    }
+
+    /// Position of the lower boundary of voxels with given coordinate along
+    /// an axis.
+    fn voxel_to_position(&self, v: usize, axis: usize) -> Float {
+        self.bounds.min[axis] + v as Float * self.voxel_size[axis]
+    }
+
+    /// Bounds of a voxel, slightly padded so that clipping errors don't lose
+    /// primitives on its boundaries.
+    fn voxel_bounds(&self, voxel: [usize; 3]) -> Bounds3f {
+        let corner = |offset: usize| {
+            point3(
+                self.voxel_to_position(voxel[0] + offset, 0),
+                self.voxel_to_position(voxel[1] + offset, 1),
+                self.voxel_to_position(voxel[2] + offset, 2),
+            )
+        };
+        let padding = self.voxel_size * 1e-4;
+        Bounds3 {
+            min: corner(0) + -padding,
+            max: corner(1) + padding,
+        }
+    }
+
+    fn voxel_primitives(&self, voxel: [usize; 3]) -> &[u32] {
+        let v = self.voxel_index(voxel);
+        &self.indices[self.cells[v] as usize..self.cells[v + 1] as usize]
+    }
+}
+
+/// State of a ray stepping through the voxels of a grid.
+struct Dda {
+    voxel: [usize; 3],
+    /// Distance along the ray to the next voxel boundary on each axis
+    next_crossing: [Float; 3],
+    /// Distance along the ray between voxel boundaries on each axis
+    delta: [Float; 3],
+    step_up: [bool; 3],
+    /// Last voxel coordinate along each axis
+    end: [usize; 3],
+}
+
+impl Dda {
+    /// Starts a walk through the grid at distance `t` along the ray.
+    fn new<P>(grid: &Grid<P>, ray: &Ray, t: Float) -> Dda {
+        let d = ray.direction();
+        let p = ray.eval(t);
+        let voxel = grid.voxel_of(p);
+        let mut dda = Dda {
+            voxel,
+            next_crossing: [Float::INFINITY; 3],
+            delta: [Float::INFINITY; 3],
+            step_up: [true; 3],
+            end: [0; 3],
+        };
+        for axis in 0..3 {
+            if d[axis] > 0.0 {
+                let boundary = grid.voxel_to_position(voxel[axis] + 1, axis);
+                dda.next_crossing[axis] = t + (boundary - p[axis]) / d[axis];
+                dda.delta[axis] = grid.voxel_size[axis] / d[axis];
+                dda.end[axis] = grid.resolution[axis] - 1;
+            } else if d[axis] < 0.0 {
+                let boundary = grid.voxel_to_position(voxel[axis], axis);
+                dda.next_crossing[axis] = t + (boundary - p[axis]) / d[axis];
+                dda.delta[axis] = -grid.voxel_size[axis] / d[axis];
+                dda.step_up[axis] = false;
+            }
+        }
+        dda
+    }
+
+    /// Distance along the ray to where it leaves the current voxel, and the
+    /// axis it crosses there.
+    fn exit(&self) -> (Float, usize) {
+        let c = &self.next_crossing;
+        let axis = if c[0] < c[1] {
+            if c[0] < c[2] {
+                0
+            } else {
+                2
+            }
+        } else if c[1] < c[2] {
+            1
+        } else {
+            2
+        };
+        (c[axis], axis)
+    }
+
+    /// Moves on to the next voxel across `axis`. Returns false if the ray
+    /// leaves the grid.
+    fn step(&mut self, axis: usize) -> bool {
+        if self.voxel[axis] == self.end[axis] {
+            return false;
+        }
+        if self.step_up[axis] {
+            self.voxel[axis] += 1;
+        } else {
+            self.voxel[axis] -= 1;
+        }
+        self.next_crossing[axis] += self.delta[axis];
+        true
+    }
+}
+
+impl<P: Primitive> Hit for Grid<P> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.hit_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.occluded_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+}
+
+impl<P: Primitive> Aggregate for Grid<P> {
+    fn hit_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>> {
+        stats.rays += 1;
+        if self.primitives.is_empty() {
+            return None;
+        }
+        let (t0, t1) = self.bounds.hit_interval(ray, (t_min, t_max))?;
+        let mut dda = Dda::new(self, ray, t0);
+
+        let mut closest = t_max;
+        let mut result = None;
+        loop {
+            stats.nodes += 1;
+            let primitives = self.voxel_primitives(dda.voxel);
+            stats.primitives += primitives.len() as u64;
+            for &i in primitives {
+                if let Some(hit) = self.primitives[i as usize].hit(ray, t_min, closest) {
+                    closest = hit.t;
+                    result = Some(hit);
+                }
+            }
+
+            // Hits found so far may lie in voxels farther along, but nothing
+            // in those can be closer than the hits in this one
+            let (exit, axis) = dda.exit();
+            if closest <= exit || exit > t1 || !dda.step(axis) {
+                break;
+            }
+        }
+
+        result
+    }
+
+    fn occluded_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> bool {
+        stats.rays += 1;
+        if self.primitives.is_empty() {
+            return false;
+        }
+        let (t0, t1) = match self.bounds.hit_interval(ray, (t_min, t_max)) {
+            Some(interval) => interval,
+            None => return false,
+        };
+        let mut dda = Dda::new(self, ray, t0);
+
+        loop {
+            stats.nodes += 1;
+            for &i in self.voxel_primitives(dda.voxel) {
+                stats.primitives += 1;
+                if self.primitives[i as usize].occluded(ray, t_min, t_max) {
+                    return true;
+                }
+            }
+
+            let (exit, axis) = dda.exit();
+            if exit > t1 || !dda.step(axis) {
+                return false;
+            }
+        }
+    }
+}
+
+impl<P: Primitive> Primitive for Grid<P> {
+    fn world_bound(&self) -> Bounds3f {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::material::null::NullMaterial;
+    use crate::shape::sphere::Sphere;
+    use crate::shape::triangle::Triangle;
+
+    #[test]
+    fn grid_finds_closest_hits() {
+        let triangles = random_triangles(2000, 51);
+        let spheres: Vec<_> = (0..20)
+            .map(|i| Sphere {
+                center: point3(i as Float - 10.0, 2.0, 0.0),
+                radius: 0.5,
+                material: &NullMaterial,
+            })
+            .collect();
+        let mut primitives: Vec<&dyn Primitive> = Vec::new();
+        triangles.iter().for_each(|t| primitives.push(t));
+        spheres.iter().for_each(|s| primitives.push(s));
+
+        let grid = Grid::new(primitives.clone());
+        let mut stats = TraversalStats::default();
+        for ray in random_rays(500, 52).iter() {
+            let expected = brute_force(&primitives, ray);
+            let t = grid
+                .hit_with_stats(ray, 0.0, Float::INFINITY, &mut stats)
+                .map(|hit| hit.t);
+            assert_eq!(t, expected);
+            assert_eq!(grid.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+        }
+
+        assert_eq!(stats.rays, 500);
+        assert!(stats.primitives_per_ray() < 0.1 * primitives.len() as f64);
+    }
+
+    #[test]
+    fn flat_grid() {
+        // All triangles in the plane z = 0
+        let triangles: Vec<_> = random_triangles(200, 53)
+            .into_iter()
+            .map(|t| Triangle {
+                positions: t.positions.map(|p| point3(p.x, p.y, 0.0)),
+                ..t
+            })
+            .collect();
+        let grid = Grid::new(triangles.iter().collect());
+        assert_eq!(grid.resolution()[2], 1);
+
+        for ray in random_rays(200, 54).iter() {
+            let t = grid.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, brute_force(&triangles, ray));
+        }
+    }
+
+    #[test]
+    fn empty_grid() {
+        let grid: Grid<Triangle> = Grid::new(Vec::new());
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(grid.hit(&ray, 0.0, Float::INFINITY).is_none());
+        assert!(!grid.occluded(&ray, 0.0, Float::INFINITY));
+    }
+}
@@ -0,0 +1,537 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::*;
+use crate::primitive::Primitive;
+
+use super::{Aggregate, TraversalStats};
+
+/// Parameters of kd-tree construction. Costs are relative to each other.
+#[derive(Copy, Clone, Debug)]
+pub struct KdTreeSpec {
+    /// Cost of a ray-primitive intersection test.
+    pub intersect_cost: Float,
+    /// Cost of a traversal step.
+    pub traversal_cost: Float,
+    /// Discount on the cost of splits which leave one side empty, from 0 to
+    /// 1. Empty space is cheap to skip.
+    pub empty_bonus: Float,
+    /// Nodes with this many primitives or fewer become leaves.
+    pub max_prims: usize,
+    /// Deepest the tree can get. By default, `8 + 1.3 log2(n)` for `n`
+    /// primitives.
+    pub max_depth: Option<usize>,
+}
+
+impl Default for KdTreeSpec {
+    fn default() -> KdTreeSpec {
+        KdTreeSpec {
+            intersect_cost: 80.0,
+            traversal_cost: 1.0,
+            empty_bonus: 0.5,
+            max_prims: 1,
+            max_depth: None,
+        }
+    }
+}
+
+/// kd-tree over a set of primitives, built with the surface area heuristic,
+/// after PBR book, section 4.4.
+///
+/// Space is split in two by axis-aligned planes, recursively. Unlike with a
+/// BVH, children don't overlap, but primitives crossing split planes are
+/// referred to from both sides.
+pub struct KdTree<P> {
+    primitives: Vec<P>,
+    /// Indices into `primitives`, in the order leaf nodes refer to them.
+    indices: Vec<u32>,
+    nodes: Vec<KdNode>,
+    bounds: Bounds3f,
+}
+
+/// Nodes are stored flattened in depth-first order: the child below the split
+/// plane immediately follows its parent.
+#[derive(Copy, Clone, Debug)]
+enum KdNode {
+    Interior {
+        split: Float,
+        axis: u8,
+        above_child: u32,
+    },
+    Leaf {
+        first: u32,
+        count: u32,
+    },
+}
+
+/// Deepest a tree can get. Traversal keeps a stack of this size.
+const MAX_DEPTH: usize = 64;
+
+impl<P: Primitive> KdTree<P> {
+    /// Builds a kd-tree over given primitives, with default parameters.
+    pub fn new(primitives: Vec<P>) -> KdTree<P> {
+        KdTree::from_spec(primitives, KdTreeSpec::default())
+    }
+
+    /// Builds a kd-tree over given primitives.
+    pub fn from_spec(primitives: Vec<P>, spec: KdTreeSpec) -> KdTree<P> {
+        use rayon::prelude::*;
+
+        let bounds: Vec<_> = primitives.par_iter().map(|p| p.world_bound()).collect();
+        let world_bound = bounds
+            .iter()
+            .fold(Bounds3f::empty(), |a, b| Bounds3::union(&a, b));
+
+        let n = primitives.len();
+        let max_depth = spec
+            .max_depth
+            .unwrap_or_else(|| (8.0 + 1.3 * (n.max(1) as Float).log2()).round() as usize)
+            .min(MAX_DEPTH - 1);
+
+        let mut builder = KdTreeBuilder {
+            spec,
+            bounds: &bounds,
+            edges: [Vec::new(), Vec::new(), Vec::new()],
+            nodes: Vec::new(),
+            indices: Vec::new(),
+        };
+        if n > 0 {
+            builder.build(&world_bound, (0..n as u32).collect(), max_depth, 0);
+        }
+        let KdTreeBuilder { nodes, indices, .. } = builder;
+
+        KdTree {
+            primitives,
+            indices,
+            nodes,
+            bounds: world_bound,
+        }
+    }
+}
+
+impl<P> KdTree<P> {
+    /// Primitives the tree was built over, in their original order.
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+/// Start or end of primitive bounds along an axis.
+#[derive(Copy, Clone, Debug)]
+struct BoundEdge {
+    t: Float,
+    primitive: u32,
+    start: bool,
+}
+
+struct KdTreeBuilder<'a> {
+    spec: KdTreeSpec,
+    /// Bounds of all primitives
+    bounds: &'a [Bounds3f],
+    /// Scratch space for sorting bounds along each of the axes
+    edges: [Vec<BoundEdge>; 3],
+    nodes: Vec<KdNode>,
+    indices: Vec<u32>,
+}
+
+impl KdTreeBuilder<'_> {
+    /// Builds a subtree over primitives with given indices. Splits which
+    /// don't pay off are tolerated, but only a few in a row.
+    fn build(
+        &mut self,
+        node_bounds: &Bounds3f,
+        primitives: Vec<u32>,
+        depth_left: usize,
+        bad_refines: usize,
+    ) {
+        let n = primitives.len();
+        if n <= self.spec.max_prims || depth_left == 0 {
+            return self.leaf(&primitives);
+        }
+
+        let KdTreeSpec {
+            intersect_cost,
+            traversal_cost,
+            empty_bonus,
+            ..
+        } = self.spec;
+        let d = node_bounds.diagonal();
+        let inv_area = node_bounds.area().recip();
+        let leaf_cost = intersect_cost * n as Float;
+
+        // Try the longest axis first, then the others, if it can't be split
+        let mut best: Option<(usize, usize)> = None;
+        let mut best_cost = Float::INFINITY;
+        let mut axis = node_bounds.maximum_extent();
+        for _ in 0..3 {
+            let edges = &mut self.edges[axis];
+            edges.clear();
+            for &i in &primitives {
+                let b = &self.bounds[i as usize];
+                edges.push(BoundEdge {
+                    t: b.min[axis],
+                    primitive: i,
+                    start: true,
+                });
+                edges.push(BoundEdge {
+                    t: b.max[axis],
+                    primitive: i,
+                    start: false,
+                });
+            }
+            // At the same position, starts go before ends
+            edges.sort_by(|a, b| {
+                a.t.partial_cmp(&b.t)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(b.start.cmp(&a.start))
+            });
+
+            let (other0, other1) = ((axis + 1) % 3, (axis + 2) % 3);
+            let (mut below, mut above) = (0, n);
+            for (i, edge) in edges.iter().enumerate() {
+                if !edge.start {
+                    above -= 1;
+                }
+                if edge.t > node_bounds.min[axis] && edge.t < node_bounds.max[axis] {
+                    let cap = d[other0] * d[other1];
+                    let side = d[other0] + d[other1];
+                    let below_area = 2.0 * (cap + (edge.t - node_bounds.min[axis]) * side);
+                    let above_area = 2.0 * (cap + (node_bounds.max[axis] - edge.t) * side);
+                    let bonus = if below == 0 || above == 0 {
+                        empty_bonus
+                    } else {
+                        0.0
+                    };
+                    let cost = traversal_cost
+                        + intersect_cost
+                            * (1.0 - bonus)
+                            * (below_area * below as Float + above_area * above as Float)
+                            * inv_area;
+                    if cost < best_cost {
+                        best_cost = cost;
+                        best = Some((axis, i));
+                    }
+                }
+                if edge.start {
+                    below += 1;
+                }
+            }
+
+            if best.is_some() {
+                break;
+            }
+            axis = (axis + 1) % 3;
+        }
+
+        let bad_refines = if best_cost > leaf_cost {
+            bad_refines + 1
+        } else {
+            bad_refines
+        };
+        let (axis, offset) = match best {
+            Some(best) if !(best_cost > 4.0 * leaf_cost && n < 16) && bad_refines < 3 => best,
+            _ => return self.leaf(&primitives),
+        };
+
+        let edges = &self.edges[axis];
+        let split = edges[offset].t;
+        let below: Vec<_> = edges[..offset]
+            .iter()
+            .filter(|e| e.start)
+            .map(|e| e.primitive)
+            .collect();
+        let above: Vec<_> = edges[offset + 1..]
+            .iter()
+            .filter(|e| !e.start)
+            .map(|e| e.primitive)
+            .collect();
+
+        let node = self.nodes.len();
+        self.nodes.push(KdNode::Interior {
+            split,
+            axis: axis as u8,
+            above_child: 0,
+        });
+
+        let below_bounds = Bounds3 {
+            max: with_coordinate(node_bounds.max, axis, split),
+            ..*node_bounds
+        };
+        self.build(&below_bounds, below, depth_left - 1, bad_refines);
+
+        let above_child = self.nodes.len() as u32;
+        let above_bounds = Bounds3 {
+            min: with_coordinate(node_bounds.min, axis, split),
+            ..*node_bounds
+        };
+        self.build(&above_bounds, above, depth_left - 1, bad_refines);
+
+        self.nodes[node] = KdNode::Interior {
+            split,
+            axis: axis as u8,
+            above_child,
+        };
+    }
+
+    fn leaf(&mut self, primitives: &[u32]) {
+        self.nodes.push(KdNode::Leaf {
+            first: self.indices.len() as u32,
+            count: primitives.len() as u32,
+        });
+        self.indices.extend_from_slice(primitives);
+    }
+}
+
+fn with_coordinate(p: Point3f, axis: usize, value: Float) -> Point3f {
+    let mut c = [p.x, p.y, p.z];
+    c[axis] = value;
+    point3(c[0], c[1], c[2])
+}
+
+impl<P: Primitive> Hit for KdTree<P> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.hit_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.occluded_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+}
+
+impl<P: Primitive> Aggregate for KdTree<P> {
+    fn hit_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>> {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            return None;
+        }
+        // Part of the ray inside of the current node
+        let (mut t0, mut t1) = self.bounds.hit_interval(ray, (t_min, t_max))?;
+
+        let (o, d) = ray.origin_and_direction();
+        let inv_d = vec3(d.x.recip(), d.y.recip(), d.z.recip());
+
+        let mut closest = t_max;
+        let mut result = None;
+
+        // Nodes yet to be visited, with their parts of the ray
+        let mut stack = [(0usize, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            // Everything left to visit is farther than the closest hit
+            if closest < t0 {
+                break;
+            }
+            stats.nodes += 1;
+            match self.nodes[current] {
+                KdNode::Interior {
+                    split,
+                    axis,
+                    above_child,
+                } => {
+                    let axis = axis as usize;
+                    let t_plane = (split - o[axis]) * inv_d[axis];
+                    let below_first = o[axis] < split || (o[axis] == split && d[axis] <= 0.0);
+                    let (near, far) = if below_first {
+                        (current + 1, above_child as usize)
+                    } else {
+                        (above_child as usize, current + 1)
+                    };
+
+                    if t_plane > t1 || t_plane <= 0.0 {
+                        current = near;
+                    } else if t_plane < t0 {
+                        current = far;
+                    } else {
+                        stack[stack_len] = (far, t_plane, t1);
+                        stack_len += 1;
+                        current = near;
+                        t1 = t_plane;
+                    }
+                    continue;
+                }
+                KdNode::Leaf { first, count } => {
+                    let first = first as usize;
+                    stats.primitives += count as u64;
+                    for &i in &self.indices[first..first + count as usize] {
+                        if let Some(hit) = self.primitives[i as usize].hit(ray, t_min, closest) {
+                            closest = hit.t;
+                            result = Some(hit);
+                        }
+                    }
+                }
+            }
+
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            let (node, node_t0, node_t1) = stack[stack_len];
+            current = node;
+            t0 = node_t0;
+            t1 = node_t1;
+        }
+
+        result
+    }
+
+    fn occluded_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> bool {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            return false;
+        }
+        let (mut t0, mut t1) = match self.bounds.hit_interval(ray, (t_min, t_max)) {
+            Some(interval) => interval,
+            None => return false,
+        };
+
+        let (o, d) = ray.origin_and_direction();
+        let inv_d = vec3(d.x.recip(), d.y.recip(), d.z.recip());
+
+        let mut stack = [(0usize, 0.0, 0.0); MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut current = 0;
+
+        loop {
+            stats.nodes += 1;
+            match self.nodes[current] {
+                KdNode::Interior {
+                    split,
+                    axis,
+                    above_child,
+                } => {
+                    let axis = axis as usize;
+                    let t_plane = (split - o[axis]) * inv_d[axis];
+                    let below_first = o[axis] < split || (o[axis] == split && d[axis] <= 0.0);
+                    let (near, far) = if below_first {
+                        (current + 1, above_child as usize)
+                    } else {
+                        (above_child as usize, current + 1)
+                    };
+
+                    if t_plane > t1 || t_plane <= 0.0 {
+                        current = near;
+                    } else if t_plane < t0 {
+                        current = far;
+                    } else {
+                        stack[stack_len] = (far, t_plane, t1);
+                        stack_len += 1;
+                        current = near;
+                        t1 = t_plane;
+                    }
+                    continue;
+                }
+                KdNode::Leaf { first, count } => {
+                    let first = first as usize;
+                    for &i in &self.indices[first..first + count as usize] {
+                        stats.primitives += 1;
+                        if self.primitives[i as usize].occluded(ray, t_min, t_max) {
+                            return true;
+                        }
+                    }
+                }
+            }
+
+            if stack_len == 0 {
+                return false;
+            }
+            stack_len -= 1;
+            let (node, node_t0, node_t1) = stack[stack_len];
+            current = node;
+            t0 = node_t0;
+            t1 = node_t1;
+        }
+    }
+}
+
+impl<P: Primitive> Primitive for KdTree<P> {
+    fn world_bound(&self) -> Bounds3f {
+        self.bounds
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::material::null::NullMaterial;
+    use crate::shape::sphere::Sphere;
+    use crate::shape::triangle::Triangle;
+
+    #[test]
+    fn kdtree_finds_closest_hits() {
+        let triangles = random_triangles(2000, 41);
+        let spheres: Vec<_> = (0..20)
+            .map(|i| Sphere {
+                center: point3(i as Float - 10.0, 2.0, 0.0),
+                radius: 0.5,
+                material: &NullMaterial,
+            })
+            .collect();
+        let mut primitives: Vec<&dyn Primitive> = Vec::new();
+        triangles.iter().for_each(|t| primitives.push(t));
+        spheres.iter().for_each(|s| primitives.push(s));
+
+        let tree = KdTree::new(primitives.clone());
+        let mut stats = TraversalStats::default();
+        for ray in random_rays(500, 42).iter() {
+            let expected = brute_force(&primitives, ray);
+            let t = tree
+                .hit_with_stats(ray, 0.0, Float::INFINITY, &mut stats)
+                .map(|hit| hit.t);
+            assert_eq!(t, expected);
+            assert_eq!(tree.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+        }
+
+        assert_eq!(stats.rays, 500);
+        // Much better than testing every primitive
+        assert!(stats.primitives_per_ray() < 0.1 * primitives.len() as f64);
+    }
+
+    #[test]
+    fn kdtree_max_depth() {
+        let spec = KdTreeSpec {
+            max_depth: Some(0),
+            ..Default::default()
+        };
+        let tree = KdTree::from_spec(random_triangles(100, 43), spec);
+        assert_eq!(tree.node_count(), 1);
+
+        let spec = KdTreeSpec {
+            max_depth: Some(1000),
+            ..Default::default()
+        };
+        let tree = KdTree::from_spec(random_triangles(100, 43), spec);
+        for ray in random_rays(100, 44).iter() {
+            let t = tree.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, brute_force(&random_triangles(100, 43), ray));
+        }
+    }
+
+    #[test]
+    fn empty_kdtree() {
+        let tree: KdTree<Triangle> = KdTree::new(Vec::new());
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(tree.hit(&ray, 0.0, Float::INFINITY).is_none());
+        assert!(!tree.occluded(&ray, 0.0, Float::INFINITY));
+    }
+}
@@ -0,0 +1,413 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+
+use super::bvh::{BuildPrimitive, Node, MAX_DEPTH};
+
+use rayon::prelude::*;
+
+/// Subtrees with fewer primitives than this are built on the current thread.
+const PARALLEL_THRESHOLD: usize = 1024;
+
+/// Number of bits per dimension in Morton codes.
+const MORTON_BITS: u32 = 10;
+
+/// Most leaves a treelet can have. The optimal treelet topology is searched
+/// among all the `3^n` ways to partition the set of its leaves.
+const TREELET_LEAVES: usize = 7;
+
+#[derive(Copy, Clone)]
+struct MortonPrimitive {
+    code: u32,
+    primitive: BuildPrimitive,
+}
+
+/// Builds a linear BVH: sorts primitives along the Morton curve, and splits
+/// them by the bits of their codes, starting from the most significant one.
+pub(crate) fn build(
+    refs: Vec<BuildPrimitive>,
+    max_prims_in_node: usize,
+    optimize_treelets: bool,
+) -> (Vec<Node>, Vec<u32>) {
+    if refs.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    let centroid_bounds = refs
+        .par_iter()
+        .map(|r| Bounds3::from_point(r.centroid))
+        .reduce(Bounds3f::empty, |a, b| Bounds3::union(&a, &b));
+
+    let mut sorted: Vec<_> = refs
+        .par_iter()
+        .map(|&primitive| MortonPrimitive {
+            code: morton_code(centroid_bounds.offset(primitive.centroid)),
+            primitive,
+        })
+        .collect();
+    sorted.par_sort_unstable_by_key(|m| m.code);
+
+    let root = emit(&sorted, 0, 3 * MORTON_BITS as i32 - 1, max_prims_in_node);
+    let root = if optimize_treelets {
+        let optimized = optimize(root.clone());
+        // Restructuring may deepen the tree a bit; in the unlikely case it
+        // gets too deep to traverse, keep the original.
+        if optimized.height() < MAX_DEPTH {
+            optimized
+        } else {
+            root
+        }
+    } else {
+        root
+    };
+
+    let mut nodes = Vec::with_capacity(2 * sorted.len());
+    let mut indices = Vec::with_capacity(sorted.len());
+    flatten(&root, &sorted, &mut nodes, &mut indices);
+    (nodes, indices)
+}
+
+/// Interleaves bits of quantized coordinates in `[0, 1]`.
+fn morton_code(p: Vec3f) -> u32 {
+    let scale = (1 << MORTON_BITS) as Float;
+    let quantize = |x: Float| ((x * scale) as u32).min((1 << MORTON_BITS) - 1);
+    (left_shift_3(quantize(p.z)) << 2)
+        | (left_shift_3(quantize(p.y)) << 1)
+        | left_shift_3(quantize(p.x))
+}
+
+/// Spreads the lower 10 bits of `x` out, so that there are two zero bits
+/// between each of them.
+fn left_shift_3(x: u32) -> u32 {
+    let x = (x | (x << 16)) & 0b0000_0011_0000_0000_0000_0000_1111_1111;
+    let x = (x | (x << 8)) & 0b0000_0011_0000_0000_1111_0000_0000_1111;
+    let x = (x | (x << 4)) & 0b0000_0011_0000_1100_0011_0000_1100_0011;
+    (x | (x << 2)) & 0b0000_1001_0010_0100_1001_0010_0100_1001
+}
+
+/// Intermediate tree, built in parallel and restructured before it's
+/// flattened.
+#[derive(Clone)]
+enum BuildNode {
+    Leaf {
+        bounds: Bounds3f,
+        /// Range of primitives in the Morton-sorted array.
+        first: usize,
+        count: usize,
+    },
+    Interior {
+        bounds: Bounds3f,
+        children: Box<[BuildNode; 2]>,
+        axis: usize,
+        /// SAH cost of the subtree, not normalized by the root's area.
+        cost: Float,
+        height: usize,
+        count: usize,
+    },
+}
+
+impl BuildNode {
+    fn leaf(sorted: &[MortonPrimitive], first: usize) -> BuildNode {
+        let bounds = sorted.iter().fold(Bounds3f::empty(), |b, m| {
+            Bounds3::union(&b, &m.primitive.bounds)
+        });
+        BuildNode::Leaf {
+            bounds,
+            first,
+            count: sorted.len(),
+        }
+    }
+
+    fn interior([a, b]: [BuildNode; 2]) -> BuildNode {
+        let bounds = Bounds3::union(a.bounds(), b.bounds());
+        // Order children along the axis they're separated the most, so that
+        // traversal can visit the nearer one first.
+        let (ca, cb) = (a.bounds().centroid(), b.bounds().centroid());
+        let axis = (cb - ca).abs().max_dimension();
+        let children = if ca[axis] <= cb[axis] { [a, b] } else { [b, a] };
+        BuildNode::Interior {
+            bounds,
+            axis,
+            cost: bounds.area() + children[0].cost() + children[1].cost(),
+            height: 1 + children[0].height().max(children[1].height()),
+            count: children[0].count() + children[1].count(),
+            children: Box::new(children),
+        }
+    }
+
+    fn bounds(&self) -> &Bounds3f {
+        match self {
+            BuildNode::Leaf { bounds, .. } => bounds,
+            BuildNode::Interior { bounds, .. } => bounds,
+        }
+    }
+
+    fn cost(&self) -> Float {
+        match self {
+            BuildNode::Leaf { bounds, count, .. } => bounds.area() * *count as Float,
+            BuildNode::Interior { cost, .. } => *cost,
+        }
+    }
+
+    fn height(&self) -> usize {
+        match self {
+            BuildNode::Leaf { .. } => 0,
+            BuildNode::Interior { height, .. } => *height,
+        }
+    }
+
+    fn count(&self) -> usize {
+        match self {
+            BuildNode::Leaf { count, .. } => *count,
+            BuildNode::Interior { count, .. } => *count,
+        }
+    }
+}
+
+/// Builds a subtree over Morton-sorted primitives, which all share code bits
+/// above `bit`. `first` is the offset of `sorted` in the whole array.
+fn emit(sorted: &[MortonPrimitive], first: usize, bit: i32, max_prims_in_node: usize) -> BuildNode {
+    let n = sorted.len();
+    if n <= max_prims_in_node {
+        return BuildNode::leaf(sorted, first);
+    }
+
+    let split = if bit < 0 {
+        // Out of bits, the primitives are too close to tell apart
+        n / 2
+    } else {
+        let mask = 1 << bit;
+        if sorted[0].code & mask == sorted[n - 1].code & mask {
+            // Every primitive is on the same side of this bit
+            return emit(sorted, first, bit - 1, max_prims_in_node);
+        }
+        sorted.partition_point(|m| m.code & mask == 0)
+    };
+
+    let (left, right) = sorted.split_at(split);
+    let build_left = || emit(left, first, bit - 1, max_prims_in_node);
+    let build_right = || emit(right, first + split, bit - 1, max_prims_in_node);
+    let children = if n > PARALLEL_THRESHOLD {
+        rayon::join(build_left, build_right)
+    } else {
+        (build_left(), build_right())
+    };
+    BuildNode::interior([children.0, children.1])
+}
+
+/// Optimizes treelets bottom-up, so that every treelet is formed from
+/// already optimized subtrees.
+fn optimize(node: BuildNode) -> BuildNode {
+    match node {
+        BuildNode::Leaf { .. } => node,
+        BuildNode::Interior {
+            children, count, ..
+        } => {
+            let [a, b] = *children;
+            let optimize_a = || optimize(a);
+            let optimize_b = || optimize(b);
+            let (a, b) = if count > PARALLEL_THRESHOLD {
+                rayon::join(optimize_a, optimize_b)
+            } else {
+                (optimize_a(), optimize_b())
+            };
+            restructure(BuildNode::interior([a, b]))
+        }
+    }
+}
+
+/// Finds the topology of the treelet rooted at `node` with the lowest SAH
+/// cost.
+///
+/// The treelet is grown from `node` by repeatedly expanding the largest of
+/// its interior leaves. Its leaves are kept as they are, while all the
+/// interior nodes get rearranged.
+fn restructure(node: BuildNode) -> BuildNode {
+    let mut leaves = vec![node];
+    while leaves.len() < TREELET_LEAVES {
+        let largest = leaves
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| matches!(n, BuildNode::Interior { .. }))
+            .max_by(|(_, a), (_, b)| {
+                let (a, b) = (a.bounds().area(), b.bounds().area());
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i);
+        match largest {
+            Some(i) => {
+                if let BuildNode::Interior { children, .. } = leaves.swap_remove(i) {
+                    let [a, b] = *children;
+                    leaves.push(a);
+                    leaves.push(b);
+                }
+            }
+            None => break,
+        }
+    }
+
+    let n = leaves.len();
+    if n < 3 {
+        // Two leaves can only be arranged in one way
+        let b = leaves.pop();
+        let a = leaves.pop();
+        return match (a, b) {
+            (Some(a), Some(b)) => BuildNode::interior([a, b]),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => unreachable!(),
+        };
+    }
+
+    // Dynamic programming over subsets of leaves, smallest first
+    let subsets = 1usize << n;
+    let mut cost = vec![0.0 as Float; subsets];
+    let mut partition = vec![0usize; subsets];
+    for s in 1..subsets {
+        if s.count_ones() == 1 {
+            cost[s] = leaves[s.trailing_zeros() as usize].cost();
+            continue;
+        }
+        let area = (0..n)
+            .filter(|i| s & (1 << i) != 0)
+            .fold(Bounds3f::empty(), |b, i| {
+                Bounds3::union(&b, leaves[i].bounds())
+            })
+            .area();
+
+        // Enumerate subsets containing the lowest bit, to see every
+        // partition exactly once
+        let lowest = s & s.wrapping_neg();
+        let (mut best, mut best_cost) = (0, Float::INFINITY);
+        let mut p = (s - 1) & s;
+        while p > 0 {
+            if p & lowest != 0 {
+                let c = cost[p] + cost[s ^ p];
+                if c < best_cost {
+                    best = p;
+                    best_cost = c;
+                }
+            }
+            p = (p - 1) & s;
+        }
+        cost[s] = area + best_cost;
+        partition[s] = best;
+    }
+
+    let mut leaves: Vec<_> = leaves.into_iter().map(Some).collect();
+    rebuild(subsets - 1, &partition, &mut leaves)
+}
+
+fn rebuild(s: usize, partition: &[usize], leaves: &mut [Option<BuildNode>]) -> BuildNode {
+    if s.count_ones() == 1 {
+        return leaves[s.trailing_zeros() as usize]
+            .take()
+            .expect("every treelet leaf is used once");
+    }
+    let p = partition[s];
+    let a = rebuild(p, partition, leaves);
+    let b = rebuild(s ^ p, partition, leaves);
+    BuildNode::interior([a, b])
+}
+
+/// Appends the subtree in depth-first order, returns the index of its root.
+fn flatten(
+    node: &BuildNode,
+    sorted: &[MortonPrimitive],
+    nodes: &mut Vec<Node>,
+    indices: &mut Vec<u32>,
+) -> usize {
+    let index = nodes.len();
+    match node {
+        BuildNode::Leaf {
+            bounds,
+            first,
+            count,
+        } => {
+            nodes.push(Node::leaf(*bounds, indices.len(), *count));
+            indices.extend(
+                sorted[*first..*first + *count]
+                    .iter()
+                    .map(|m| m.primitive.index),
+            );
+        }
+        BuildNode::Interior {
+            bounds,
+            children,
+            axis,
+            ..
+        } => {
+            nodes.push(Node::interior(*bounds, 0, *axis));
+            flatten(&children[0], sorted, nodes, indices);
+            let second_child = flatten(&children[1], sorted, nodes, indices);
+            nodes[index] = Node::interior(*bounds, second_child, *axis);
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::accel::{Bvh, BvhSpec, SplitMethod};
+    use crate::hit::Hit;
+
+    #[test]
+    fn morton_codes_interleave_bits() {
+        assert_eq!(morton_code(vec3(0.0, 0.0, 0.0)), 0);
+        assert_eq!(morton_code(vec3(1.0, 1.0, 1.0)), (1 << 30) - 1);
+        let x = 1.0 / 1024.0;
+        assert_eq!(morton_code(vec3(x, 0.0, 0.0)), 0b001);
+        assert_eq!(morton_code(vec3(0.0, x, 0.0)), 0b010);
+        assert_eq!(morton_code(vec3(0.0, 0.0, x)), 0b100);
+        assert_eq!(morton_code(vec3(0.5, 0.0, 0.0)), 1 << 27);
+    }
+
+    fn lbvh_spec(optimize_treelets: bool) -> BvhSpec {
+        BvhSpec {
+            split_method: SplitMethod::Lbvh { optimize_treelets },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn lbvh_finds_closest_hits() {
+        for &optimize_treelets in &[false, true] {
+            let triangles = random_triangles(3000, 7);
+            let expected: Vec<_> = random_rays(500, 8)
+                .iter()
+                .map(|r| brute_force(&triangles, r))
+                .collect();
+
+            let bvh = Bvh::from_spec(triangles, lbvh_spec(optimize_treelets));
+            for (ray, expected) in random_rays(500, 8).iter().zip(expected) {
+                let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+                assert_eq!(t, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn lbvh_references_every_primitive_once() {
+        for &optimize_treelets in &[false, true] {
+            let bvh = Bvh::from_spec(random_triangles(3000, 9), lbvh_spec(optimize_treelets));
+            let mut indices = bvh.indices.clone();
+            indices.sort_unstable();
+            assert_eq!(indices, (0..3000).collect::<Vec<_>>());
+            assert!(bvh.nodes.iter().all(|n| n.n_primitives <= 4));
+        }
+    }
+
+    #[test]
+    fn treelet_optimization_lowers_sah_cost() {
+        let plain = Bvh::from_spec(random_triangles(5000, 10), lbvh_spec(false));
+        let optimized = Bvh::from_spec(random_triangles(5000, 10), lbvh_spec(true));
+        assert!(
+            optimized.sah_cost() < plain.sah_cost(),
+            "{} >= {}",
+            optimized.sah_cost(),
+            plain.sah_cost()
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::primitive::Primitive;
+
+use super::bvh::{partition, sah_costs, split_equal_counts, BuildPrimitive, Node, N_BUCKETS};
+
+/// Past this depth, the builder stops looking for good splits and simply
+/// halves reference counts. Duplicated references make for more of them than
+/// there are primitives, so it is lower than for the plain SAH builder.
+const MAX_SAH_DEPTH: usize = 28;
+
+/// Number of bins for spatial splits.
+const N_BINS: usize = 16;
+
+/// Spatial splits are only tried where children of the best object split
+/// overlap by this much, relative to the area of the whole scene. See Stich
+/// et al., "Spatial Splits in Bounding Volume Hierarchies" (HPG 2009).
+const MIN_OVERLAP: Float = 1.0e-5;
+
+/// Builds a BVH with spatial splits: references to primitives straddling a
+/// split plane may be duplicated on both of its sides, with their bounds
+/// clipped to each side.
+///
+/// No more than `max_duplication` times the number of primitives extra
+/// references are made.
+pub(crate) fn build<P: Primitive>(
+    primitives: &[P],
+    refs: Vec<BuildPrimitive>,
+    max_prims_in_node: usize,
+    max_duplication: Float,
+) -> (Vec<Node>, Vec<u32>) {
+    if refs.is_empty() {
+        return (Vec::new(), Vec::new());
+    }
+
+    let bounds = union_all(&refs);
+    let budget = (refs.len() as Float * max_duplication.max(0.0)) as usize;
+    let mut builder = SbvhBuilder {
+        primitives,
+        max_prims_in_node,
+        min_overlap: MIN_OVERLAP * bounds.area(),
+        budget,
+        nodes: Vec::with_capacity(2 * refs.len()),
+        indices: Vec::with_capacity(refs.len()),
+    };
+    builder.build(refs, 0);
+    (builder.nodes, builder.indices)
+}
+
+struct SbvhBuilder<'a, P> {
+    primitives: &'a [P],
+    max_prims_in_node: usize,
+    /// Least overlap of object split children to try spatial splits.
+    min_overlap: Float,
+    /// Number of references yet allowed to be duplicated.
+    budget: usize,
+    nodes: Vec<Node>,
+    indices: Vec<u32>,
+}
+
+/// Best split of a node found so far.
+#[derive(Copy, Clone, Debug)]
+enum Split {
+    /// Partition by bucket of the centroid.
+    Object { axis: usize, bucket: usize },
+    /// Cut along an axis-aligned plane.
+    Spatial { axis: usize, plane: Float },
+}
+
+impl<P: Primitive> SbvhBuilder<'_, P> {
+    /// Builds a subtree over `refs`, returns the index of its root node.
+    fn build(&mut self, mut refs: Vec<BuildPrimitive>, depth: usize) -> usize {
+        let bounds = union_all(&refs);
+        let n = refs.len();
+
+        if n == 1 {
+            return self.leaf(bounds, &refs);
+        }
+
+        let centroid_bounds = refs.iter().fold(Bounds3f::empty(), |b, r| {
+            Bounds3::union_point(&b, r.centroid)
+        });
+
+        if depth >= MAX_SAH_DEPTH || centroid_bounds.diagonal().max_component() == 0.0 {
+            if n <= self.max_prims_in_node {
+                return self.leaf(bounds, &refs);
+            }
+            let axis = centroid_bounds.maximum_extent();
+            let mid = split_equal_counts(&mut refs, axis);
+            let right = refs.split_off(mid);
+            return self.interior(bounds, axis, refs, right, depth);
+        }
+
+        let (mut split, mut cost, overlap) =
+            self.best_object_split(&refs, &bounds, &centroid_bounds);
+        if n > self.max_prims_in_node && overlap > self.min_overlap && self.budget > 0 {
+            if let Some((spatial, spatial_cost)) = self.best_spatial_split(&refs, &bounds) {
+                if spatial_cost < cost {
+                    split = spatial;
+                    cost = spatial_cost;
+                }
+            }
+        }
+
+        if n <= self.max_prims_in_node && n as Float <= cost {
+            return self.leaf(bounds, &refs);
+        }
+
+        match split {
+            Split::Object { axis, bucket } => {
+                let bucket_of = |r: &BuildPrimitive| bucket_index(r, &centroid_bounds, axis);
+                let mut mid = partition(&mut refs, |r| bucket_of(r) <= bucket);
+                if mid == 0 || mid == n {
+                    mid = split_equal_counts(&mut refs, axis);
+                }
+                let right = refs.split_off(mid);
+                self.interior(bounds, axis, refs, right, depth)
+            }
+            Split::Spatial { axis, plane } => {
+                let (left, right) = self.split_spatial(refs, axis, plane);
+                self.interior(bounds, axis, left, right, depth)
+            }
+        }
+    }
+
+    fn interior(
+        &mut self,
+        bounds: Bounds3f,
+        axis: usize,
+        left: Vec<BuildPrimitive>,
+        right: Vec<BuildPrimitive>,
+        depth: usize,
+    ) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node::interior(bounds, 0, axis));
+        self.build(left, depth + 1);
+        let second_child = self.build(right, depth + 1);
+        self.nodes[node] = Node::interior(bounds, second_child, axis);
+        node
+    }
+
+    fn leaf(&mut self, bounds: Bounds3f, refs: &[BuildPrimitive]) -> usize {
+        let node = self.nodes.len();
+        self.nodes
+            .push(Node::leaf(bounds, self.indices.len(), refs.len()));
+        self.indices.extend(refs.iter().map(|r| r.index));
+        node
+    }
+
+    /// Finds the cheapest centroid bucket boundary along any axis. Returns
+    /// the split, its cost, and how much the children would overlap.
+    fn best_object_split(
+        &self,
+        refs: &[BuildPrimitive],
+        bounds: &Bounds3f,
+        centroid_bounds: &Bounds3f,
+    ) -> (Split, Float, Float) {
+        let mut best = (Split::Object { axis: 0, bucket: 0 }, Float::INFINITY, 0.0);
+        for axis in 0..3 {
+            if centroid_bounds.max[axis] == centroid_bounds.min[axis] {
+                continue;
+            }
+
+            let mut counts = [0usize; N_BUCKETS];
+            let mut bucket_bounds = [Bounds3f::empty(); N_BUCKETS];
+            for r in refs {
+                let b = bucket_index(r, centroid_bounds, axis);
+                counts[b] += 1;
+                bucket_bounds[b] = Bounds3::union(&bucket_bounds[b], &r.bounds);
+            }
+
+            let costs = sah_costs(&counts, &bucket_bounds, bounds.area());
+            for (bucket, &cost) in costs.iter().enumerate() {
+                if cost < best.1 {
+                    let below = union_all_bounds(&bucket_bounds[..=bucket]);
+                    let above = union_all_bounds(&bucket_bounds[bucket + 1..]);
+                    let overlap = Bounds3::intersect(&below, &above);
+                    let overlap = if overlap.is_empty() {
+                        0.0
+                    } else {
+                        overlap.area()
+                    };
+                    best = (Split::Object { axis, bucket }, cost, overlap);
+                }
+            }
+        }
+        best
+    }
+
+    /// Finds the cheapest bin boundary to cut the node along its longest
+    /// axis, if there is one which fits in the duplication budget.
+    ///
+    /// Other axes are not tried, clipping is expensive enough as it is.
+    fn best_spatial_split(
+        &self,
+        refs: &[BuildPrimitive],
+        bounds: &Bounds3f,
+    ) -> Option<(Split, Float)> {
+        let n = refs.len();
+        let axis = bounds.maximum_extent();
+        let (min, extent) = (bounds.min[axis], bounds.max[axis] - bounds.min[axis]);
+        if extent <= 0.0 {
+            return None;
+        }
+        let plane = |i: usize| min + extent * i as Float / N_BINS as Float;
+        let bin = |x: Float| (((x - min) / extent * N_BINS as Float) as usize).min(N_BINS - 1);
+
+        // References entering and leaving each bin, and bounds of their parts
+        // inside of the bin
+        let mut entries = [0usize; N_BINS];
+        let mut exits = [0usize; N_BINS];
+        let mut bin_bounds = [Bounds3f::empty(); N_BINS];
+        for r in refs {
+            let (first, last) = (bin(r.bounds.min[axis]), bin(r.bounds.max[axis]));
+            entries[first] += 1;
+            exits[last] += 1;
+            if first == last {
+                bin_bounds[first] = Bounds3::union(&bin_bounds[first], &r.bounds);
+                continue;
+            }
+            let primitive = &self.primitives[r.index as usize];
+            for (i, b) in bin_bounds.iter_mut().enumerate().take(last + 1).skip(first) {
+                let slab = slab(&r.bounds, axis, plane(i), plane(i + 1));
+                *b = Bounds3::union(b, &primitive.clipped_bound(&slab));
+            }
+        }
+
+        // Sweep from the right, to accumulate everything above each plane
+        let mut above = [(0usize, 0.0 as Float); N_BINS - 1];
+        let (mut count, mut b) = (0, Bounds3f::empty());
+        for i in (1..N_BINS).rev() {
+            count += exits[i];
+            b = Bounds3::union(&b, &bin_bounds[i]);
+            above[i - 1] = (count, if count > 0 { b.area() } else { 0.0 });
+        }
+
+        let inv_area = bounds.area().recip();
+        let mut best = None;
+        let (mut count, mut b) = (0, Bounds3f::empty());
+        for i in 0..N_BINS - 1 {
+            count += entries[i];
+            b = Bounds3::union(&b, &bin_bounds[i]);
+            let (count_above, area_above) = above[i];
+            let duplicated = (count + count_above).saturating_sub(n);
+            // Both sides may end up with all of the references, but each such
+            // split eats into the budget
+            if count == 0 || count_above == 0 || duplicated > self.budget {
+                continue;
+            }
+            let cost =
+                1.0 + (count as Float * b.area() + count_above as Float * area_above) * inv_area;
+            let better = match best {
+                Some((_, best_cost)) => cost < best_cost,
+                None => true,
+            };
+            if better {
+                let split = Split::Spatial {
+                    axis,
+                    plane: plane(i + 1),
+                };
+                best = Some((split, cost));
+            }
+        }
+
+        best
+    }
+
+    /// Distributes references to both sides of the plane. The ones crossing
+    /// it are clipped, and end up on both sides.
+    fn split_spatial(
+        &mut self,
+        refs: Vec<BuildPrimitive>,
+        axis: usize,
+        plane: Float,
+    ) -> (Vec<BuildPrimitive>, Vec<BuildPrimitive>) {
+        let (mut left, mut right) = (Vec::new(), Vec::new());
+        for r in refs {
+            if r.bounds.max[axis] <= plane {
+                left.push(r);
+            } else if r.bounds.min[axis] >= plane {
+                right.push(r);
+            } else {
+                let primitive = &self.primitives[r.index as usize];
+                let below =
+                    primitive.clipped_bound(&slab(&r.bounds, axis, r.bounds.min[axis], plane));
+                let above =
+                    primitive.clipped_bound(&slab(&r.bounds, axis, plane, r.bounds.max[axis]));
+                match (below.is_empty(), above.is_empty()) {
+                    (false, false) => {
+                        left.push(BuildPrimitive::new(r.index as usize, below));
+                        right.push(BuildPrimitive::new(r.index as usize, above));
+                        self.budget = self.budget.saturating_sub(1);
+                    }
+                    (false, true) => left.push(r),
+                    (true, false) => right.push(r),
+                    // Clipping away the whole primitive is a rounding error
+                    (true, true) => {
+                        if r.centroid[axis] < plane {
+                            left.push(r)
+                        } else {
+                            right.push(r)
+                        }
+                    }
+                }
+            }
+        }
+
+        // Every primitive crossing the plane may have turned out to be on one
+        // side of it after all
+        if left.is_empty() || right.is_empty() {
+            let mut refs = left;
+            refs.append(&mut right);
+            let mid = split_equal_counts(&mut refs, axis);
+            let right = refs.split_off(mid);
+            (refs, right)
+        } else {
+            (left, right)
+        }
+    }
+}
+
+fn bucket_index(r: &BuildPrimitive, centroid_bounds: &Bounds3f, axis: usize) -> usize {
+    let b = (N_BUCKETS as Float * centroid_bounds.offset(r.centroid)[axis]) as usize;
+    b.min(N_BUCKETS - 1)
+}
+
+/// Part of the bounds between two planes along an axis.
+fn slab(bounds: &Bounds3f, axis: usize, min: Float, max: Float) -> Bounds3f {
+    let mut lo = [bounds.min.x, bounds.min.y, bounds.min.z];
+    let mut hi = [bounds.max.x, bounds.max.y, bounds.max.z];
+    lo[axis] = min;
+    hi[axis] = max;
+    Bounds3::intersect(
+        bounds,
+        &Bounds3 {
+            min: point3(lo[0], lo[1], lo[2]),
+            max: point3(hi[0], hi[1], hi[2]),
+        },
+    )
+}
+
+fn union_all(refs: &[BuildPrimitive]) -> Bounds3f {
+    refs.iter()
+        .fold(Bounds3f::empty(), |b, r| Bounds3::union(&b, &r.bounds))
+}
+
+fn union_all_bounds(bounds: &[Bounds3f]) -> Bounds3f {
+    bounds
+        .iter()
+        .fold(Bounds3f::empty(), |a, b| Bounds3::union(&a, b))
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::accel::{Bvh, BvhSpec, SplitMethod};
+    use crate::hit::Hit;
+    use crate::material::null::NullMaterial;
+    use crate::shape::triangle::Triangle;
+
+    /// Long thin triangles, criss-crossing the scene diagonally.
+    fn slivers(n: usize) -> Vec<Triangle<'static>> {
+        (0..n)
+            .map(|i| {
+                let x = i as Float / n as Float * 20.0 - 10.0;
+                let (a, b) = if i % 2 == 0 {
+                    (point3(x, -10.0, -10.0), point3(-x, 10.0, 10.0))
+                } else {
+                    (point3(-10.0, x, 10.0), point3(10.0, -x, -10.0))
+                };
+                Triangle {
+                    positions: [a, b, b + vec3(0.05, 0.0, 0.05)],
+                    material: &NullMaterial,
+                    backface_culling: false,
+                    uvs: None,
+                    tangents: None,
+                }
+            })
+            .collect()
+    }
+
+    fn sbvh(max_duplication: Float) -> BvhSpec {
+        BvhSpec {
+            split_method: SplitMethod::Sbvh { max_duplication },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sbvh_finds_closest_hits() {
+        let scenes: [fn() -> Vec<Triangle<'static>>; 2] =
+            [|| random_triangles(2000, 31), || slivers(500)];
+        for scene in &scenes {
+            let triangles = scene();
+            let bvh = Bvh::from_spec(scene(), sbvh(0.5));
+            for ray in random_rays(500, 32).iter() {
+                let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+                let expected = brute_force(&triangles, ray);
+                assert_eq!(t, expected);
+                assert_eq!(bvh.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+            }
+        }
+    }
+
+    #[test]
+    fn sbvh_is_cheaper_for_slivers() {
+        let sah = Bvh::new(slivers(500));
+        let sbvh = Bvh::from_spec(slivers(500), sbvh(1.0));
+        assert!(sbvh.sah_cost() < 0.8 * sah.sah_cost());
+    }
+
+    #[test]
+    fn sbvh_stays_within_budget() {
+        let n = 500;
+        for &max_duplication in &[0.0, 0.1, 0.5] {
+            let bvh = Bvh::from_spec(slivers(n), sbvh(max_duplication));
+            assert!(bvh.indices.len() <= n + (n as Float * max_duplication) as usize);
+
+            // Every primitive is referred to at least once
+            let mut seen = vec![false; n];
+            bvh.indices.iter().for_each(|&i| seen[i as usize] = true);
+            assert!(seen.iter().all(|&s| s));
+        }
+
+        let no_duplication = Bvh::from_spec(slivers(n), sbvh(0.0));
+        assert_eq!(no_duplication.indices.len(), n);
+    }
+}
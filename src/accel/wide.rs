@@ -0,0 +1,452 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::*;
+use crate::primitive::Primitive;
+
+use super::bvh::{Bvh, BvhSpec, Node};
+use super::{Aggregate, TraversalStats};
+
+/// Bounding volume hierarchy with up to `N` children per node.
+///
+/// It is built as a binary BVH first, which is then collapsed: each node
+/// takes in the children of its largest interior children, until it has `N`
+/// of them. Bounds of all children are stored together in structure-of-arrays
+/// layout, so a ray is tested against all of them at once, and visits them
+/// nearest first.
+///
+/// Fewer, fatter nodes mean less jumping around in memory than with a binary
+/// BVH.
+pub struct WideBvh<P, const N: usize = 4> {
+    primitives: Vec<P>,
+    /// Indices into `primitives`, in the order leaf children refer to them.
+    indices: Vec<u32>,
+    nodes: Vec<WideNode<N>>,
+    /// Most children the traversal can have on the stack at once.
+    stack_size: usize,
+}
+
+/// 4-wide BVH.
+pub type Bvh4<P> = WideBvh<P, 4>;
+
+/// 8-wide BVH.
+pub type Bvh8<P> = WideBvh<P, 8>;
+
+#[derive(Copy, Clone, Debug)]
+struct WideNode<const N: usize> {
+    bounds: WideBounds3<N>,
+    /// Interior children: index of the node. Leaf children: offset of the
+    /// first primitive index.
+    offset: [u32; N],
+    /// Number of primitives in leaf children, zero for interior children.
+    n_primitives: [u16; N],
+    /// Number of children in use, the rest are empty.
+    n_children: u8,
+}
+
+impl<const N: usize> WideNode<N> {
+    fn empty() -> WideNode<N> {
+        WideNode {
+            bounds: WideBounds3::empty(),
+            offset: [0; N],
+            n_primitives: [0; N],
+            n_children: 0,
+        }
+    }
+}
+
+/// A child to visit, and the time the ray enters it.
+#[derive(Copy, Clone, Debug, Default)]
+struct Entry {
+    offset: u32,
+    n_primitives: u16,
+    t: Float,
+}
+
+/// Widest node supported. Wider nodes would waste more empty slots than
+/// they save box tests.
+const MAX_WIDTH: usize = 8;
+
+/// Traversal stack is kept on the machine stack when it fits in this many
+/// entries, which it does for all but the most unbalanced trees. Larger
+/// stacks are slow to initialize for every ray.
+const SMALL_STACK_SIZE: usize = 64;
+
+impl<P: Primitive, const N: usize> WideBvh<P, N> {
+    /// Builds a wide BVH over given primitives, with default parameters.
+    pub fn new(primitives: Vec<P>) -> WideBvh<P, N> {
+        WideBvh::from_spec(primitives, BvhSpec::default())
+    }
+
+    /// Builds a wide BVH over given primitives. The spec is that of the
+    /// binary BVH which gets collapsed.
+    pub fn from_spec(primitives: Vec<P>, spec: BvhSpec) -> WideBvh<P, N> {
+        WideBvh::from_bvh(Bvh::from_spec(primitives, spec))
+    }
+
+    /// Collapses a binary BVH into a wide one.
+    pub fn from_bvh(bvh: Bvh<P>) -> WideBvh<P, N> {
+        assert!((2..=MAX_WIDTH).contains(&N), "unsupported BVH width {}", N);
+
+        let mut nodes = Vec::with_capacity(bvh.nodes.len() / (N - 1) + 1);
+        if !bvh.nodes.is_empty() {
+            collapse(&bvh.nodes, 0, &mut nodes);
+        }
+
+        let stack_size = if nodes.is_empty() {
+            0
+        } else {
+            stack_size(&nodes, 0)
+        };
+
+        WideBvh {
+            primitives: bvh.primitives,
+            indices: bvh.indices,
+            nodes,
+            stack_size,
+        }
+    }
+}
+
+impl<P, const N: usize> WideBvh<P, N> {
+    /// Primitives the BVH was built over, in their original order.
+    pub fn primitives(&self) -> &[P] {
+        &self.primitives
+    }
+
+    /// Number of nodes in the tree.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+/// Appends a wide node made of the binary node `root` and its descendants,
+/// followed by its own descendants, depth first. Returns its index.
+fn collapse<const N: usize>(binary: &[Node], root: usize, nodes: &mut Vec<WideNode<N>>) -> usize {
+    let index = nodes.len();
+    nodes.push(WideNode::empty());
+
+    let mut children = Vec::with_capacity(N);
+    if binary[root].is_leaf() {
+        children.push(root);
+    } else {
+        children.extend_from_slice(&[root + 1, binary[root].offset as usize]);
+    }
+
+    // Open up the largest interior children, the ones most likely to be hit
+    while children.len() < N {
+        let largest = children
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| !binary[c].is_leaf())
+            .max_by(|(_, &a), (_, &b)| {
+                let (a, b) = (binary[a].bounds.area(), binary[b].bounds.area());
+                a.partial_cmp(&b).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .map(|(i, _)| i);
+        match largest {
+            Some(i) => {
+                let c = children[i];
+                children[i] = c + 1;
+                children.push(binary[c].offset as usize);
+            }
+            None => break,
+        }
+    }
+
+    let mut node = WideNode::empty();
+    node.n_children = children.len() as u8;
+    for (i, &c) in children.iter().enumerate() {
+        let child = &binary[c];
+        node.bounds.set(i, &child.bounds);
+        if child.is_leaf() {
+            node.offset[i] = child.offset;
+            node.n_primitives[i] = child.n_primitives;
+        } else {
+            node.offset[i] = collapse(binary, c, nodes) as u32;
+        }
+    }
+    nodes[index] = node;
+
+    index
+}
+
+/// Stack size needed to traverse the subtree under a node: its children are
+/// pushed, and the nearest one is popped, to push its own children.
+fn stack_size<const N: usize>(nodes: &[WideNode<N>], index: usize) -> usize {
+    let node = &nodes[index];
+    let n = node.n_children as usize;
+    (0..n)
+        .filter(|&i| node.n_primitives[i] == 0)
+        .map(|i| n - 1 + stack_size(nodes, node.offset[i] as usize))
+        .fold(n, usize::max)
+}
+
+impl<P: Primitive, const N: usize> Hit for WideBvh<P, N> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.hit_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.occluded_with_stats(ray, t_min, t_max, &mut TraversalStats::default())
+    }
+}
+
+impl<P: Primitive, const N: usize> Aggregate for WideBvh<P, N> {
+    fn hit_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>> {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            None
+        } else if self.stack_size <= SMALL_STACK_SIZE {
+            let mut stack = [Entry::default(); SMALL_STACK_SIZE];
+            self.hit_with_stack(&mut stack, ray, (t_min, t_max), stats)
+        } else {
+            let mut stack = vec![Entry::default(); self.stack_size];
+            self.hit_with_stack(&mut stack, ray, (t_min, t_max), stats)
+        }
+    }
+
+    fn occluded_with_stats(
+        &self,
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        stats: &mut TraversalStats,
+    ) -> bool {
+        stats.rays += 1;
+        if self.nodes.is_empty() {
+            false
+        } else if self.stack_size <= SMALL_STACK_SIZE {
+            let mut stack = [Entry::default(); SMALL_STACK_SIZE];
+            self.occluded_with_stack(&mut stack, ray, (t_min, t_max), stats)
+        } else {
+            let mut stack = vec![Entry::default(); self.stack_size];
+            self.occluded_with_stack(&mut stack, ray, (t_min, t_max), stats)
+        }
+    }
+}
+
+impl<P: Primitive, const N: usize> WideBvh<P, N> {
+    /// Finds the closest hit. The stack starts with the root node.
+    fn hit_with_stack(
+        &self,
+        stack: &mut [Entry],
+        ray: &Ray,
+        (t_min, t_max): (Float, Float),
+        stats: &mut TraversalStats,
+    ) -> Option<HitStruct<'_>> {
+        let (o, d) = ray.origin_and_direction();
+        let inv_d = vec3(d.x.recip(), d.y.recip(), d.z.recip());
+
+        let mut closest = t_max;
+        let mut result = None;
+
+        // Children yet to be visited, the nearest ones on top
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let entry = stack[stack_len];
+            // A closer hit was found since the child was pushed
+            if entry.t > closest {
+                continue;
+            }
+
+            if entry.n_primitives > 0 {
+                let first = entry.offset as usize;
+                let last = first + entry.n_primitives as usize;
+                stats.primitives += entry.n_primitives as u64;
+                for &i in &self.indices[first..last] {
+                    if let Some(hit) = self.primitives[i as usize].hit(ray, t_min, closest) {
+                        closest = hit.t;
+                        result = Some(hit);
+                    }
+                }
+            } else {
+                let node = &self.nodes[entry.offset as usize];
+                stats.nodes += 1;
+                let t = node.bounds.hit(o, inv_d, (t_min, closest));
+                // Insertion sort of the hit children, farthest first
+                let bottom = stack_len;
+                for (i, &t) in t.iter().enumerate().take(node.n_children as usize) {
+                    if t == Float::INFINITY {
+                        // Missed
+                        continue;
+                    }
+                    let mut j = stack_len;
+                    while j > bottom && stack[j - 1].t < t {
+                        stack[j] = stack[j - 1];
+                        j -= 1;
+                    }
+                    stack[j] = Entry {
+                        offset: node.offset[i],
+                        n_primitives: node.n_primitives[i],
+                        t,
+                    };
+                    stack_len += 1;
+                }
+            }
+        }
+
+        result
+    }
+
+    /// Finds any hit. The stack starts with the root node.
+    fn occluded_with_stack(
+        &self,
+        stack: &mut [Entry],
+        ray: &Ray,
+        (t_min, t_max): (Float, Float),
+        stats: &mut TraversalStats,
+    ) -> bool {
+        let (o, d) = ray.origin_and_direction();
+        let inv_d = vec3(d.x.recip(), d.y.recip(), d.z.recip());
+
+        // Any hit will do, so children are visited in no particular order
+        let mut stack_len = 1;
+
+        while stack_len > 0 {
+            stack_len -= 1;
+            let entry = stack[stack_len];
+
+            if entry.n_primitives > 0 {
+                let first = entry.offset as usize;
+                let last = first + entry.n_primitives as usize;
+                for &i in &self.indices[first..last] {
+                    stats.primitives += 1;
+                    if self.primitives[i as usize].occluded(ray, t_min, t_max) {
+                        return true;
+                    }
+                }
+            } else {
+                let node = &self.nodes[entry.offset as usize];
+                stats.nodes += 1;
+                let t = node.bounds.hit(o, inv_d, (t_min, t_max));
+                for (i, &t) in t.iter().enumerate().take(node.n_children as usize) {
+                    if t < Float::INFINITY {
+                        stack[stack_len] = Entry {
+                            offset: node.offset[i],
+                            n_primitives: node.n_primitives[i],
+                            t,
+                        };
+                        stack_len += 1;
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
+
+impl<P: Primitive, const N: usize> Primitive for WideBvh<P, N> {
+    fn world_bound(&self) -> Bounds3f {
+        match self.nodes.first() {
+            Some(root) => (0..root.n_children as usize)
+                .map(|i| root.bounds.get(i))
+                .fold(Bounds3f::empty(), |a, b| Bounds3::union(&a, &b)),
+            None => Bounds3f::empty(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::accel::SplitMethod;
+    use crate::shape::triangle::Triangle;
+
+    fn check_hits<const N: usize>(spec: BvhSpec) {
+        let triangles = random_triangles(2000, 21);
+        let rays = random_rays(500, 22);
+        let expected: Vec<_> = rays.iter().map(|r| brute_force(&triangles, r)).collect();
+        assert!(expected.iter().any(|t| t.is_some()));
+
+        let mut bvh: WideBvh<_, N> = WideBvh::from_spec(triangles, spec);
+        assert!(bvh.stack_size <= SMALL_STACK_SIZE);
+        for (ray, expected) in rays.iter().zip(&expected) {
+            let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, *expected);
+            assert_eq!(bvh.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+        }
+
+        // Same thing, with the stack on the heap
+        bvh.stack_size = SMALL_STACK_SIZE + 1;
+        for (ray, expected) in rays.iter().zip(&expected) {
+            let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, *expected);
+            assert_eq!(bvh.occluded(ray, 0.0, Float::INFINITY), expected.is_some());
+        }
+    }
+
+    #[test]
+    fn wide_bvh_finds_closest_hits() {
+        check_hits::<2>(BvhSpec::default());
+        check_hits::<4>(BvhSpec::default());
+        check_hits::<8>(BvhSpec::default());
+        check_hits::<4>(BvhSpec {
+            split_method: SplitMethod::Lbvh {
+                optimize_treelets: false,
+            },
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn wide_bvh_is_collapsed() {
+        let bvh: Bvh<_> = Bvh::new(random_triangles(1000, 23));
+        let binary_nodes = bvh.node_count();
+        let bounds = bvh.world_bound();
+        let wide = Bvh4::from_bvh(bvh);
+
+        // Every binary interior node is merged into some wide node
+        let n_binary_interior = binary_nodes / 2;
+        assert!(wide.node_count() * 3 >= n_binary_interior);
+        assert!(wide.node_count() < n_binary_interior);
+        assert!(wide.nodes.iter().all(|n| n.n_children >= 2));
+
+        // Every primitive is referred to exactly once
+        let mut seen = vec![0; wide.primitives().len()];
+        for node in &wide.nodes {
+            for i in 0..node.n_children as usize {
+                let first = node.offset[i] as usize;
+                let count = node.n_primitives[i] as usize;
+                for &p in &wide.indices[first..first + count] {
+                    seen[p as usize] += 1;
+                }
+            }
+        }
+        assert!(seen.iter().all(|&n| n == 1));
+
+        let wide_bounds = wide.world_bound();
+        assert_eq!(wide_bounds.min, bounds.min);
+        assert_eq!(wide_bounds.max, bounds.max);
+    }
+
+    #[test]
+    fn wide_bvh_with_a_single_leaf() {
+        let triangles = random_triangles(3, 24);
+        let bvh = Bvh8::new(random_triangles(3, 24));
+        assert_eq!(bvh.node_count(), 1);
+        for ray in random_rays(100, 25).iter() {
+            let t = bvh.hit(ray, 0.0, Float::INFINITY).map(|hit| hit.t);
+            assert_eq!(t, brute_force(&triangles, ray));
+        }
+    }
+
+    #[test]
+    fn empty_wide_bvh() {
+        let bvh: Bvh4<Triangle> = WideBvh::new(Vec::new());
+        let ray = Ray::new(point3(0.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(bvh.hit(&ray, 0.0, Float::INFINITY).is_none());
+        assert!(!bvh.occluded(&ray, 0.0, Float::INFINITY));
+    }
+}
@@ -2,12 +2,17 @@ use std::fs::File;
 use std::io::BufWriter;
 use std::path::Path;
 
+use pbrt::accel::*;
+use pbrt::bsdf::BxdfFlags;
 use pbrt::camera::*;
 use pbrt::color::*;
 use pbrt::geo::*;
-use pbrt::hit::{Hit, HitStruct};
+use pbrt::light::AreaLight;
 use pbrt::material::*;
+use pbrt::medium::MediumStack;
 use pbrt::prelude::*;
+use pbrt::primitive::Primitive;
+use pbrt::sampling::{power_heuristic, uniform_sample_sphere};
 
 fn tonemap(colors: &[LinearColor], (nx, ny): (usize, usize)) -> Vec<Rgba<u8>> {
     use rayon::prelude::*;
@@ -25,55 +30,183 @@ fn tonemap(colors: &[LinearColor], (nx, ny): (usize, usize)) -> Vec<Rgba<u8>> {
     pixels
 }
 
-fn ray_color(scene: &Scene, ray: &Ray, limit: usize) -> LinearColor {
-    // 1.0e-4 prevents shadow acne
-    if let Some(hit) = scene.hit(ray, 1.0e-4, std::f32::INFINITY) {
-        if limit == 0 {
-            return Default::default();
+/// Longest random walk of a path inside scattering media.
+const MAX_WALK: usize = 1024;
+
+/// Radiance arriving along the ray, by path tracing. At every bounce, a
+/// point on a light is sampled, and combined with hitting lights by chance
+/// through multiple importance sampling. Inside scattering media, paths
+/// walk randomly from one scattering event to the next between surfaces.
+fn ray_color(
+    scene: &Scene,
+    lights: &[AreaLight],
+    ray: &Ray,
+    max_bounces: usize,
+    stats: &mut TraversalStats,
+    rng: &mut impl rand::Rng,
+) -> LinearColor {
+    let zero = Vec3f::default();
+    let mut radiance = zero;
+    let mut throughput = vec3(1.0, 1.0, 1.0);
+    let mut ray = Ray::new(ray.origin(), ray.direction().normalized());
+    // Where the ray comes from, and the density of the BSDF sampling it.
+    // Infinite for camera rays and specular bounces: nothing else would see
+    // lights along them.
+    let mut origin = ray.origin();
+    let mut bsdf_pdf = Float::INFINITY;
+
+    let mut media = MediumStack::new();
+    // Scattering events inside media so far, which aren't bounces
+    let mut walk = 0;
+
+    for bounce in 0..=max_bounces {
+        // Closest surface not inside a medium with a higher priority, and the
+        // refraction index on the other side of it
+        let (hit, outside_index) = loop {
+            // 1.0e-4 prevents shadow acne
+            let hit = match scene.hit_with_stats(&ray, 1.0e-4, Float::INFINITY, stats) {
+                Some(hit) => hit,
+                None => break (None, 1.0),
+            };
+            if let Some(medium) = media.current() {
+                if medium.scatters() && walk < MAX_WALK {
+                    let (distance, weight) = medium.sample_distance(hit.t, rng.gen(), rng.gen());
+                    throughput = throughput * weight;
+                    if let Some(distance) = distance {
+                        // Scatter evenly in all directions, and walk on
+                        walk += 1;
+                        let wi = uniform_sample_sphere(Point2f::new(rng.gen(), rng.gen()));
+                        ray = Ray::new(ray.eval(distance), wi);
+                        bsdf_pdf = Float::INFINITY;
+                        continue;
+                    }
+                } else {
+                    throughput = throughput * medium.transmittance(hit.t);
+                }
+            }
+            let interior = match hit.material.interior() {
+                Some(interior) => interior,
+                None => break (Some(hit), 1.0),
+            };
+            match media.outside_index(hit.material, interior, hit.front_face) {
+                Some(outside_index) => break (Some(hit), outside_index),
+                None => {
+                    // Go straight through
+                    if hit.front_face {
+                        media.enter(hit.material, interior);
+                    } else {
+                        media.exit(hit.material);
+                    }
+                    ray = Ray::new(hit.p, ray.direction());
+                }
+            }
+        };
+        let hit = match hit {
+            Some(hit) => hit,
+            None => {
+                let c = environment(&ray);
+                radiance += throughput * vec3(c.r, c.g, c.b);
+                break;
+            }
+        };
+
+        let emitted = hit.material.emitted(&ray, &hit);
+        if emitted != zero {
+            // Other lights with the same emission are missed by the ray
+            let light_pdf = lights
+                .iter()
+                .filter(|light| light.emits_at(&hit))
+                .map(|light| light.pdf_li(origin, ray.direction()))
+                .sum::<Float>()
+                / lights.len().max(1) as Float;
+            radiance += throughput * emitted * power_heuristic(bsdf_pdf, light_pdf);
         }
-        let mut attenuation = vec3(0.0, 0.0, 0.0);
-        if let Some(scattered) = hit.material.scatter(ray, &hit, &mut attenuation) {
-            let c = ray_color(scene, &scattered, limit - 1);
-            LinearColor::from_channels(
-                c.r * attenuation.x,
-                c.g * attenuation.y,
-                c.b * attenuation.z,
-                1.0,
-            )
-        } else {
-            Default::default()
+
+        if bounce == max_bounces {
+            break;
+        }
+        let bsdf = match hit.material.bsdf_between(&hit, outside_index) {
+            Some(bsdf) => bsdf,
+            None => break,
+        };
+        let wo = -ray.direction();
+
+        if !bsdf.flags().is_specular() && !lights.is_empty() {
+            let light = &lights[rng.gen_range(0, lights.len())];
+            let u = Point2f::new(rng.gen(), rng.gen());
+            if let Some(sample) = light.sample_li(hit.p, u) {
+                let f = bsdf.eval(wo, sample.wi) * bsdf.abs_cos(sample.wi);
+                if f != zero && sample.radiance != zero {
+                    let shadow_ray = Ray::new(hit.p, sample.wi);
+                    let t_max = sample.distance * (1.0 - 1.0e-4);
+                    if !scene.occluded_with_stats(&shadow_ray, 1.0e-4, t_max, stats) {
+                        let light_pdf = sample.pdf / lights.len() as Float;
+                        let weight = power_heuristic(light_pdf, bsdf.pdf(wo, sample.wi));
+                        radiance += throughput * f * sample.radiance * (weight / light_pdf);
+                    }
+                }
+            }
         }
-    } else {
-        let unit = ray.direction().normalized();
-        let t = (unit.y + 1.0) * 0.5;
 
-        let sun = unit.dot(vec3(-1.0, 1.0, 1.0).normalized());
+        let sample = match bsdf.sample(wo, rng.gen(), Point2f::new(rng.gen(), rng.gen())) {
+            Some(sample) => sample,
+            None => break,
+        };
+        throughput = throughput * sample.f * (bsdf.abs_cos(sample.wi) / sample.pdf);
+        if let Some(interior) = hit.material.interior() {
+            if sample.flags.contains(BxdfFlags::TRANSMISSION) {
+                if hit.front_face {
+                    media.enter(hit.material, interior);
+                } else {
+                    media.exit(hit.material);
+                }
+            }
+        }
+        bsdf_pdf = if sample.flags.contains(BxdfFlags::SPECULAR) {
+            Float::INFINITY
+        } else if sample.proportional {
+            bsdf.pdf(wo, sample.wi)
+        } else {
+            sample.pdf
+        };
+        origin = hit.p;
+        ray = Ray::new(hit.p, sample.wi);
+    }
 
-        // Sky
-        // lerp(t, LinearColor::from_channels(1.0, 1.0, 1.0, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
+    LinearColor::from_channels(radiance.x, radiance.y, radiance.z, 1.0)
+}
 
-        // Studio
-        // lerp(
-        //     t,
-        //     LinearColor::from_channels(0.0, 0.0, 0.0, 0.0),
-        //     LinearColor::from_channels(1.0, 1.0, 1.0, 0.0),
-        // )
+/// Light coming from far away: the sky, with the sun.
+fn environment(ray: &Ray) -> LinearColor {
+    let unit = ray.direction().normalized();
+    let t = (unit.y + 1.0) * 0.5;
 
-        if sun > 0.98 {
-            LinearColor::from_channels(100.0, 100.0, 85.0, 1.0)
-        } else {
-            // Sky
-            lerp(
-                t,
-                LinearColor::from_channels(1.0, 1.0, 1.0, 1.0),
-                LinearColor::from_channels(0.5, 0.7, 1.0, 1.0),
-            )
-        }
+    let sun = unit.dot(vec3(-1.0, 1.0, 1.0).normalized());
+
+    // Sky
+    // lerp(t, LinearColor::from_channels(1.0, 1.0, 1.0, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
+
+    // Studio
+    // lerp(
+    //     t,
+    //     LinearColor::from_channels(0.0, 0.0, 0.0, 0.0),
+    //     LinearColor::from_channels(1.0, 1.0, 1.0, 0.0),
+    // )
 
-        // lerp(t, LinearColor::from_channels(0.7, 0.2, 0.1, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
-        // lerp(t, LinearColor::from_channels(1.0, 1.0, 1.0, 1.0), LinearColor::from_channels(0.0, 0.0, 0.0, 1.0))
-        // lerp(t, LinearColor::from_channels(0.0, 0.0, 0.0, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
+    if sun > 0.98 {
+        LinearColor::from_channels(100.0, 100.0, 85.0, 1.0)
+    } else {
+        // Sky
+        lerp(
+            t,
+            LinearColor::from_channels(1.0, 1.0, 1.0, 1.0),
+            LinearColor::from_channels(0.5, 0.7, 1.0, 1.0),
+        )
     }
+
+    // lerp(t, LinearColor::from_channels(0.7, 0.2, 0.1, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
+    // lerp(t, LinearColor::from_channels(1.0, 1.0, 1.0, 1.0), LinearColor::from_channels(0.0, 0.0, 0.0, 1.0))
+    // lerp(t, LinearColor::from_channels(0.0, 0.0, 0.0, 1.0), LinearColor::from_channels(0.5, 0.7, 1.0, 1.0))
 }
 
 fn lerp<T>(t: Float, a: T, b: T) -> T
@@ -83,29 +216,32 @@ where
     a * (1.0 - t) + b * t
 }
 
-struct Scene<'a> {
-    objects: Vec<&'a dyn Hit>,
-}
-
-impl Hit for Scene<'_> {
-    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct> {
-        let mut t_max = t_max;
-        let mut hit_struct = None;
-        for obj in self.objects.iter() {
-            if let Some(hit) = obj.hit(ray, t_min, t_max) {
-                t_max = hit.t;
-                hit_struct = Some(hit);
-            }
-        }
-        hit_struct
+/// Everything there is to render, in an acceleration structure.
+type Scene<'a> = dyn Aggregate + 'a;
+
+/// Builds the acceleration structure chosen in the options.
+fn build_scene<'a>(objects: Vec<&'a dyn Primitive>, accelerator: Accelerator) -> Box<Scene<'a>> {
+    match accelerator {
+        Accelerator::Bvh => Box::new(Bvh::new(objects)),
+        Accelerator::Bvh4 => Box::new(Bvh4::new(objects)),
+        Accelerator::Bvh8 => Box::new(Bvh8::new(objects)),
+        Accelerator::KdTree => Box::new(KdTree::new(objects)),
+        Accelerator::Grid => Box::new(Grid::new(objects)),
     }
 }
 
-fn render(scene: &Scene, camera: &Camera, opt: RenderOptions) -> Vec<LinearColor> {
+/// Renders the scene, returns colors of pixels, and the work it took to
+/// trace the rays.
+fn render(
+    scene: &Scene,
+    lights: &[AreaLight],
+    camera: &Camera,
+    opt: RenderOptions,
+) -> (Vec<LinearColor>, TraversalStats) {
     use rand::prelude::*;
     use rayon::prelude::*;
 
-    let pixels = (0..opt.ny)
+    let rows: Vec<_> = (0..opt.ny)
         .into_par_iter()
         .rev()
         .map(|j| {
@@ -114,24 +250,37 @@ fn render(scene: &Scene, camera: &Camera, opt: RenderOptions) -> Vec<LinearColor
                 .map(|i| {
                     let mut rng = rand::thread_rng();
                     let mut color = LinearColor::default();
+                    let mut stats = TraversalStats::default();
                     for _ in 0..opt.ns {
                         let u = ((i as f32) + rng.gen::<f32>()) / (opt.nx as f32);
                         let v = ((j as f32) + rng.gen::<f32>()) / (opt.ny as f32);
                         let ray = camera.get_ray(u, v);
-                        color = color + ray_color(&scene, &ray, opt.n_max_bounce);
+                        color = color
+                            + ray_color(
+                                scene,
+                                lights,
+                                &ray,
+                                opt.n_max_bounce,
+                                &mut stats,
+                                &mut rng,
+                            );
                     }
                     color = color * (1.0 / opt.ns as f32);
 
-                    color
+                    (color, stats)
                 })
                 .collect();
 
             row
         })
-        .collect::<Vec<_>>()
-        .concat();
+        .collect();
 
-    pixels
+    let stats = rows
+        .iter()
+        .flatten()
+        .fold(TraversalStats::default(), |a, &(_, b)| a + b);
+    let colors = rows.concat().into_iter().map(|(c, _)| c).collect();
+    (colors, stats)
 }
 
 fn write_image(
@@ -141,7 +290,7 @@ fn write_image(
 ) -> Result<(), std::io::Error> {
     let path = Path::new(&filename);
     let file = File::create(path).unwrap();
-    let ref mut w = BufWriter::new(file);
+    let w = &mut BufWriter::new(file);
 
     let mut encoder = png::Encoder::new(w, nx as u32, ny as u32);
     encoder.set_color(png::ColorType::RGBA);
@@ -161,12 +310,38 @@ fn write_image(
     Ok(())
 }
 
+/// Acceleration structure to find hits with.
+#[derive(Copy, Clone, Debug)]
+enum Accelerator {
+    Bvh,
+    Bvh4,
+    Bvh8,
+    KdTree,
+    Grid,
+}
+
+impl std::str::FromStr for Accelerator {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Accelerator, String> {
+        match s {
+            "bvh" => Ok(Accelerator::Bvh),
+            "bvh4" => Ok(Accelerator::Bvh4),
+            "bvh8" => Ok(Accelerator::Bvh8),
+            "kdtree" => Ok(Accelerator::KdTree),
+            "grid" => Ok(Accelerator::Grid),
+            _ => Err(format!("unknown accelerator: {}", s)),
+        }
+    }
+}
+
 #[derive(Copy, Clone)]
 struct RenderOptions {
     nx: usize,
     ny: usize,
     ns: usize,
     n_max_bounce: usize,
+    accelerator: Accelerator,
 }
 
 impl RenderOptions {
@@ -185,6 +360,7 @@ impl RenderOptions {
             nx: arg(args.get(2), default.nx),
             ny: arg(args.get(3), default.ny),
             n_max_bounce: default.n_max_bounce,
+            accelerator: arg(args.get(4), default.accelerator),
         }
     }
 }
@@ -196,6 +372,7 @@ impl Default for RenderOptions {
             ny: 40 * 9,
             ns: 8,
             n_max_bounce: 50,
+            accelerator: Accelerator::Bvh,
         }
     }
 }
@@ -204,14 +381,11 @@ fn main() -> Result<(), std::io::Error> {
     let render_options = RenderOptions::parse();
 
     println!(
-        "Rendering {}x{} at {} samples per pixels",
-        render_options.nx, render_options.ny, render_options.ns
+        "Rendering {}x{} at {} samples per pixels, with {:?}",
+        render_options.nx, render_options.ny, render_options.ns, render_options.accelerator
     );
 
-    let mut scene = Scene {
-        objects: Vec::new(),
-    };
-
+    use pbrt::shape::quad::Quad;
     use pbrt::shape::sphere::Sphere;
     use pbrt::shape::triangle::Triangle;
 
@@ -231,10 +405,7 @@ fn main() -> Result<(), std::io::Error> {
     let s_pos_x = Sphere {
         center: Point3f::new(1.1, 0.0, 0.0),
         radius: 0.5,
-        material: &Metal {
-            albedo: vec3(0.8, 0.6, 0.2),
-            roughness: 0.3,
-        },
+        material: &Conductor::gold(0.3),
     };
     // Rubber
     let s_pos_y = Sphere {
@@ -248,9 +419,7 @@ fn main() -> Result<(), std::io::Error> {
     let s_pos_z = Sphere {
         center: Point3f::new(0.0, 0.0, 1.1),
         radius: 0.5,
-        material: &Dielectric {
-            refraction_index: 1.5,
-        },
+        material: &Dielectric::new(1.5),
     };
 
     let s_neg_x = Sphere {
@@ -262,14 +431,26 @@ fn main() -> Result<(), std::io::Error> {
         },
     };
 
+    // Wax
     let s_neg_z = Sphere {
         center: Point3f::new(0.0, 0.0, -1.1),
         radius: 0.5,
-        material: &Lambertian {
-            albedo: vec3(1.0, 1.0, 0.0),
-        },
+        material: &Subsurface::new(vec3(0.95, 0.85, 0.1), vec3(0.1, 0.08, 0.05), 1.45),
     };
 
+    // Panel light above, facing down
+    let lamp_emission = DiffuseLight {
+        radiance: vec3(8.0, 7.0, 6.0),
+        two_sided: false,
+    };
+    let lamp = Quad {
+        origin: Point3f::new(-0.5, 2.5, -0.5),
+        u: vec3(1.0, 0.0, 0.0),
+        v: vec3(0.0, 0.0, 1.0),
+        material: &lamp_emission,
+    };
+    let lights = vec![AreaLight::new(&lamp, &lamp_emission)];
+
     let s = 0.499;
     let mut vertices = Vec::with_capacity(8);
     for x in 0..=1 {
@@ -312,9 +493,7 @@ fn main() -> Result<(), std::io::Error> {
     //     albedo: vec3(0.9, 0.9, 0.9),
     //     roughness: 0.01,
     // };
-    let m = Dielectric {
-        refraction_index: 1.33333,
-    };
+    let m = Dielectric::new(1.33333);
     // let m = NullMaterial;
 
     let triangles: Vec<Triangle> = indices
@@ -322,17 +501,19 @@ fn main() -> Result<(), std::io::Error> {
         .map(|&(a, b, c)| Triangle {
             positions: [vertices[a], vertices[b], vertices[c]],
             material: &m,
+            backface_culling: false,
+            uvs: None,
+            tangents: None,
         })
         .collect();
 
-    scene.objects.push(&ground);
-    scene.objects.push(&s_pos_x);
-    scene.objects.push(&s_pos_y);
-    scene.objects.push(&s_pos_z);
-    scene.objects.push(&s_neg_x);
-    scene.objects.push(&s_neg_z);
+    let mut objects: Vec<&dyn Primitive> = vec![
+        &ground, &s_pos_x, &s_pos_y, &s_pos_z, &s_neg_x, &s_neg_z, &lamp,
+    ];
 
-    triangles.iter().for_each(|t| scene.objects.push(t));
+    triangles.iter().for_each(|t| objects.push(t));
+
+    let scene = build_scene(objects, render_options.accelerator);
 
     let camera = Camera::from_spec(CameraSpec {
         vfov: 60.0,
@@ -346,7 +527,8 @@ fn main() -> Result<(), std::io::Error> {
 
     let RenderOptions { nx, ny, .. } = render_options;
 
-    let colors = render(&scene, &camera, render_options);
+    let (colors, stats) = render(scene.as_ref(), &lights, &camera, render_options);
+    println!("Traced {}", stats);
 
     let mut pixels = tonemap(&colors, (nx, ny));
 
@@ -355,3 +537,161 @@ fn main() -> Result<(), std::io::Error> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use pbrt::hit::Hit;
+    use pbrt::sampling::concentric_sample_disk;
+    use pbrt::shape::quad::Quad;
+    use pbrt::shape::sphere::Sphere;
+    use rand::prelude::*;
+
+    /// Mean radiance of a sphere seen from outside, with light coming evenly
+    /// from all directions.
+    fn furnace(material: &dyn Material, samples: usize) -> Vec3f {
+        let furnace = DiffuseLight {
+            radiance: vec3(1.0, 1.0, 1.0),
+            two_sided: true,
+        };
+        let walls = Sphere {
+            center: Point3f::new(0.0, 0.0, 0.0),
+            radius: 100.0,
+            material: &furnace,
+        };
+        let sphere = Sphere {
+            center: Point3f::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material,
+        };
+        let scene = build_scene(vec![&walls, &sphere], Accelerator::Bvh);
+
+        let mut rng = StdRng::seed_from_u64(47);
+        let mut stats = TraversalStats::default();
+        let mut sum = Vec3f::default();
+        for _ in 0..samples {
+            // Towards the sphere, anywhere on it
+            let p = concentric_sample_disk(Point2f::new(rng.gen(), rng.gen()));
+            let ray = Ray::new(point3(p.x, p.y, 10.0), vec3(0.0, 0.0, -1.0));
+            let c = ray_color(scene.as_ref(), &[], &ray, 50, &mut stats, &mut rng);
+            sum += vec3(c.r, c.g, c.b);
+        }
+        sum * (samples as Float).recip()
+    }
+
+    #[test]
+    fn normal_mapped_looks_like_its_albedo() {
+        let lambertian = Lambertian {
+            albedo: vec3(1.0, 1.0, 1.0),
+        };
+        let mapped = NormalMap {
+            material: &lambertian,
+            normals: &vec3(0.7, 0.5, 0.9),
+        };
+        let sphere = Sphere {
+            center: Point3f::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: &mapped,
+        };
+
+        // Light reflected once, by the BSDF about the perturbed normal, as
+        // the sphere is convex
+        let mut rng = StdRng::seed_from_u64(53);
+        let mut albedo = 0.0;
+        let n = 4000;
+        for _ in 0..n {
+            let p = concentric_sample_disk(Point2f::new(rng.gen(), rng.gen()));
+            let ray = Ray::new(point3(p.x, p.y, 10.0), vec3(0.0, 0.0, -1.0));
+            let hit = sphere.hit(&ray, 0.0, Float::INFINITY).unwrap();
+            let bsdf = mapped.bsdf(&hit).unwrap();
+            let wi = uniform_sample_sphere(Point2f::new(rng.gen(), rng.gen()));
+            let f = bsdf.eval(-ray.direction(), wi) * bsdf.abs_cos(wi);
+            albedo += f.x * 4.0 * std::f32::consts::PI;
+        }
+        let albedo = albedo / n as Float;
+
+        let c = furnace(&mapped, 4000);
+        assert!((c.x - albedo).abs() < 0.02, "{:?} {}", c, albedo);
+    }
+
+    #[test]
+    fn subsurface_looks_like_its_albedo() {
+        // Index-matched, and much larger than the mean free path
+        let albedo = vec3(0.8, 0.5, 0.2);
+        let m = Subsurface::new(albedo, vec3(0.01, 0.01, 0.01), 1.0);
+        let c = furnace(&m, 4000);
+        assert!((c - albedo).len() < 0.04, "{:?}", c);
+
+        // Light is trapped inside by total internal reflection
+        let m = Subsurface::new(albedo, vec3(0.01, 0.01, 0.01), 1.5);
+        let trapped = furnace(&m, 4000);
+        assert!(trapped.x < c.x && trapped.y < c.y && trapped.z < c.z);
+
+        // And goes further into thinner ones, to come out again
+        let m = Subsurface::new(albedo, vec3(1.0, 1.0, 1.0), 1.0);
+        let thin = furnace(&m, 4000);
+        assert!(thin.x > c.x && thin.y > c.y && thin.z > c.z);
+    }
+
+    /// Renders a sphere under the lamp of the main scene.
+    fn render_sphere(material: &dyn Material, ns: usize) -> Vec<LinearColor> {
+        let sphere = Sphere {
+            center: Point3f::new(0.0, 0.0, 0.0),
+            radius: 0.5,
+            material,
+        };
+        let lamp_emission = DiffuseLight {
+            radiance: vec3(8.0, 7.0, 6.0),
+            two_sided: false,
+        };
+        let lamp = Quad {
+            origin: Point3f::new(-0.5, 2.5, -0.5),
+            u: vec3(1.0, 0.0, 0.0),
+            v: vec3(0.0, 0.0, 1.0),
+            material: &lamp_emission,
+        };
+        let lights = vec![AreaLight::new(&lamp, &lamp_emission)];
+        let scene = build_scene(vec![&sphere, &lamp], Accelerator::Bvh);
+
+        let opt = RenderOptions {
+            nx: 32,
+            ny: 32,
+            ns,
+            ..RenderOptions::default()
+        };
+        let camera = Camera::from_spec(CameraSpec {
+            vfov: 40.0,
+            aspect: 1.0,
+            look_from: Point3f::new(0.0, 1.0, 2.0),
+            look_at: Point3f::new(0.0, 0.0, 0.0),
+            up: vec3(0.0, 1.0, 0.0),
+        });
+        render(scene.as_ref(), &lights, &camera, opt).0
+    }
+
+    /// Long-running: `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn subsurface_matches_lambertian_reference() {
+        // As light goes less and less deep, and scatters many times, it's
+        // more and more like Lambertian reflection
+        let albedo = vec3(0.9, 0.85, 0.8);
+        let subsurface = render_sphere(
+            &Subsurface::new(albedo, vec3(0.002, 0.002, 0.002), 1.0),
+            1024,
+        );
+        let reference = render_sphere(&Lambertian { albedo }, 1024);
+
+        let total = |colors: &[LinearColor]| {
+            colors
+                .iter()
+                .fold(Vec3f::default(), |sum, c| sum + vec3(c.r, c.g, c.b))
+        };
+        let (s, r) = (total(&subsurface), total(&reference));
+        for i in 0..3 {
+            assert!((s[i] / r[i] - 1.0).abs() < 0.06, "{:?} {:?}", s, r);
+        }
+    }
+}
@@ -0,0 +1,416 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::HitStruct;
+
+/// Kinds of scattering a BxDF does. Flags are combined with `|`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct BxdfFlags(u8);
+
+impl BxdfFlags {
+    /// Scatters light back to the side it came from.
+    pub const REFLECTION: BxdfFlags = BxdfFlags(1);
+    /// Scatters light through the surface.
+    pub const TRANSMISSION: BxdfFlags = BxdfFlags(2);
+    /// Scatters light evenly-ish in all directions.
+    pub const DIFFUSE: BxdfFlags = BxdfFlags(4);
+    /// Scatters light around a preferred direction.
+    pub const GLOSSY: BxdfFlags = BxdfFlags(8);
+    /// Scatters light in a single direction. Such scattering can be
+    /// sampled, but not evaluated.
+    pub const SPECULAR: BxdfFlags = BxdfFlags(16);
+
+    /// No flags at all.
+    pub fn empty() -> BxdfFlags {
+        BxdfFlags(0)
+    }
+
+    /// True if all of the `other` flags are set.
+    ///
+    /// ```
+    /// use pbrt::bsdf::BxdfFlags;
+    ///
+    /// let flags = BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE;
+    /// assert!(flags.contains(BxdfFlags::DIFFUSE));
+    /// assert!(!flags.contains(BxdfFlags::DIFFUSE | BxdfFlags::TRANSMISSION));
+    /// ```
+    pub fn contains(self, other: BxdfFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// True if the only scattering there is is specular, so there's nothing
+    /// to evaluate, e.g. for light sampling.
+    pub fn is_specular(self) -> bool {
+        self.contains(BxdfFlags::SPECULAR)
+            && !self.contains(BxdfFlags::DIFFUSE)
+            && !self.contains(BxdfFlags::GLOSSY)
+    }
+}
+
+impl std::ops::BitOr for BxdfFlags {
+    type Output = BxdfFlags;
+
+    fn bitor(self, rhs: BxdfFlags) -> BxdfFlags {
+        BxdfFlags(self.0 | rhs.0)
+    }
+}
+
+/// Incident direction sampled from a BSDF.
+#[derive(Copy, Clone, Debug)]
+pub struct BsdfSample {
+    /// Unit direction light comes from
+    pub wi: Vec3f,
+    /// Value of the BSDF for the pair of directions
+    pub f: Vec3f,
+    /// Probability density of sampling `wi`, with respect to solid angle.
+    /// For specular scattering, it's the probability of choosing it, and
+    /// `f` is divided by `|cos θi|` so that `f |cos θi| / pdf` is still the
+    /// weight of the sample.
+    pub pdf: Float,
+    /// Kind of scattering that was sampled
+    pub flags: BxdfFlags,
+    /// True if `f` and `pdf` are only proportional to the value and density
+    /// of the BSDF, as for stochastic BSDFs: `f |cos θi| / pdf` is still the
+    /// weight of the sample, but the density has to be asked for with `pdf`.
+    pub proportional: bool,
+}
+
+/// Scattering function of a surface, in the local shading frame, where the
+/// normal is `+z`.
+///
+/// Both `wo`, the direction light leaves in, and `wi`, the direction it
+/// comes from, point away from the surface.
+pub trait Bxdf {
+    /// Kinds of scattering this BxDF does.
+    fn flags(&self) -> BxdfFlags;
+
+    /// Fraction of light coming from `wi` scattered towards `wo`, per unit
+    /// of solid angle. Zero for specular scattering.
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f;
+
+    /// Samples a direction light comes from, for uniform `uc` in `[0, 1)`
+    /// and `u` in `[0, 1)²`. `uc` is for choosing between lobes. `None` if
+    /// the sample is absorbed.
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample>;
+
+    /// Probability density of `sample` choosing `wi`, with respect to solid
+    /// angle. Zero for specular scattering.
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float;
+}
+
+impl<T: Bxdf + ?Sized> Bxdf for &T {
+    fn flags(&self) -> BxdfFlags {
+        (**self).flags()
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        (**self).eval(wo, wi)
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        (**self).sample(wo, uc, u)
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        (**self).pdf(wo, wi)
+    }
+}
+
+/// Scattering function at a point of a surface, in world space.
+///
+/// It's a BxDF placed in the shading frame of a hit. The normal of the frame
+/// faces the side the ray came from, so `wo.z` is positive in the BxDF, save
+/// for shading normals perturbed too far.
+pub struct Bsdf<'a> {
+    frame: Frame,
+    bxdf: Box<dyn Bxdf + 'a>,
+}
+
+impl<'a> Bsdf<'a> {
+    /// BSDF at the point of hit.
+    pub fn new(hit: &HitStruct, bxdf: impl Bxdf + 'a) -> Bsdf<'a> {
+        Bsdf {
+            frame: hit.shading_frame(),
+            bxdf: Box::new(bxdf),
+        }
+    }
+
+    /// The BxDF in the shading frame, for materials made of others.
+    pub fn into_bxdf(self) -> Box<dyn Bxdf + 'a> {
+        self.bxdf
+    }
+
+    /// The BxDF in another `frame`, for materials made of others whose
+    /// shading frames differ, e.g. rotated or with perturbed normals.
+    pub fn into_bxdf_in(self, frame: Frame) -> Box<dyn Bxdf + 'a> {
+        if self.frame == frame {
+            return self.bxdf;
+        }
+        Box::new(Reframed {
+            bxdf: self.bxdf,
+            outer: frame,
+            inner: self.frame,
+        })
+    }
+
+    /// `|cos θ|` of `w` to the shading normal: the one the BSDF is defined
+    /// with, perturbed or not.
+    pub fn abs_cos(&self, w: Vec3f) -> Float {
+        self.frame.n.dot(w).abs()
+    }
+
+    /// Kinds of scattering the BSDF does.
+    pub fn flags(&self) -> BxdfFlags {
+        self.bxdf.flags()
+    }
+
+    /// Fraction of light coming from `wi` scattered towards `wo`, per unit
+    /// of solid angle.
+    pub fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        let wo = self.frame.to_local(wo);
+        if wo.z == 0.0 {
+            return Vec3f::default();
+        }
+        self.bxdf.eval(wo, self.frame.to_local(wi))
+    }
+
+    /// Samples a direction light comes from, given the direction it leaves
+    /// in, for uniform `uc` in `[0, 1)` and `u` in `[0, 1)²`.
+    pub fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let wo = self.frame.to_local(wo);
+        if wo.z == 0.0 {
+            return None;
+        }
+        let sample = self.bxdf.sample(wo, uc, u)?;
+        if sample.pdf == 0.0 || sample.wi.z == 0.0 || sample.f == Vec3f::default() {
+            return None;
+        }
+        Some(BsdfSample {
+            wi: self.frame.to_world(sample.wi),
+            ..sample
+        })
+    }
+
+    /// Probability density of `sample` choosing `wi`, with respect to solid
+    /// angle.
+    pub fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let wo = self.frame.to_local(wo);
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.bxdf.pdf(wo, self.frame.to_local(wi))
+    }
+}
+
+/// BxDF of the `inner` frame, seen from the local space of the `outer` one.
+///
+/// Values are scaled by the ratio of the cosines of `wi` to either normal,
+/// so that `f |cos θi|` stays the same in the outer frame. Densities are
+/// those of directions, whatever the frame.
+struct Reframed<'a> {
+    bxdf: Box<dyn Bxdf + 'a>,
+    outer: Frame,
+    inner: Frame,
+}
+
+impl<'a> Reframed<'a> {
+    /// `w`, from the outer frame to the inner one.
+    fn inward(&self, w: Vec3f) -> Vec3f {
+        self.inner.to_local(self.outer.to_world(w))
+    }
+
+    /// Ratio of `|cos θi|` in the inner frame to that in the outer one.
+    fn cos_ratio(wi: Vec3f, inner_wi: Vec3f) -> Float {
+        if wi.z == 0.0 {
+            0.0
+        } else {
+            (inner_wi.z / wi.z).abs()
+        }
+    }
+}
+
+impl<'a> Bxdf for Reframed<'a> {
+    fn flags(&self) -> BxdfFlags {
+        self.bxdf.flags()
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        let (wo, inner_wi) = (self.inward(wo), self.inward(wi));
+        if wo.z == 0.0 {
+            return Vec3f::default();
+        }
+        self.bxdf.eval(wo, inner_wi) * Self::cos_ratio(wi, inner_wi)
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let wo = self.inward(wo);
+        if wo.z == 0.0 {
+            return None;
+        }
+        let sample = self.bxdf.sample(wo, uc, u)?;
+        let wi = self.outer.to_local(self.inner.to_world(sample.wi));
+        Some(BsdfSample {
+            wi,
+            f: sample.f * Self::cos_ratio(wi, sample.wi),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let wo = self.inward(wo);
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.bxdf.pdf(wo, self.inward(wi))
+    }
+}
+
+/// Mirror reflection of `w` around the normal `+z` of the local frame.
+pub fn reflect(w: Vec3f) -> Vec3f {
+    vec3(-w.x, -w.y, w.z)
+}
+
+/// Refraction of `w` through a surface with normal `n`, by Snell's law.
+///
+/// `eta` is the index of refraction on the side `n` doesn't point to,
+/// relative to the other side. Returns the refracted direction and the
+/// relative index it went through, or `None` on total internal reflection.
+pub fn refract(w: Vec3f, n: Vec3f, eta: Float) -> Option<(Vec3f, Float)> {
+    let cos_i = n.dot(w);
+    let (cos_i, n, eta) = if cos_i < 0.0 {
+        (-cos_i, -n, eta.recip())
+    } else {
+        (cos_i, n, eta)
+    };
+
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some((-w * eta.recip() + n * (cos_i / eta - cos_t), eta))
+}
+
+/// True if `w` and `wp` are on the same side of the surface.
+pub fn same_hemisphere(w: Vec3f, wp: Vec3f) -> bool {
+    w.z * wp.z > 0.0
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+
+    use super::*;
+
+    use rand::prelude::*;
+
+    /// Checks that a BxDF agrees with itself: sampled values and densities
+    /// match `eval` and `pdf`, densities integrate to at most one, and it
+    /// doesn't reflect more light than it gets. Returns the fraction of
+    /// light scattered towards `wo`, per channel.
+    pub(crate) fn check_bxdf(bxdf: &dyn Bxdf, wo: Vec3f) -> Vec3f {
+        let mut rng = StdRng::seed_from_u64(81);
+        let n = 20_000;
+        let mut albedo = Vec3f::default();
+        let mut pdf_integral = 0.0;
+        for _ in 0..n {
+            let u = Point2f::new(rng.gen(), rng.gen());
+            if let Some(s) = bxdf.sample(wo, rng.gen(), u) {
+                assert!((s.wi.len() - 1.0).abs() < 1.0e-3, "{:?}", s.wi);
+                albedo += s.f * (s.wi.z.abs() / s.pdf);
+                if !s.flags.contains(BxdfFlags::SPECULAR) && !s.proportional {
+                    let f = bxdf.eval(wo, s.wi);
+                    let pdf = bxdf.pdf(wo, s.wi);
+                    assert!(
+                        (f - s.f).len() <= 1.0e-3 * f.len().max(1.0),
+                        "{:?} {:?}",
+                        f,
+                        s
+                    );
+                    assert!(
+                        (pdf - s.pdf).abs() <= 1.0e-3 * pdf.max(1.0),
+                        "{} {:?}",
+                        pdf,
+                        s
+                    );
+                }
+            }
+
+            // Uniform directions over the sphere, to integrate the density
+            let z = 1.0 - 2.0 * rng.gen::<Float>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * std::f32::consts::PI * rng.gen::<Float>();
+            let wi = vec3(r * phi.cos(), r * phi.sin(), z);
+            pdf_integral += bxdf.pdf(wo, wi) * 4.0 * std::f32::consts::PI;
+        }
+        let albedo = albedo * (n as Float).recip();
+        assert!(pdf_integral / (n as Float) < 1.05, "{}", pdf_integral);
+        assert!(
+            albedo.x < 1.02 && albedo.y < 1.02 && albedo.z < 1.02,
+            "{:?}",
+            albedo
+        );
+        albedo
+    }
+
+    #[test]
+    fn bsdf_works_in_shading_frame() {
+        use crate::material::*;
+
+        let ray = Ray::new(point3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0));
+        let hit = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 1.0, 0.0),
+            &NullMaterial,
+        );
+        let lambertian = Lambertian {
+            albedo: vec3(0.5, 0.5, 0.5),
+        };
+        let bsdf = Bsdf::new(&hit, &lambertian);
+        assert_eq!(bsdf.flags(), BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE);
+
+        let wo = vec3(0.0, 1.0, 0.0);
+        let s = bsdf.sample(wo, 0.5, Point2f::new(0.3, 0.6)).unwrap();
+        assert!(s.wi.y > 0.0);
+        assert_eq!(bsdf.eval(wo, s.wi), s.f);
+        assert_eq!(bsdf.pdf(wo, s.wi), s.pdf);
+        assert_eq!(bsdf.eval(wo, vec3(0.0, -1.0, 0.0)), Vec3f::default());
+    }
+
+    #[test]
+    fn bxdf_keeps_its_frame_in_another() {
+        use crate::material::*;
+
+        let ray = Ray::new(point3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0));
+        let hit = |n: Vec3f| HitStruct::new(1.0, point3(0.0, 0.0, 0.0), &ray, n, &NullMaterial);
+        let (flat, tilted) = (
+            hit(vec3(0.0, 1.0, 0.0)),
+            hit(vec3(0.3, 1.0, 0.0).normalized()),
+        );
+        let lambertian = Lambertian {
+            albedo: vec3(0.5, 0.5, 0.5),
+        };
+        let frame = flat.shading_frame();
+        let bxdf = Bsdf::new(&tilted, &lambertian).into_bxdf_in(frame);
+        let bsdf = Bsdf::new(&tilted, &lambertian);
+
+        let wo = vec3(0.2, 1.0, 0.1).normalized();
+        for &wi in &[
+            vec3(-0.5, 0.5, 0.0),
+            vec3(0.0, 0.6, 0.8),
+            vec3(0.9, 0.1, 0.0),
+        ] {
+            let wi = wi.normalized();
+            let f = bxdf.eval(frame.to_local(wo), frame.to_local(wi)) * wi.y;
+            let expected = bsdf.eval(wo, wi) * wi.dot(tilted.shading.n);
+            assert!((f - expected).len() < 1.0e-5, "{:?} {:?}", f, expected);
+        }
+        // Samples weigh the same in either frame
+        let albedo = check_bxdf(&*bxdf, frame.to_local(wo));
+        assert!((albedo.x - 0.5).abs() < 1.0e-3, "{:?}", albedo);
+        // Same frames need no conversion
+        let same = Bsdf::new(&flat, &lambertian).into_bxdf_in(frame);
+        assert_eq!(same.eval(wo, wo), lambertian.eval(wo, wo));
+    }
+}
@@ -0,0 +1,296 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+
+use std::f32::consts::PI;
+
+/// Fraction of unpolarized light reflected by a conductor, for light
+/// arriving at an angle with cosine `cos_i` to the normal, per channel.
+///
+/// The conductor has a complex index of refraction `eta + i k`, relative to
+/// the medium the light comes from.
+///
+/// ```
+/// use pbrt::fresnel::fresnel_conductor;
+/// use pbrt::geo::*;
+///
+/// // Without absorption, a conductor is a dielectric: glass reflects 4%
+/// let r = fresnel_conductor(1.0, vec3(1.5, 1.5, 1.5), vec3(0.0, 0.0, 0.0));
+/// assert!((r.x - 0.04).abs() < 1.0e-6);
+///
+/// // Everything reflects everything at grazing angles
+/// let r = fresnel_conductor(0.0, vec3(0.2, 0.9, 1.1), vec3(3.9, 2.5, 2.1));
+/// assert!((r.x - 1.0).abs() < 1.0e-6);
+/// ```
+pub fn fresnel_conductor(cos_i: Float, eta: Vec3f, k: Vec3f) -> Vec3f {
+    vec3(
+        fresnel_complex(cos_i, eta.x, k.x),
+        fresnel_complex(cos_i, eta.y, k.y),
+        fresnel_complex(cos_i, eta.z, k.z),
+    )
+}
+
+/// `fresnel_conductor` for a single wavelength.
+fn fresnel_complex(cos_i: Float, eta: Float, k: Float) -> Float {
+    let cos_i = cos_i.abs().min(1.0);
+    let cos2 = cos_i * cos_i;
+    let sin2 = 1.0 - cos2;
+    let (eta2, k2) = (eta * eta, k * k);
+
+    let t0 = eta2 - k2 - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta2 * k2).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rs + rp)
+}
+
+/// Fraction of unpolarized light reflected by a dielectric, for light
+/// arriving at an angle with cosine `cos_i` to the normal.
+///
+/// `eta` is the index of refraction below the surface relative to the one
+/// above it. Negative `cos_i` means the light comes from below.
+///
+/// ```
+/// use pbrt::fresnel::fresnel_dielectric;
+///
+/// assert!((fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1.0e-6);
+/// assert!((fresnel_dielectric(-1.0, 1.5) - 0.04).abs() < 1.0e-6);
+///
+/// // Total internal reflection
+/// assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.0);
+/// ```
+pub fn fresnel_dielectric(cos_i: Float, eta: Float) -> Float {
+    let cos_i = cos_i.clamp(-1.0, 1.0);
+    let (cos_i, eta) = if cos_i < 0.0 {
+        (-cos_i, eta.recip())
+    } else {
+        (cos_i, eta)
+    };
+
+    // Snell's law
+    let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).max(0.0).sqrt();
+
+    let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    0.5 * (r_parallel * r_parallel + r_perpendicular * r_perpendicular)
+}
+
+/// Thin transparent film over a surface, like soap, oil or the coating of a
+/// lens. Light reflected at the top and the bottom of the film interferes,
+/// differently at different wavelengths: the surface is iridescent.
+///
+/// The film and the media around it are described relative to the medium
+/// above it, where light comes from.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ThinFilm {
+    /// Thickness of the film in nanometers, times the refraction index of
+    /// the medium above
+    pub thickness: Float,
+    /// Refraction index of the film relative to the medium above
+    pub eta: Float,
+}
+
+/// Wavelengths of the red, green and blue channels, in nanometers.
+const WAVELENGTHS: [Float; 3] = [650.0, 550.0, 450.0];
+
+impl ThinFilm {
+    /// Film `thickness` nanometers thick with a refraction index of
+    /// `refraction_index`, under a medium of refraction index `above`.
+    pub fn new(thickness: Float, refraction_index: Float, above: Float) -> ThinFilm {
+        ThinFilm {
+            thickness: thickness * above,
+            eta: refraction_index / above,
+        }
+    }
+
+    /// Fraction of unpolarized light reflected by the film over a surface of
+    /// complex index of refraction `eta + i k`, relative to the medium above,
+    /// for light arriving at an angle with cosine `cos_i` to the normal, per
+    /// channel. Reflections inside the film are summed after Airy.
+    ///
+    /// ```
+    /// use pbrt::fresnel::*;
+    /// use pbrt::geo::*;
+    ///
+    /// let glass = vec3(1.5, 1.5, 1.5);
+    /// let zero = Vec3f::default();
+    ///
+    /// // A quarter-wave coating of magnesium fluoride barely reflects green
+    /// let coating = ThinFilm::new(550.0 / (4.0 * 1.38), 1.38, 1.0);
+    /// let r = coating.reflectance(1.0, glass, zero);
+    /// assert!(r.y < 0.015 && r.y < r.x && r.y < r.z);
+    ///
+    /// // Infinitely thin films aren't there
+    /// let r = ThinFilm::new(0.0, 1.38, 1.0).reflectance(0.8, glass, zero);
+    /// assert!((r - fresnel_conductor(0.8, glass, zero)).len() < 1.0e-6);
+    /// ```
+    pub fn reflectance(&self, cos_i: Float, eta: Vec3f, k: Vec3f) -> Vec3f {
+        vec3(
+            self.airy(cos_i, WAVELENGTHS[0], Complex::new(eta.x, k.x)),
+            self.airy(cos_i, WAVELENGTHS[1], Complex::new(eta.y, k.y)),
+            self.airy(cos_i, WAVELENGTHS[2], Complex::new(eta.z, k.z)),
+        )
+    }
+
+    /// `reflectance` at a single wavelength, over a surface of index `n3`.
+    fn airy(&self, cos_i: Float, wavelength: Float, n3: Complex) -> Float {
+        let cos1 = Complex::real(cos_i.abs().min(1.0));
+        let sin2 = Complex::real(1.0 - cos_i * cos_i);
+        let n2 = Complex::real(self.eta);
+
+        // Snell's law, for the cosines in the film and below it
+        let cos2 = (Complex::real(1.0) - sin2 / (n2 * n2)).sqrt();
+        let cos3 = (Complex::real(1.0) - sin2 / (n3 * n3)).sqrt();
+
+        // Amplitudes reflected at the top and the bottom of the film
+        let rs12 = (cos1 - n2 * cos2) / (cos1 + n2 * cos2);
+        let rp12 = (n2 * cos1 - cos2) / (n2 * cos1 + cos2);
+        let rs23 = (n2 * cos2 - n3 * cos3) / (n2 * cos2 + n3 * cos3);
+        let rp23 = (n3 * cos2 - n2 * cos3) / (n3 * cos2 + n2 * cos3);
+
+        // Phase shift of a round trip through the film
+        let delta = n2 * cos2 * Complex::real(4.0 * PI * self.thickness / wavelength);
+        let round_trip = (Complex::new(0.0, 1.0) * delta).exp();
+
+        let airy = |r12: Complex, r23: Complex| {
+            ((r12 + r23 * round_trip) / (Complex::real(1.0) + r12 * r23 * round_trip)).norm2()
+        };
+        (0.5 * (airy(rs12, rs23) + airy(rp12, rp23))).min(1.0)
+    }
+}
+
+/// Just enough complex numbers for `ThinFilm`.
+#[derive(Copy, Clone, Debug)]
+struct Complex {
+    re: Float,
+    im: Float,
+}
+
+impl Complex {
+    fn new(re: Float, im: Float) -> Complex {
+        Complex { re, im }
+    }
+
+    fn real(re: Float) -> Complex {
+        Complex::new(re, 0.0)
+    }
+
+    /// Squared magnitude.
+    fn norm2(self) -> Float {
+        self.re * self.re + self.im * self.im
+    }
+
+    /// Principal square root, with a non-negative real part.
+    fn sqrt(self) -> Complex {
+        let n = self.norm2().sqrt();
+        if n == 0.0 {
+            return Complex::real(0.0);
+        }
+        let re = (0.5 * (n + self.re)).max(0.0).sqrt();
+        let im = (0.5 * (n - self.re)).max(0.0).sqrt();
+        Complex::new(re, if self.im < 0.0 { -im } else { im })
+    }
+
+    fn exp(self) -> Complex {
+        let (sin, cos) = self.im.sin_cos();
+        let r = self.re.exp();
+        Complex::new(r * cos, r * sin)
+    }
+}
+
+impl std::ops::Add for Complex {
+    type Output = Complex;
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl std::ops::Sub for Complex {
+    type Output = Complex;
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl std::ops::Mul for Complex {
+    type Output = Complex;
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+impl std::ops::Div for Complex {
+    type Output = Complex;
+    fn div(self, other: Complex) -> Complex {
+        let d = other.norm2();
+        Complex::new(
+            (self.re * other.re + self.im * other.im) / d,
+            (self.im * other.re - self.re * other.im) / d,
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn thin_film_without_contrast_isnt_there() {
+        let zero = Vec3f::default();
+        for &cos_i in &[1.0, 0.7, 0.3] {
+            // On gold, and on glass
+            for &(eta, k) in &[
+                (vec3(0.143, 0.374, 1.442), vec3(3.983, 2.386, 1.603)),
+                (vec3(1.5, 1.5, 1.5), zero),
+            ] {
+                let bare = fresnel_conductor(cos_i, eta, k);
+                for &thickness in &[0.0, 120.0, 480.0] {
+                    let film = ThinFilm::new(thickness, 1.0, 1.0);
+                    let r = film.reflectance(cos_i, eta, k);
+                    assert!((r - bare).len() < 1.0e-5, "{:?} {:?}", r, bare);
+                }
+                let r = ThinFilm::new(0.0, 1.33, 1.0).reflectance(cos_i, eta, k);
+                assert!((r - bare).len() < 1.0e-5, "{:?} {:?}", r, bare);
+            }
+        }
+    }
+
+    #[test]
+    fn soap_bubble_is_iridescent() {
+        // Soap film in the air: the film over air, seen from air
+        let air = vec3(1.0, 1.0, 1.0);
+        let zero = Vec3f::default();
+        let mut colors = Vec::new();
+        for thickness in (100..800).step_by(50) {
+            let film = ThinFilm::new(thickness as Float, 1.33, 1.0);
+            let r = film.reflectance(1.0, air, zero);
+            // At most 4 times a single surface's reflection, and no more
+            // than everything
+            assert!(r.max_component() < 0.09, "{} {:?}", thickness, r);
+            colors.push(r);
+        }
+        // The brightest channel changes with the thickness
+        let brightest: std::collections::HashSet<_> =
+            colors.iter().map(|r| r.max_dimension()).collect();
+        assert_eq!(brightest.len(), 3);
+
+        // And the film is invisible when much thinner than the wavelength
+        let r = ThinFilm::new(1.0, 1.33, 1.0).reflectance(1.0, air, zero);
+        assert!(r.max_component() < 1.0e-3);
+    }
+}
@@ -1,5 +1,6 @@
 pub mod bounds2;
 pub mod bounds3;
+pub mod frame;
 pub mod mat4;
 pub mod point2;
 pub mod point3;
@@ -9,7 +10,7 @@ pub mod vec2;
 pub mod vec3;
 
 pub use bounds2::{Bounds2, Bounds2f};
-pub use bounds3::{Bounds3, Bounds3f};
+pub use bounds3::{Bounds3, Bounds3f, WideBounds3};
 
 pub use point2::{Point2, Point2f};
 pub use point3::{Point3, Point3f};
@@ -17,10 +18,14 @@ pub use point3::{Point3, Point3f};
 pub use vec2::{Vec2, Vec2f};
 pub use vec3::{Vec3, Vec3f};
 
+pub use frame::Frame;
+
 pub use mat4::Mat4;
 
 pub use ray::Ray;
 
+pub use transform::Transform;
+
 use crate::num_traits::*;
 
 /// Creates a new 3D vector.
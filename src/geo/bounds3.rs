@@ -74,6 +74,28 @@ where
         Bounds3 { min, max }
     }
 
+    /// Construct an AABB of points inside both given AABBs. If there are no
+    /// such points, the result is empty, with some `min` coordinates above
+    /// `max` ones.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b1 = Bounds3::from_corners(point3(0.0, 0.0, 0.0), point3(2.0, 2.0, 2.0));
+    /// let b2 = Bounds3::from_corners(point3(1.0, -1.0, 1.0), point3(3.0, 1.0, 3.0));
+    ///
+    /// let i = Bounds3::intersect(&b1, &b2);
+    /// assert_eq!(i.min, point3(1.0, 0.0, 1.0));
+    /// assert_eq!(i.max, point3(2.0, 1.0, 2.0));
+    /// ```
+    pub fn intersect(b1: &Bounds3<T>, b2: &Bounds3<T>) -> Bounds3<T>
+    where
+        T: PartialOrd,
+    {
+        let (min, max) = (b1.min.max(b2.min), b1.max.min(b2.max));
+        Bounds3 { min, max }
+    }
+
     /// Surface area of the bounding box.
     ///
     /// ```
@@ -105,8 +127,120 @@ where
 }
 
 impl Bounds3f {
+    /// Constructs empty bounds, which contain no points at all. A union with
+    /// empty bounds leaves the other bounds intact.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_point(point3(1.0, 2.0, 3.0));
+    /// let u = Bounds3::union(&Bounds3f::empty(), &b);
+    ///
+    /// assert_eq!(u.min, b.min);
+    /// assert_eq!(u.max, b.max);
+    /// ```
+    pub fn empty() -> Bounds3f {
+        Bounds3 {
+            min: Point3::new(Float::INFINITY, Float::INFINITY, Float::INFINITY),
+            max: Point3::new(-Float::INFINITY, -Float::INFINITY, -Float::INFINITY),
+        }
+    }
+
+    /// True if the bounds contain no points. Flat bounds aren't empty.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert!(Bounds3f::empty().is_empty());
+    /// assert!(!Bounds3::from_point(point3(1.0, 2.0, 3.0)).is_empty());
+    /// ```
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Construct an AABB enclosing a given AABB and a point.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_point(point3(-1.0, 2.0, 3.0));
+    ///
+    /// let u = Bounds3::union_point(&b, point3(2.0, -4.0, 2.0));
+    /// assert_eq!(u.min, point3(-1.0, -4.0, 2.0));
+    /// assert_eq!(u.max, point3(2.0, 2.0, 3.0));
+    /// ```
+    pub fn union_point(b: &Bounds3f, p: Point3f) -> Bounds3f {
+        Bounds3 {
+            min: b.min.min(p),
+            max: b.max.max(p),
+        }
+    }
+
+    /// Index of the longest axis of the box.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_corners(point3(1.0, -1.0, 3.0), point3(2.0, 1.0, 0.0));
+    /// assert_eq!(b.maximum_extent(), 2);
+    /// ```
+    pub fn maximum_extent(&self) -> usize {
+        self.diagonal().max_dimension()
+    }
+
+    /// Position of a point relative to the corners of the box: `(0, 0, 0)`
+    /// at the min corner, and `(1, 1, 1)` at the max corner.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_corners(point3(0.0, 0.0, 0.0), point3(2.0, 4.0, 8.0));
+    /// assert_eq!(b.offset(point3(1.0, 1.0, 2.0)), vec3(0.5, 0.25, 0.25));
+    /// ```
+    pub fn offset(&self, p: Point3f) -> Vec3f {
+        let o = p - self.min;
+        let d = self.diagonal();
+        let f = |o: Float, d: Float| if d > 0.0 { o / d } else { o };
+        vec3(f(o.x, d.x), f(o.y, d.y), f(o.z, d.z))
+    }
+
     /// Quickly finds if the ray hits the AABB, in a given time interval.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// // Flat boxes are hit, too
+    /// let b = Bounds3::from_corners(point3(-1.0, 0.0, -1.0), point3(1.0, 0.0, 1.0));
+    /// let r = Ray::new(point3(0.5, 1.0, 0.0), vec3(0.0, -1.0, 0.0));
+    ///
+    /// assert!(b.hit(&r, (0.0, 10.0)));
+    /// assert!(!b.hit(&r, (0.0, 0.5)));
+    /// assert!(!b.hit(&Ray::new(point3(2.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0)), (0.0, 10.0)));
+    /// ```
     pub fn hit(&self, ray: &Ray, (t_min, t_max): (Float, Float)) -> bool {
+        hit_naive(self, ray, (t_min, t_max)).is_some()
+    }
+
+    /// Finds the part of a given time interval the ray spends inside of the
+    /// AABB, if any.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_corners(point3(-1.0, -1.0, -1.0), point3(1.0, 1.0, 1.0));
+    /// let r = Ray::new(point3(-3.0, 0.0, 0.0), vec3(1.0, 0.0, 0.0));
+    ///
+    /// let (t0, t1) = b.hit_interval(&r, (0.0, 10.0)).unwrap();
+    /// assert_eq!(t0, 2.0);
+    /// assert!((t1 - 4.0).abs() < 1.0e-5);
+    /// assert_eq!(b.hit_interval(&r, (3.0, 3.5)), Some((3.0, 3.5)));
+    /// assert_eq!(b.hit_interval(&r, (0.0, 1.0)), None);
+    /// ```
+    pub fn hit_interval(
+        &self,
+        ray: &Ray,
+        (t_min, t_max): (Float, Float),
+    ) -> Option<(Float, Float)> {
         hit_naive(self, ray, (t_min, t_max))
     }
 
@@ -123,7 +257,7 @@ impl Bounds3f {
     }
 }
 
-fn hit_naive(b: &Bounds3f, r: &Ray, (t_min, t_max): (Float, Float)) -> bool {
+fn hit_naive(b: &Bounds3f, r: &Ray, (t_min, t_max): (Float, Float)) -> Option<(Float, Float)> {
     // for (int a = 0; a < 3; a++) {
     //     auto t0 = ffmin((_min[a] - r.origin()[a]) / r.direction()[a],
     //                     (_max[a] - r.origin()[a]) / r.direction()[a]);
@@ -140,33 +274,124 @@ fn hit_naive(b: &Bounds3f, r: &Ray, (t_min, t_max): (Float, Float)) -> bool {
 
     let (o, d) = r.origin_and_direction();
 
+    // Flat boxes (e.g. around axis-aligned triangles) have `t0 == t1`, so
+    // intervals are closed. `t1` is enlarged slightly to stay conservative
+    // in the face of rounding errors.
     let inv = d.x.recip();
     let (u, v) = ((b.min.x - o.x) * inv, (b.max.x - o.x) * inv);
     let (t0, t1) = min_max(u, v);
-    let (t_min, t_max) = (max(t0, t_min), min(t1, t_max));
-    if t_max <= t_min {
-        return false;
+    let (t_min, t_max) = (max(t0, t_min), min(t1 * ROUNDING, t_max));
+    if t_max < t_min {
+        return None;
     }
 
     let inv = d.y.recip();
     let (u, v) = ((b.min.y - o.y) * inv, (b.max.y - o.y) * inv);
     let (t0, t1) = min_max(u, v);
-    let (t_min, t_max) = (max(t0, t_min), min(t1, t_max));
-    if t_max <= t_min {
-        return false;
+    let (t_min, t_max) = (max(t0, t_min), min(t1 * ROUNDING, t_max));
+    if t_max < t_min {
+        return None;
     }
 
     let inv = d.z.recip();
     let (u, v) = ((b.min.z - o.z) * inv, (b.max.z - o.z) * inv);
     let (t0, t1) = min_max(u, v);
-    let (t_min, t_max) = (max(t0, t_min), min(t1, t_max));
-    if t_max <= t_min {
-        return false;
+    let (t_min, t_max) = (max(t0, t_min), min(t1 * ROUNDING, t_max));
+    if t_max < t_min {
+        return None;
     }
 
-    true
+    Some((t_min, t_max))
+}
+
+/// `N` bounding boxes in structure-of-arrays layout, so that a ray can be
+/// tested against all of them at once.
+///
+/// The slab test is the same as in `Bounds3::hit`, only done lane by lane,
+/// without branches, which lets the compiler vectorize it.
+#[derive(Copy, Clone, Debug)]
+pub struct WideBounds3<const N: usize> {
+    /// Min corners, by axis, then by box
+    pub min: [[Float; N]; 3],
+    /// Max corners, by axis, then by box
+    pub max: [[Float; N]; 3],
 }
 
+impl<const N: usize> WideBounds3<N> {
+    /// No boxes: all of them are pushed to infinity, where no ray can hit
+    /// them.
+    pub fn empty() -> WideBounds3<N> {
+        WideBounds3 {
+            min: [[Float::INFINITY; N]; 3],
+            max: [[Float::INFINITY; N]; 3],
+        }
+    }
+
+    /// The `i`th box.
+    pub fn get(&self, i: usize) -> Bounds3f {
+        Bounds3 {
+            min: point3(self.min[0][i], self.min[1][i], self.min[2][i]),
+            max: point3(self.max[0][i], self.max[1][i], self.max[2][i]),
+        }
+    }
+
+    pub fn set(&mut self, i: usize, b: &Bounds3f) {
+        for axis in 0..3 {
+            self.min[axis][i] = b.min[axis];
+            self.max[axis][i] = b.max[axis];
+        }
+    }
+
+    /// Finds where the ray enters each of the boxes, in a given time
+    /// interval. Boxes the ray misses get `Float::INFINITY`.
+    ///
+    /// The ray is given as its origin and reciprocal direction, which are
+    /// better computed once per ray than once per node.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let mut b = WideBounds3::<4>::empty();
+    /// b.set(0, &Bounds3::from_corners(point3(2.0, -1.0, -1.0), point3(3.0, 1.0, 1.0)));
+    /// b.set(1, &Bounds3::from_corners(point3(5.0, -1.0, -1.0), point3(6.0, 1.0, 1.0)));
+    /// b.set(2, &Bounds3::from_corners(point3(5.0, 2.0, -1.0), point3(6.0, 3.0, 1.0)));
+    ///
+    /// let t = b.hit(point3(0.0, 0.0, 0.0), vec3(1.0, f32::INFINITY, f32::INFINITY), (0.0, 10.0));
+    /// assert_eq!(t, [2.0, 5.0, f32::INFINITY, f32::INFINITY]);
+    /// ```
+    #[inline]
+    pub fn hit(
+        &self,
+        origin: Point3f,
+        inv_direction: Vec3f,
+        (t_min, t_max): (Float, Float),
+    ) -> [Float; N] {
+        let mut t0 = [t_min; N];
+        let mut t1 = [t_max; N];
+        for axis in 0..3 {
+            let (o, inv) = (origin[axis], inv_direction[axis]);
+            for i in 0..N {
+                let u = (self.min[axis][i] - o) * inv;
+                let v = (self.max[axis][i] - o) * inv;
+                let (near, far) = min_max(u, v);
+                t0[i] = max(near, t0[i]);
+                t1[i] = min(far * ROUNDING, t1[i]);
+            }
+        }
+        let mut result = [Float::INFINITY; N];
+        for i in 0..N {
+            if t0[i] <= t1[i] {
+                result[i] = t0[i];
+            }
+        }
+        result
+    }
+}
+
+/// `1 + 2 * gamma(3)`, see PBR book, section 3.9.2.
+const ROUNDING: Float =
+    1.0 + 2.0 * (3.0 * 0.5 * Float::EPSILON) / (1.0 - 3.0 * 0.5 * Float::EPSILON);
+
 #[inline]
 fn min_max(u: Float, v: Float) -> (Float, Float) {
     if u < v {
@@ -0,0 +1,92 @@
+use crate::geo::vec3::{Vec3, Vec3f};
+use crate::num_traits::Float;
+
+/// Orthonormal frame: three mutually perpendicular unit vectors.
+///
+/// Converts directions between world space and the local space of the frame,
+/// where `s`, `t` and `n` are the x, y and z axes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub s: Vec3f,
+    pub t: Vec3f,
+    pub n: Vec3f,
+}
+
+impl Frame {
+    /// Constructs an arbitrary frame around a unit normal `n`.
+    ///
+    /// Uses the branchless construction from Duff et al., "Building an
+    /// Orthonormal Basis, Revisited" (JCGT, 2017).
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let n = vec3(1.0, 2.0, 3.0).normalized();
+    /// let f = Frame::from_normal(n);
+    ///
+    /// assert!(f.s.dot(n).abs() < 1.0e-6);
+    /// assert!(f.t.dot(n).abs() < 1.0e-6);
+    /// assert!(f.s.dot(f.t).abs() < 1.0e-6);
+    /// ```
+    pub fn from_normal(n: Vec3f) -> Frame {
+        let sign = (1.0 as Float).copysign(n.z);
+        let a = -(sign + n.z).recip();
+        let b = n.x * n.y * a;
+        let s = Vec3::new(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+        let t = Vec3::new(b, sign + n.y * n.y * a, -n.y);
+        Frame { s, t, n }
+    }
+
+    /// Constructs a frame around a unit normal `n`, with `s` aligned with
+    /// the tangent `dpdu` as closely as possible.
+    ///
+    /// Falls back to an arbitrary frame if `dpdu` is degenerate or parallel
+    /// to `n`.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let f = Frame::from_normal_and_tangent(vec3(0.0, 0.0, 1.0), vec3(2.0, 0.0, 1.0));
+    ///
+    /// assert_eq!(f.s, vec3(1.0, 0.0, 0.0));
+    /// assert_eq!(f.t, vec3(0.0, 1.0, 0.0));
+    /// ```
+    pub fn from_normal_and_tangent(n: Vec3f, dpdu: Vec3f) -> Frame {
+        let s = dpdu - n * n.dot(dpdu);
+        let len_squared = s.len_squared();
+        if len_squared < 1.0e-12 {
+            return Frame::from_normal(n);
+        }
+        let s = s * len_squared.sqrt().recip();
+        let t = n.cross(&s);
+        Frame { s, t, n }
+    }
+
+    /// Transforms a world-space direction into the local space of the frame.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let f = Frame::from_normal(vec3(0.0, 1.0, 0.0));
+    ///
+    /// assert_eq!(f.to_local(vec3(0.0, 1.0, 0.0)).z, 1.0);
+    /// ```
+    pub fn to_local(&self, v: Vec3f) -> Vec3f {
+        Vec3::new(v.dot(self.s), v.dot(self.t), v.dot(self.n))
+    }
+
+    /// Transforms a direction from the local space of the frame into world
+    /// space.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let f = Frame::from_normal(vec3(1.0, 2.0, 3.0).normalized());
+    /// let v = vec3(0.5, -1.0, 2.0);
+    ///
+    /// assert!((f.to_world(f.to_local(v)) - v).len() < 1.0e-6);
+    /// ```
+    pub fn to_world(&self, v: Vec3f) -> Vec3f {
+        self.s * v.x + self.t * v.y + self.n * v.z
+    }
+}
@@ -10,12 +10,19 @@ use crate::num_traits::Float;
 ///
 /// assert_eq!(m[(0, 0)], 1.0);
 /// ```
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct Mat4 {
     m: [[Float; 4]; 4],
 }
 
 impl Mat4 {
+    ///
+    /// Creates a matrix from its rows.
+    ///
+    pub fn new(m: [[Float; 4]; 4]) -> Mat4 {
+        Mat4 { m }
+    }
+
     ///
     /// Creates an identity matrix.
     ///
@@ -28,6 +35,86 @@ impl Mat4 {
         ];
         Mat4 { m }
     }
+
+    ///
+    /// Swaps rows and columns.
+    ///
+    /// ```
+    /// use pbrt::geo::Mat4;
+    ///
+    /// let mut rows = [[0.0; 4]; 4];
+    /// rows[0][3] = 5.0;
+    /// let m = Mat4::new(rows).transpose();
+    ///
+    /// assert_eq!(m[(3, 0)], 5.0);
+    /// assert_eq!(m[(0, 3)], 0.0);
+    /// ```
+    pub fn transpose(&self) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, x) in row.iter_mut().enumerate() {
+                *x = self.m[c][r];
+            }
+        }
+        Mat4 { m }
+    }
+
+    ///
+    /// Inverse matrix, found with Gauss-Jordan elimination. Returns `None`
+    /// if the matrix is singular.
+    ///
+    /// ```
+    /// use pbrt::geo::Mat4;
+    ///
+    /// let m = Mat4::new([
+    ///     [2.0, 0.0, 0.0, 1.0],
+    ///     [0.0, 4.0, 0.0, 2.0],
+    ///     [0.0, 0.0, 8.0, 3.0],
+    ///     [0.0, 0.0, 0.0, 1.0],
+    /// ]);
+    ///
+    /// assert_eq!(m * m.inverse().unwrap(), Mat4::identity());
+    /// assert!(Mat4::new([[0.0; 4]; 4]).inverse().is_none());
+    /// ```
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for c in 0..4 {
+            // Partial pivoting: pick the row with the largest magnitude
+            let pivot = (c..4)
+                .max_by(|&i, &j| {
+                    a[i][c]
+                        .abs()
+                        .partial_cmp(&a[j][c].abs())
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap_or(c);
+            if a[pivot][c] == 0.0 {
+                return None;
+            }
+            a.swap(c, pivot);
+            inv.swap(c, pivot);
+
+            let recip = a[c][c].recip();
+            for k in 0..4 {
+                a[c][k] *= recip;
+                inv[c][k] *= recip;
+            }
+
+            for r in 0..4 {
+                if r != c {
+                    let f = a[r][c];
+                    for k in 0..4 {
+                        a[r][k] -= f * a[c][k];
+                        inv[r][k] -= f * inv[c][k];
+                    }
+                }
+            }
+        }
+
+        Some(Mat4 { m: inv })
+    }
 }
 
 impl std::ops::Index<(usize, usize)> for Mat4 {
@@ -36,3 +123,33 @@ impl std::ops::Index<(usize, usize)> for Mat4 {
         &self.m[r][c]
     }
 }
+
+///
+/// Matrix multiplication.
+///
+impl std::ops::Mul for Mat4 {
+    type Output = Mat4;
+
+    /// ```
+    /// use pbrt::geo::Mat4;
+    ///
+    /// let m = Mat4::new([
+    ///     [1.0, 2.0, 0.0, 0.0],
+    ///     [0.0, 1.0, 0.0, 0.0],
+    ///     [0.0, 0.0, 1.0, 0.0],
+    ///     [0.0, 0.0, 0.0, 1.0],
+    /// ]);
+    ///
+    /// assert_eq!((m * m)[(0, 1)], 4.0);
+    /// assert_eq!(m * Mat4::identity(), m);
+    /// ```
+    fn mul(self, rhs: Mat4) -> Mat4 {
+        let mut m = [[0.0; 4]; 4];
+        for (r, row) in m.iter_mut().enumerate() {
+            for (c, x) in row.iter_mut().enumerate() {
+                *x = (0..4).map(|k| self.m[r][k] * rhs.m[k][c]).sum();
+            }
+        }
+        Mat4 { m }
+    }
+}
@@ -72,6 +72,28 @@ impl<T> Point3<T> {
     }
 }
 
+///
+/// Allows indexing into a point by the number of the dimension.
+///
+impl<T> std::ops::Index<usize> for Point3<T> {
+    type Output = T;
+
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let p = point3(1.0, 2.0, 3.0);
+    /// assert_eq!(p[1], 2.0);
+    /// ```
+    fn index(&self, dim: usize) -> &Self::Output {
+        match dim {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Point3 index out of bounds: {}", dim),
+        }
+    }
+}
+
 impl Point3f {
     pub fn lerp(t: Float, p1: Point3f, p2: Point3f) -> Point3f {
         lerp(t, p1, p2)
@@ -1 +1,266 @@
-pub struct Transform {}
+use crate::geo::*;
+use crate::hit::{HitStruct, Shading};
+use crate::num_traits::Float;
+
+/// Affine transformation of space. Keeps the inverse matrix, too, to
+/// transform normals and to undo itself.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    m: Mat4,
+    m_inv: Mat4,
+}
+
+impl Transform {
+    /// Constructs a transform from a matrix. Returns `None` if the matrix
+    /// can't be inverted.
+    pub fn from_matrix(m: Mat4) -> Option<Transform> {
+        m.inverse().map(|m_inv| Transform { m, m_inv })
+    }
+
+    /// Transformation which does nothing.
+    pub fn identity() -> Transform {
+        Transform {
+            m: Mat4::identity(),
+            m_inv: Mat4::identity(),
+        }
+    }
+
+    /// Translation by a vector.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let t = Transform::translate(vec3(1.0, 2.0, 3.0));
+    ///
+    /// assert_eq!(t.transform_point(point3(1.0, 1.0, 1.0)), point3(2.0, 3.0, 4.0));
+    /// assert_eq!(t.transform_vector(vec3(1.0, 1.0, 1.0)), vec3(1.0, 1.0, 1.0));
+    /// ```
+    pub fn translate(d: Vec3f) -> Transform {
+        let m = Mat4::new([
+            [1.0, 0.0, 0.0, d.x],
+            [0.0, 1.0, 0.0, d.y],
+            [0.0, 0.0, 1.0, d.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let m_inv = Mat4::new([
+            [1.0, 0.0, 0.0, -d.x],
+            [0.0, 1.0, 0.0, -d.y],
+            [0.0, 0.0, 1.0, -d.z],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform { m, m_inv }
+    }
+
+    /// Non-uniform scaling along the axes.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let t = Transform::scale(vec3(2.0, 1.0, 0.5));
+    ///
+    /// assert_eq!(t.transform_point(point3(1.0, 1.0, 1.0)), point3(2.0, 1.0, 0.5));
+    /// assert_eq!(t.inverse().transform_point(point3(2.0, 1.0, 0.5)), point3(1.0, 1.0, 1.0));
+    /// ```
+    pub fn scale(s: Vec3f) -> Transform {
+        let m = Mat4::new([
+            [s.x, 0.0, 0.0, 0.0],
+            [0.0, s.y, 0.0, 0.0],
+            [0.0, 0.0, s.z, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        let m_inv = Mat4::new([
+            [s.x.recip(), 0.0, 0.0, 0.0],
+            [0.0, s.y.recip(), 0.0, 0.0],
+            [0.0, 0.0, s.z.recip(), 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        Transform { m, m_inv }
+    }
+
+    /// Rotation by `degrees` around an arbitrary `axis`, counter-clockwise
+    /// when looking from the tip of the axis.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let t = Transform::rotate(90.0, vec3(0.0, 1.0, 0.0));
+    /// let p = t.transform_point(point3(1.0, 0.0, 0.0));
+    ///
+    /// assert!((p - point3(0.0, 0.0, -1.0)).len() < 1.0e-6);
+    /// ```
+    pub fn rotate(degrees: Float, axis: Vec3f) -> Transform {
+        let a = axis.normalized();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let m = Mat4::new([
+            [
+                a.x * a.x + (1.0 - a.x * a.x) * cos,
+                a.x * a.y * (1.0 - cos) - a.z * sin,
+                a.x * a.z * (1.0 - cos) + a.y * sin,
+                0.0,
+            ],
+            [
+                a.x * a.y * (1.0 - cos) + a.z * sin,
+                a.y * a.y + (1.0 - a.y * a.y) * cos,
+                a.y * a.z * (1.0 - cos) - a.x * sin,
+                0.0,
+            ],
+            [
+                a.x * a.z * (1.0 - cos) - a.y * sin,
+                a.y * a.z * (1.0 - cos) + a.x * sin,
+                a.z * a.z + (1.0 - a.z * a.z) * cos,
+                0.0,
+            ],
+            [0.0, 0.0, 0.0, 1.0],
+        ]);
+        // Rotations are orthogonal
+        Transform {
+            m,
+            m_inv: m.transpose(),
+        }
+    }
+
+    /// Transformation undoing this one.
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            m: self.m_inv,
+            m_inv: self.m,
+        }
+    }
+
+    pub fn matrix(&self) -> &Mat4 {
+        &self.m
+    }
+
+    /// True if the transformation turns a right-handed coordinate system
+    /// into a left-handed one.
+    pub fn swaps_handedness(&self) -> bool {
+        let m = &self.m;
+        let det = m[(0, 0)] * (m[(1, 1)] * m[(2, 2)] - m[(1, 2)] * m[(2, 1)])
+            - m[(0, 1)] * (m[(1, 0)] * m[(2, 2)] - m[(1, 2)] * m[(2, 0)])
+            + m[(0, 2)] * (m[(1, 0)] * m[(2, 1)] - m[(1, 1)] * m[(2, 0)]);
+        det < 0.0
+    }
+
+    pub fn transform_point(&self, p: Point3f) -> Point3f {
+        let m = &self.m;
+        let x = m[(0, 0)] * p.x + m[(0, 1)] * p.y + m[(0, 2)] * p.z + m[(0, 3)];
+        let y = m[(1, 0)] * p.x + m[(1, 1)] * p.y + m[(1, 2)] * p.z + m[(1, 3)];
+        let z = m[(2, 0)] * p.x + m[(2, 1)] * p.y + m[(2, 2)] * p.z + m[(2, 3)];
+        let w = m[(3, 0)] * p.x + m[(3, 1)] * p.y + m[(3, 2)] * p.z + m[(3, 3)];
+        if w == 1.0 {
+            point3(x, y, z)
+        } else {
+            point3(x, y, z) * w.recip()
+        }
+    }
+
+    pub fn transform_vector(&self, v: Vec3f) -> Vec3f {
+        let m = &self.m;
+        vec3(
+            m[(0, 0)] * v.x + m[(0, 1)] * v.y + m[(0, 2)] * v.z,
+            m[(1, 0)] * v.x + m[(1, 1)] * v.y + m[(1, 2)] * v.z,
+            m[(2, 0)] * v.x + m[(2, 1)] * v.y + m[(2, 2)] * v.z,
+        )
+    }
+
+    /// Transforms a surface normal, which is done with the inverse transpose
+    /// matrix, to keep it perpendicular to the surface. The result is not
+    /// normalized.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let t = Transform::scale(vec3(2.0, 1.0, 1.0));
+    /// // Normal to the plane x + y = 0
+    /// let n = t.transform_normal(vec3(1.0, 1.0, 0.0));
+    /// // ...stays normal to the plane x + 2y = 0
+    /// assert_eq!(n, vec3(0.5, 1.0, 0.0));
+    /// ```
+    pub fn transform_normal(&self, n: Vec3f) -> Vec3f {
+        let m = &self.m_inv;
+        vec3(
+            m[(0, 0)] * n.x + m[(1, 0)] * n.y + m[(2, 0)] * n.z,
+            m[(0, 1)] * n.x + m[(1, 1)] * n.y + m[(2, 1)] * n.z,
+            m[(0, 2)] * n.x + m[(1, 2)] * n.y + m[(2, 2)] * n.z,
+        )
+    }
+
+    /// Transforms a ray. The direction is not renormalized, so hit times
+    /// along the ray stay the same.
+    pub fn transform_ray(&self, r: &Ray) -> Ray {
+        Ray::new(
+            self.transform_point(r.origin()),
+            self.transform_vector(r.direction()),
+        )
+    }
+
+    /// Bounding box of transformed bounds, i.e. of all eight of its corners.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let b = Bounds3::from_corners(point3(0.0, 0.0, 0.0), point3(1.0, 1.0, 1.0));
+    /// let t = Transform::rotate(45.0, vec3(0.0, 0.0, 1.0));
+    /// let tb = t.transform_bounds(&b);
+    ///
+    /// assert!((tb.min.x + 0.5f32.sqrt()).abs() < 1.0e-6);
+    /// assert!((tb.max.y - 2.0f32.sqrt()).abs() < 1.0e-6);
+    /// ```
+    pub fn transform_bounds(&self, b: &Bounds3f) -> Bounds3f {
+        let mut result = Bounds3f::empty();
+        for i in 0..8 {
+            let corner = point3(
+                if i & 1 == 0 { b.min.x } else { b.max.x },
+                if i & 2 == 0 { b.min.y } else { b.max.y },
+                if i & 4 == 0 { b.min.z } else { b.max.z },
+            );
+            result = Bounds3::union_point(&result, self.transform_point(corner));
+        }
+        result
+    }
+
+    /// Transforms a hit record: its point, normals and partial derivatives.
+    /// The time of hit and the orientation relative to the (transformed) ray
+    /// stay the same.
+    pub fn transform_hit<'a>(&self, hit: HitStruct<'a>) -> HitStruct<'a> {
+        let n = self.transform_normal(hit.n).normalized();
+        let shading = Shading {
+            n: self.transform_normal(hit.shading.n).normalized(),
+            dpdu: self.transform_vector(hit.shading.dpdu),
+            dpdv: self.transform_vector(hit.shading.dpdv),
+            dndu: self.transform_normal(hit.shading.dndu),
+            dndv: self.transform_normal(hit.shading.dndv),
+        };
+        HitStruct {
+            p: self.transform_point(hit.p),
+            n,
+            dpdu: self.transform_vector(hit.dpdu),
+            dpdv: self.transform_vector(hit.dpdv),
+            dndu: self.transform_normal(hit.dndu),
+            dndv: self.transform_normal(hit.dndv),
+            shading,
+            ..hit
+        }
+    }
+}
+
+///
+/// Composition of transformations: `a * b` applies `b` first, then `a`.
+///
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let t = Transform::translate(vec3(1.0, 0.0, 0.0)) * Transform::scale(vec3(2.0, 2.0, 2.0));
+    ///
+    /// assert_eq!(t.transform_point(point3(1.0, 1.0, 1.0)), point3(3.0, 2.0, 2.0));
+    /// ```
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            m: self.m * rhs.m,
+            m_inv: rhs.m_inv * self.m_inv,
+        }
+    }
+}
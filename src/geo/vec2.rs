@@ -82,7 +82,7 @@ impl<T> Vec2<T> {
     where
         T: Numeric<T>,
     {
-        self.dot(self.clone())
+        self.dot(self)
     }
 
     ///
@@ -347,6 +347,7 @@ where
     /// let v = Vec2::new(1.0, 2.0);
     /// assert_eq!(v / 2.0, Vec2::new(0.5, 1.0));
     /// ```
+    #[allow(clippy::suspicious_arithmetic_impl)]
     fn div(self, divisor: T) -> Self::Output {
         let s = divisor.recip();
         self * s
@@ -1,4 +1,5 @@
-use crate::num_traits::{Float, Numeric, One, Recip, Sqrt};
+use crate::geo::{max, min};
+use crate::num_traits::{Abs, Float, Numeric, One, Recip, Sqrt};
 
 /// A 3-dimensional vector.
 #[derive(Copy, Clone, Debug, Default, Hash, Eq, PartialEq)]
@@ -16,6 +17,7 @@ impl<T> Vec3<T> {
         Vec3 { x, y, z }
     }
 
+    #[allow(clippy::eq_op)]
     pub fn has_nans(&self) -> bool
     where
         T: PartialEq,
@@ -83,6 +85,111 @@ impl<T> Vec3<T> {
     {
         self * (self.len().recip())
     }
+
+    /// Component-wise absolute value
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert_eq!(vec3(-1.0, 2.0, -3.0).abs(), vec3(1.0, 2.0, 3.0));
+    /// ```
+    pub fn abs(self) -> Vec3<T>
+    where
+        T: Abs,
+    {
+        Vec3::new(self.x.abs(), self.y.abs(), self.z.abs())
+    }
+
+    /// Largest coordinate value
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert_eq!(vec3(1.0, 3.0, 2.0).max_component(), 3.0);
+    /// ```
+    pub fn max_component(self) -> T
+    where
+        T: Copy + PartialOrd,
+    {
+        max(self.x, max(self.y, self.z))
+    }
+
+    /// Smallest coordinate value
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert_eq!(vec3(2.0, 1.0, 3.0).min_component(), 1.0);
+    /// ```
+    pub fn min_component(self) -> T
+    where
+        T: Copy + PartialOrd,
+    {
+        min(self.x, min(self.y, self.z))
+    }
+
+    /// Index of the dimension with the largest coordinate value
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert_eq!(vec3(1.0, 3.0, 2.0).max_dimension(), 1);
+    /// assert_eq!(vec3(1.0, 3.0, 5.0).max_dimension(), 2);
+    /// ```
+    pub fn max_dimension(self) -> usize
+    where
+        T: PartialOrd,
+    {
+        if self.x > self.y {
+            if self.x > self.z {
+                0
+            } else {
+                2
+            }
+        } else if self.y > self.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    /// Rearranges coordinates, so that `x`, `y` and `z` are taken from the
+    /// given dimensions
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// assert_eq!(vec3(1.0, 2.0, 3.0).permute(2, 0, 1), vec3(3.0, 1.0, 2.0));
+    /// ```
+    pub fn permute(self, x: usize, y: usize, z: usize) -> Vec3<T>
+    where
+        T: Copy,
+    {
+        Vec3::new(self[x], self[y], self[z])
+    }
+}
+
+///
+/// Allows indexing into a vector by the number of the dimension.
+///
+impl<T> std::ops::Index<usize> for Vec3<T> {
+    type Output = T;
+
+    /// ```
+    /// use pbrt::geo::*;
+    ///
+    /// let v = vec3(1.0, 2.0, 3.0);
+    /// assert_eq!(v[0], 1.0);
+    /// assert_eq!(v[2], 3.0);
+    /// ```
+    fn index(&self, dim: usize) -> &Self::Output {
+        match dim {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of bounds: {}", dim),
+        }
+    }
 }
 
 /// Vector dot-product
@@ -105,27 +212,6 @@ where
     )
 }
 
-#[cfg(test)]
-mod test {
-
-    use super::cross;
-    use crate::geo::*;
-
-    #[test]
-    fn test_cross() {
-        let u = Vec3f::new(1.0, 0.0, 0.0);
-        let v = Vec3f::new(0.0, 1.0, 0.0);
-        let w = Vec3f::new(0.0, 0.0, 1.0);
-
-        assert_eq!(cross(&u, &v), w);
-        assert_eq!(cross(&v, &u), -w);
-        assert_eq!(cross(&v, &w), u);
-        assert_eq!(cross(&w, &v), -u);
-        assert_eq!(cross(&w, &u), v);
-        assert_eq!(cross(&u, &w), -v);
-    }
-}
-
 pub fn lerp<T>(v1: Vec3<T>, v2: Vec3<T>, t: T) -> Vec3<T>
 where
     T: Numeric<T> + One,
@@ -205,9 +291,48 @@ where
     }
 }
 
+///
+/// Component-wise multiplication, for colors.
+///
+impl<T> std::ops::Mul<Vec3<T>> for Vec3<T>
+where
+    T: std::ops::Mul<Output = T> + Copy,
+{
+    type Output = Vec3<T>;
+    /// ```
+    /// use pbrt::geo::vec3::Vec3;
+    /// let v = Vec3::new(1.0, 2.0, -1.0);
+    /// assert_eq!(v * Vec3::new(0.5, 2.0, 3.0), Vec3::new(0.5, 4.0, -3.0));
+    /// ```
+    fn mul(self, rhs: Vec3<T>) -> Self::Output {
+        Vec3::new(self.x * rhs.x, self.y * rhs.y, self.z * rhs.z)
+    }
+}
+
 fn mul<T>(v: Vec3<T>, s: T) -> Vec3<T>
 where
     T: std::ops::Mul<Output = T> + Copy,
 {
     Vec3::new(v.x * s, v.y * s, v.z * s)
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::cross;
+    use crate::geo::*;
+
+    #[test]
+    fn test_cross() {
+        let u = Vec3f::new(1.0, 0.0, 0.0);
+        let v = Vec3f::new(0.0, 1.0, 0.0);
+        let w = Vec3f::new(0.0, 0.0, 1.0);
+
+        assert_eq!(cross(&u, &v), w);
+        assert_eq!(cross(&v, &u), -w);
+        assert_eq!(cross(&v, &w), u);
+        assert_eq!(cross(&w, &v), -u);
+        assert_eq!(cross(&w, &u), v);
+        assert_eq!(cross(&u, &w), -v);
+    }
+}
@@ -1,14 +1,24 @@
 use crate::num_traits::Float;
 
-use crate::geo::{Point3f, Ray, Vec3f};
+use crate::geo::{Frame, Point2f, Point3f, Ray, Vec3f};
 use crate::material::Material;
 
 // TODO: This should be called a Surface, or something. RTiaW calls it `hitable`.
 pub trait Hit: std::marker::Sync {
-    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct>;
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>>;
+
+    /// True if the ray hits anything between `t_min` and `t_max`.
+    ///
+    /// Unlike `hit`, it may stop at any hit, not just the closest one, and
+    /// doesn't build a hit record, which makes it cheaper for shadow rays.
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.hit(ray, t_min, t_max).is_some()
+    }
 }
 
 // TODO: `HitStruct` should be called `SurfaceInteraction`.
+#[non_exhaustive]
+#[derive(Clone)]
 pub struct HitStruct<'a> {
     /// Time of hit
     pub t: Float,
@@ -20,11 +30,39 @@ pub struct HitStruct<'a> {
     pub front_face: bool,
     /// Material of a surface
     pub material: &'a dyn Material,
-    /// Private field to prevent struct literal instantiation.
-    _private: (),
+    /// Surface (u, v) coordinates of the point of hit
+    pub uv: Point2f,
+    /// Partial derivative of the position with respect to `u`
+    pub dpdu: Vec3f,
+    /// Partial derivative of the position with respect to `v`
+    pub dpdv: Vec3f,
+    /// Partial derivative of the normal with respect to `u`
+    pub dndu: Vec3f,
+    /// Partial derivative of the normal with respect to `v`
+    pub dndv: Vec3f,
+    /// Shading geometry, possibly perturbed by interpolated normals or bump
+    /// mapping
+    pub shading: Shading,
+}
+
+/// Shading geometry of a surface.
+///
+/// It starts out identical to the true geometry of the surface, but may be
+/// perturbed for shading purposes only.
+#[derive(Copy, Clone, Debug)]
+pub struct Shading {
+    /// Shading normal, always in the same hemisphere as `HitStruct::n`
+    pub n: Vec3f,
+    pub dpdu: Vec3f,
+    pub dpdv: Vec3f,
+    pub dndu: Vec3f,
+    pub dndv: Vec3f,
 }
 
 impl<'a> HitStruct<'a> {
+    /// Constructs a hit record with an arbitrary parameterization around the
+    /// normal. Shapes that know better should follow it up with
+    /// `with_parameterization`.
     pub fn new(
         t: Float,
         p: Point3f,
@@ -38,13 +76,135 @@ impl<'a> HitStruct<'a> {
         } else {
             -outward_normal
         };
+        let Frame { s, t: tangent, .. } = Frame::from_normal(outward_normal);
+        let zero = Vec3f::default();
         HitStruct {
             t,
             p,
             n,
             front_face,
             material,
-            _private: (),
+            uv: Point2f::default(),
+            dpdu: s,
+            dpdv: tangent,
+            dndu: zero,
+            dndv: zero,
+            shading: Shading {
+                n,
+                dpdu: s,
+                dpdv: tangent,
+                dndu: zero,
+                dndv: zero,
+            },
         }
     }
+
+    /// Sets the surface (u, v) coordinates and partial derivatives of the
+    /// position and the normal. Resets the shading geometry to match.
+    pub fn with_parameterization(
+        mut self,
+        uv: Point2f,
+        dpdu: Vec3f,
+        dpdv: Vec3f,
+        dndu: Vec3f,
+        dndv: Vec3f,
+    ) -> HitStruct<'a> {
+        self.uv = uv;
+        self.dpdu = dpdu;
+        self.dpdv = dpdv;
+        self.dndu = dndu;
+        self.dndv = dndv;
+        self.shading = Shading {
+            n: self.n,
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        };
+        self
+    }
+
+    /// Sets the shading geometry. The shading normal `n` is flipped, if
+    /// needed, to lie in the same hemisphere as the true normal.
+    pub fn set_shading_geometry(
+        &mut self,
+        n: Vec3f,
+        dpdu: Vec3f,
+        dpdv: Vec3f,
+        dndu: Vec3f,
+        dndv: Vec3f,
+    ) {
+        let n = if n.dot(self.n) < 0.0 { -n } else { n };
+        self.shading = Shading {
+            n,
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        };
+    }
+
+    /// Orthonormal shading frame around the shading normal, with the `s`
+    /// axis following `dpdu`.
+    pub fn shading_frame(&self) -> Frame {
+        Frame::from_normal_and_tangent(self.shading.n, self.shading.dpdu)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::geo::*;
+    use crate::material::null::NullMaterial;
+
+    #[test]
+    fn shading_normal_follows_true_normal() {
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let mut hit = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, -1.0),
+            &NullMaterial,
+        );
+        assert!(!hit.front_face);
+        assert_eq!(hit.n, vec3(0.0, 0.0, 1.0));
+
+        let n = vec3(0.0, 0.6, -0.8);
+        let zero = Vec3f::default();
+        hit.set_shading_geometry(n, hit.dpdu, hit.dpdv, zero, zero);
+        assert_eq!(hit.shading.n, -n);
+    }
+
+    #[test]
+    fn shading_frame_is_orthonormal() {
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let zero = Vec3f::default();
+        let hit = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &NullMaterial,
+        )
+        .with_parameterization(
+            Point2f::new(0.5, 0.5),
+            vec3(2.0, 0.0, 0.5),
+            vec3(0.0, 3.0, 0.0),
+            zero,
+            zero,
+        );
+
+        let Frame { s, t, n } = hit.shading_frame();
+        assert_eq!(n, hit.shading.n);
+        assert!(s.dot(t).abs() < 1.0e-6);
+        assert!(s.dot(n).abs() < 1.0e-6);
+        assert!((s.len() - 1.0).abs() < 1.0e-6);
+        assert!(s.dot(hit.dpdu) > 0.0);
+
+        let v = vec3(0.3, -0.2, 0.9);
+        let local = hit.shading_frame().to_local(v);
+        assert!((local.z - v.dot(n)).abs() < 1.0e-6);
+    }
 }
@@ -14,11 +14,38 @@ pub mod color;
 /// Surface-ray interactions.
 pub mod hit;
 
+/// Scattering functions of surfaces.
+pub mod bsdf;
+
+/// Fresnel equations: how much light interfaces between media reflect.
+pub mod fresnel;
+
+/// Distributions of normals of microfacets, for rough surfaces.
+pub mod microfacet;
+
+/// Values varying over surfaces.
+pub mod texture;
+
 /// Materials.
 pub mod material;
 
+/// Transparent media inside surfaces, and how they nest.
+pub mod medium;
+
+/// Light sources.
+pub mod light;
+
+/// Warping uniform samples to other distributions.
+pub mod sampling;
+
 /// Primitive shapes: spheres and such.
 pub mod shape;
 
+/// Bounded things that can be hit by rays.
+pub mod primitive;
+
+/// Acceleration structures.
+pub mod accel;
+
 /// Scene to be rendered.
 pub mod scene;
@@ -0,0 +1,188 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::material::{DiffuseLight, Material};
+use crate::shape::Shape;
+
+use std::f32::consts::PI;
+
+/// Light emitted by the surface of a shape: a lamp, a window or a neon sign.
+///
+/// The shape should have the same `DiffuseLight` as its material, so that
+/// rays hitting it see the light as well.
+#[derive(Copy, Clone)]
+pub struct AreaLight<'a> {
+    pub shape: &'a dyn Shape,
+    pub emission: &'a DiffuseLight,
+}
+
+/// Light arriving at a point from a sampled point on a light.
+#[derive(Copy, Clone, Debug)]
+pub struct LightSample {
+    /// Unit direction towards the light
+    pub wi: Vec3f,
+    /// Distance to the sampled point on the light
+    pub distance: Float,
+    /// Radiance arriving along `wi`, unless something is in the way
+    pub radiance: Vec3f,
+    /// Probability density of sampling `wi`, with respect to solid angle
+    pub pdf: Float,
+}
+
+impl<'a> AreaLight<'a> {
+    pub fn new(shape: &'a dyn Shape, emission: &'a DiffuseLight) -> AreaLight<'a> {
+        AreaLight { shape, emission }
+    }
+
+    /// Samples light arriving at `p` from a point on the light, chosen
+    /// uniformly by area for a uniform `u` in `[0, 1)²`. Whether the point
+    /// is visible from `p` is up to the caller to check.
+    pub fn sample_li(&self, p: Point3f, u: Point2f) -> Option<LightSample> {
+        let (q, n) = self.shape.sample(u);
+        let d = q - p;
+        let distance_squared = d.len_squared();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let distance = distance_squared.sqrt();
+        let wi = d * distance.recip();
+
+        let cos_light = -n.dot(wi);
+        if cos_light == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.emission.radiance(cos_light > 0.0),
+            // Density by area, converted to density by solid angle
+            pdf: distance_squared / (cos_light.abs() * self.shape.area()),
+        })
+    }
+
+    /// Probability density of `sample_li` at `p` choosing direction `wi`,
+    /// with respect to solid angle. Zero if the direction misses the light.
+    pub fn pdf_li(&self, p: Point3f, wi: Vec3f) -> Float {
+        let ray = Ray::new(p, wi);
+        match self.shape.hit(&ray, 0.0, Float::INFINITY) {
+            Some(hit) => {
+                let cos_light = hit.n.dot(ray.direction()).abs();
+                (hit.p - p).len_squared() / (cos_light * self.shape.area())
+            }
+            None => 0.0,
+        }
+    }
+
+    /// True if the hit is on a surface with the emission of this light, so
+    /// it may be on this light.
+    pub fn emits_at(&self, hit: &HitStruct) -> bool {
+        std::ptr::eq(
+            hit.material as *const dyn Material as *const u8,
+            self.emission as *const DiffuseLight as *const u8,
+        )
+    }
+
+    /// Total power emitted by the light.
+    pub fn power(&self) -> Vec3f {
+        let sides = if self.emission.two_sided { 2.0 } else { 1.0 };
+        self.emission.radiance * (sides * PI * self.shape.area())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::hit::Hit;
+    use crate::shape::quad::Quad;
+    use crate::shape::sphere::Sphere;
+
+    use rand::prelude::*;
+
+    const EMISSION: DiffuseLight = DiffuseLight {
+        radiance: Vec3 {
+            x: 1.0,
+            y: 2.0,
+            z: 4.0,
+        },
+        two_sided: false,
+    };
+
+    /// Monte Carlo estimate of the solid angle of a light, weighted by the
+    /// radiance towards `p`.
+    fn estimate(light: &AreaLight, p: Point3f) -> Vec3f {
+        let mut rng = StdRng::seed_from_u64(71);
+        let n = 100_000;
+        let mut sum = vec3(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let u = Point2f::new(rng.gen(), rng.gen());
+            if let Some(sample) = light.sample_li(p, u) {
+                sum += sample.radiance * sample.pdf.recip();
+            }
+        }
+        sum * (n as Float).recip()
+    }
+
+    #[test]
+    fn sphere_light_covers_its_solid_angle() {
+        let sphere = Sphere {
+            center: point3(0.0, 0.0, -4.0),
+            radius: 1.0,
+            material: &EMISSION,
+        };
+        let light = AreaLight::new(&sphere, &EMISSION);
+
+        // The far side of the sphere faces away, and it's hidden anyway
+        let result = estimate(&light, point3(0.0, 0.0, 0.0));
+        let solid_angle = 2.0 * PI * (1.0 - (1.0 - 1.0 / 16.0 as Float).sqrt());
+        let expected = EMISSION.radiance * solid_angle;
+        assert!((result - expected).len() < 0.02 * expected.len());
+
+        let power = light.power();
+        assert!((power.x - 4.0 * PI * PI).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn quad_light_pdf_agrees_with_samples() {
+        let quad = Quad {
+            origin: point3(-1.0, 2.0, -1.0),
+            u: vec3(2.0, 0.0, 0.0),
+            v: vec3(0.0, 0.0, 2.0),
+            material: &EMISSION,
+        };
+        let light = AreaLight::new(&quad, &EMISSION);
+
+        let p = point3(0.5, 0.0, 0.0);
+        let mut rng = StdRng::seed_from_u64(72);
+        for _ in 0..100 {
+            let u = Point2f::new(rng.gen(), rng.gen());
+            let sample = light.sample_li(p, u).unwrap();
+            assert_eq!(sample.radiance, EMISSION.radiance);
+            let pdf = light.pdf_li(p, sample.wi);
+            assert!((pdf - sample.pdf).abs() < 1.0e-3 * pdf);
+        }
+        assert_eq!(light.pdf_li(p, vec3(0.0, -1.0, 0.0)), 0.0);
+
+        let hit = quad
+            .hit(&Ray::new(p, vec3(0.0, 1.0, 0.0)), 0.0, 10.0)
+            .unwrap();
+        assert!(light.emits_at(&hit));
+        let other = EMISSION;
+        assert!(!AreaLight::new(&quad, &other).emits_at(&hit));
+
+        // From above, the quad faces away, unless it's two-sided
+        let p = point3(0.0, 4.0, 0.0);
+        let sample = light.sample_li(p, Point2f::new(0.5, 0.5)).unwrap();
+        assert_eq!(sample.radiance, Vec3f::default());
+        let two_sided = DiffuseLight {
+            two_sided: true,
+            ..EMISSION
+        };
+        let light = AreaLight::new(&quad, &two_sided);
+        let sample = light.sample_li(p, Point2f::new(0.5, 0.5)).unwrap();
+        assert_eq!(sample.radiance, EMISSION.radiance);
+        assert_eq!(sample.distance, 2.0);
+        assert!((sample.pdf - 1.0).abs() < 1.0e-6);
+    }
+}
@@ -1,18 +1,103 @@
+/// Bump and normal mapping: fine detail of surfaces, without geometry.
+pub mod bump;
+
+/// Coatings over other materials, like varnish or lacquer.
+pub mod coated;
+
+/// Rough metals, with presets for common ones.
+pub mod conductor;
+
 pub mod dielectric;
+
+/// Emissive material of area lights.
+pub mod diffuse_light;
+
+/// Thin films over other materials, making them iridescent.
+pub mod iridescence;
+
 pub mod lambertian;
 pub mod metal;
 
+/// Blends of two materials.
+pub mod mix;
+
 /// Null material, useful for replacing missing materials and for unit tests.
 pub mod null;
 
+/// Rough diffuse materials, like clay or concrete.
+pub mod oren_nayar;
+
+/// Disney's principled material, for assets authored for it.
+pub mod principled;
+
+/// Materials with their tangent turned, for anisotropic ones.
+pub mod rotated;
+
+/// Frosted glass and other rough transparent materials.
+pub mod rough_dielectric;
+
+/// Translucent materials, like skin, wax or marble.
+pub mod subsurface;
+
+/// Anisotropic glossy materials, like brushed metal or satin.
+pub mod ward;
+
+pub use bump::*;
+pub use coated::*;
+pub use conductor::*;
 pub use dielectric::*;
+pub use diffuse_light::*;
+pub use iridescence::*;
 pub use lambertian::*;
 pub use metal::*;
+pub use mix::*;
 pub use null::*;
+pub use oren_nayar::*;
+pub use principled::*;
+pub use rotated::*;
+pub use rough_dielectric::*;
+pub use subsurface::*;
+pub use ward::*;
 
+use crate::bsdf::{Bsdf, Bxdf};
 use crate::geo::{Ray, Vec3f};
 use crate::hit::HitStruct;
+use crate::medium::Medium;
+use crate::prelude::*;
 
 pub trait Material: std::marker::Sync {
-    fn scatter(&self, ray: &Ray, hit: &HitStruct, attenuation: &mut Vec3f) -> Option<Ray>;
+    /// Scattering function at the point of hit, or `None` if the material
+    /// absorbs all light.
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>>;
+
+    /// Radiance emitted from the point of hit back along the ray. Most
+    /// materials don't emit anything.
+    fn emitted(&self, _ray: &Ray, _hit: &HitStruct) -> Vec3f {
+        Vec3f::default()
+    }
+
+    /// Transparent medium inside surfaces of the material, if any. Most
+    /// materials are opaque, or thin.
+    fn interior(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Like `bsdf`, with `outside_index` the refraction index of the medium
+    /// on the other side of the surface from the `interior`, rather than
+    /// vacuum.
+    fn bsdf_between(&self, hit: &HitStruct, _outside_index: Float) -> Option<Bsdf<'_>> {
+        self.bsdf(hit)
+    }
+}
+
+/// BxDF of a BSDF from `bsdf` or `bsdf_between`, in the shading frame of the
+/// `hit`, for materials made of others. Materials absorbing all light get a
+/// black one.
+pub(crate) fn bxdf_or_black<'a>(bsdf: Option<Bsdf<'a>>, hit: &HitStruct) -> Box<dyn Bxdf + 'a> {
+    match bsdf {
+        Some(bsdf) => bsdf.into_bxdf_in(hit.shading_frame()),
+        None => Box::new(Lambertian {
+            albedo: Vec3f::default(),
+        }),
+    }
 }
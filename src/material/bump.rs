@@ -0,0 +1,388 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::Medium;
+use crate::texture::Texture;
+
+/// Step in (u, v) over which heights are differentiated.
+const DELTA: Float = 0.0005;
+
+/// Another material, with its surface raised by a `height` texture, in
+/// world units along the outward normal: detail too fine to be worth real
+/// geometry, like the grain of leather or the dents of hammered metal. Only
+/// shading normals change; the surface stays where it is.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::*;
+/// use pbrt::texture::Checkerboard;
+///
+/// let rubber = Lambertian {
+///     albedo: vec3(0.9, 0.1, 0.1),
+/// };
+/// let tiles = Checkerboard {
+///     even: 0.0,
+///     odd: 0.002,
+///     frequency: 8.0,
+/// };
+/// let tiled = Bump {
+///     material: &rubber,
+///     height: &tiles,
+/// };
+/// ```
+#[derive(Copy, Clone)]
+pub struct Bump<'a> {
+    pub material: &'a dyn Material,
+    pub height: &'a dyn Texture<Float>,
+}
+
+impl<'a> Bump<'a> {
+    /// The hit, with its shading normal tilted by the slope of the height.
+    fn bump<'h>(&self, hit: &HitStruct<'h>) -> HitStruct<'h> {
+        let shading = hit.shading;
+        // Heights go along the outward normal, whichever side the ray comes
+        // from
+        let n = if hit.front_face {
+            shading.n
+        } else {
+            -shading.n
+        };
+        let height_at = |du: Float, dv: Float| {
+            let mut shifted = hit.clone();
+            shifted.p = hit.p + hit.dpdu * du + hit.dpdv * dv;
+            shifted.uv = Point2f::new(hit.uv.x + du, hit.uv.y + dv);
+            shifted.shading.n = (shading.n + shading.dndu * du + shading.dndv * dv).normalized();
+            self.height.evaluate(&shifted)
+        };
+        let height = height_at(0.0, 0.0);
+        let dhdu = (height_at(DELTA, 0.0) - height) / DELTA;
+        let dhdv = (height_at(0.0, DELTA) - height) / DELTA;
+
+        let dpdu = hit.dpdu + n * dhdu + shading.dndu * height;
+        let dpdv = hit.dpdv + n * dhdv + shading.dndv * height;
+        let bumped = dpdu.cross(&dpdv);
+        if bumped.len_squared() == 0.0 {
+            return hit.clone();
+        }
+        let bumped = bumped.normalized();
+        let bumped = if bumped.dot(n) < 0.0 { -bumped } else { bumped };
+
+        let mut perturbed = hit.clone();
+        perturbed.set_shading_geometry(
+            bumped,
+            shading.dpdu,
+            shading.dpdv,
+            shading.dndu,
+            shading.dndv,
+        );
+        perturbed
+    }
+}
+
+impl<'a> Material for Bump<'a> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        let bumped = self.bump(hit);
+        perturbed_bsdf(self.material.bsdf(&bumped), hit, &bumped)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitStruct) -> Vec3f {
+        self.material.emitted(ray, hit)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        let bumped = self.bump(hit);
+        perturbed_bsdf(
+            self.material.bsdf_between(&bumped, outside_index),
+            hit,
+            &bumped,
+        )
+    }
+}
+
+/// Another material, with its shading normals read from a texture of
+/// normals in tangent space, encoded as colors the usual way: each component
+/// from `[-1, 1]` to `[0, 1]`, with the tangent in red, the bitangent in
+/// green and the normal in blue.
+///
+/// The tangent is the one of the surface, such as the tangents of a mesh,
+/// and the bitangent follows `dpdv`, for mirrored (u, v) coordinates.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::*;
+///
+/// let brick = Lambertian {
+///     albedo: vec3(0.6, 0.25, 0.2),
+/// };
+/// // Leaning along the tangent
+/// let leaning = NormalMap {
+///     material: &brick,
+///     normals: &vec3(0.8, 0.5, 0.9),
+/// };
+/// ```
+#[derive(Copy, Clone)]
+pub struct NormalMap<'a> {
+    pub material: &'a dyn Material,
+    pub normals: &'a dyn Texture<Vec3f>,
+}
+
+impl<'a> NormalMap<'a> {
+    /// The hit, with its shading normal from the texture.
+    fn map<'h>(&self, hit: &HitStruct<'h>) -> HitStruct<'h> {
+        let shading = hit.shading;
+        let n = if hit.front_face {
+            shading.n
+        } else {
+            -shading.n
+        };
+        let frame = Frame::from_normal_and_tangent(n, shading.dpdu);
+        let bitangent = if frame.t.dot(hit.dpdv) < 0.0 {
+            -frame.t
+        } else {
+            frame.t
+        };
+
+        let c = self.normals.evaluate(hit) * 2.0 - vec3(1.0, 1.0, 1.0);
+        let mapped = frame.s * c.x + bitangent * c.y + n * c.z;
+        if mapped.len_squared() == 0.0 {
+            return hit.clone();
+        }
+
+        let mut perturbed = hit.clone();
+        perturbed.set_shading_geometry(
+            mapped.normalized(),
+            shading.dpdu,
+            shading.dpdv,
+            shading.dndu,
+            shading.dndv,
+        );
+        perturbed
+    }
+}
+
+impl<'a> Material for NormalMap<'a> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        let mapped = self.map(hit);
+        perturbed_bsdf(self.material.bsdf(&mapped), hit, &mapped)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitStruct) -> Vec3f {
+        self.material.emitted(ray, hit)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        let mapped = self.map(hit);
+        perturbed_bsdf(
+            self.material.bsdf_between(&mapped, outside_index),
+            hit,
+            &mapped,
+        )
+    }
+}
+
+/// `bsdf`, of a material at the `perturbed` hit, with the true normal of
+/// the `hit` guarding against light leaks.
+fn perturbed_bsdf<'a>(
+    bsdf: Option<Bsdf<'a>>,
+    hit: &HitStruct,
+    perturbed: &HitStruct,
+) -> Option<Bsdf<'a>> {
+    let bxdf = bsdf?.into_bxdf_in(perturbed.shading_frame());
+    let ng = perturbed.shading_frame().to_local(hit.n);
+    Some(Bsdf::new(perturbed, ShadowTerminator { bxdf, ng }))
+}
+
+/// BxDF under a perturbed shading normal, aware of the true normal `ng`, in
+/// its local frame.
+///
+/// Light going through the true surface while the shading normal takes it
+/// for reflection, or the other way around, is cut off: it would leak
+/// through solid surfaces. And reflected light fades smoothly where the
+/// shading normal would light facets the true surface turns away from,
+/// rather than ending at a hard terminator (Chiang et al., "Taming the
+/// Shadow Terminator", 2019).
+struct ShadowTerminator<'a> {
+    bxdf: Box<dyn Bxdf + 'a>,
+    ng: Vec3f,
+}
+
+impl<'a> ShadowTerminator<'a> {
+    fn factor(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let cos_i = wi.dot(self.ng);
+        let reflected = wo.dot(self.ng) * cos_i > 0.0;
+        if reflected != same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        if !reflected {
+            return 1.0;
+        }
+        let projected = wi.z.abs() * self.ng.z;
+        let g = if projected > 0.0 {
+            (cos_i.abs() / projected).min(1.0)
+        } else {
+            1.0
+        };
+        g * (1.0 + g * (1.0 - g))
+    }
+}
+
+impl<'a> Bxdf for ShadowTerminator<'a> {
+    fn flags(&self) -> BxdfFlags {
+        self.bxdf.flags()
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        self.bxdf.eval(wo, wi) * self.factor(wo, wi)
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let sample = self.bxdf.sample(wo, uc, u)?;
+        Some(BsdfSample {
+            f: sample.f * self.factor(wo, sample.wi),
+            ..sample
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        self.bxdf.pdf(wo, wi)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+    use crate::hit::Hit;
+    use crate::material::{Lambertian, NullMaterial};
+    use crate::shape::triangle::Triangle;
+
+    /// Height rising along `u`.
+    struct Ramp(Float);
+
+    impl Texture<Float> for Ramp {
+        fn evaluate(&self, hit: &HitStruct) -> Float {
+            self.0 * hit.uv.x
+        }
+    }
+
+    /// Hit on the plane z = 0, facing up, parameterized by x and y.
+    fn plane_hit(ray: &Ray) -> HitStruct<'_> {
+        let zero = Vec3f::default();
+        HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            ray,
+            vec3(0.0, 0.0, 1.0),
+            &NullMaterial,
+        )
+        .with_parameterization(
+            Point2f::new(0.5, 0.5),
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+            zero,
+            zero,
+        )
+    }
+
+    #[test]
+    fn bumps_tilt_the_normal() {
+        let flat = Bump {
+            material: &NullMaterial,
+            height: &0.1,
+        };
+        let ramp = Ramp(0.5);
+        let ramp = Bump {
+            material: &NullMaterial,
+            height: &ramp,
+        };
+
+        let above = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = plane_hit(&above);
+        assert_eq!(flat.bump(&hit).shading.n, hit.shading.n);
+        let tilted = vec3(-0.5, 0.0, 1.0).normalized();
+        assert!((ramp.bump(&hit).shading.n - tilted).len() < 1.0e-3);
+
+        // From below, the same bumps seen from the other side
+        let below = Ray::new(point3(0.0, 0.0, -1.0), vec3(0.0, 0.0, 1.0));
+        let hit = plane_hit(&below);
+        assert!((ramp.bump(&hit).shading.n + tilted).len() < 1.0e-3);
+    }
+
+    #[test]
+    fn normal_maps_are_in_tangent_space() {
+        let straight = NormalMap {
+            material: &NullMaterial,
+            normals: &vec3(0.5, 0.5, 1.0),
+        };
+        let leaning = NormalMap {
+            material: &NullMaterial,
+            normals: &vec3(0.8, 0.5, 0.9),
+        };
+
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = plane_hit(&ray);
+        assert!((straight.map(&hit).shading.n - hit.shading.n).len() < 1.0e-6);
+        let n = leaning.map(&hit).shading.n;
+        assert!((n - vec3(0.6, 0.0, 0.8)).len() < 1.0e-5, "{:?}", n);
+
+        // Following the tangents of meshes
+        let triangle = Triangle {
+            positions: [
+                point3(0.0, 0.0, 0.0),
+                point3(2.0, 0.0, 0.0),
+                point3(0.0, 2.0, 0.0),
+            ],
+            material: &NullMaterial,
+            backface_culling: false,
+            uvs: None,
+            tangents: Some([vec3(0.0, 1.0, 0.0); 3]),
+        };
+        let ray = Ray::new(point3(0.5, 0.5, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = triangle.hit(&ray, 0.0, Float::INFINITY).unwrap();
+        let n = leaning.map(&hit).shading.n;
+        assert!((n - vec3(0.0, 0.6, 0.8)).len() < 1.0e-5, "{:?}", n);
+    }
+
+    #[test]
+    fn perturbed_normals_dont_leak_light() {
+        let white = Lambertian {
+            albedo: vec3(1.0, 1.0, 1.0),
+        };
+        let steep = NormalMap {
+            material: &white,
+            normals: &vec3(0.9, 0.5, 0.8),
+        };
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = plane_hit(&ray);
+        let bsdf = steep.bsdf(&hit).unwrap();
+        let n = steep.map(&hit).shading.n;
+        assert!(n.z < 0.9);
+
+        // Under the surface, though above the shading normal
+        let wo = vec3(0.0, 0.0, 1.0);
+        let under = vec3(0.9, 0.0, -0.1).normalized();
+        assert!(under.dot(n) > 0.0);
+        assert_eq!(bsdf.eval(wo, under), Vec3f::default());
+        // Fading towards the terminator, rather than cut off
+        let grazing = vec3(0.98, 0.0, 0.2).normalized();
+        let lit = bsdf.eval(wo, n).x;
+        let faded = bsdf.eval(wo, grazing).x;
+        assert!(faded > 0.0 && faded < lit, "{} {}", faded, lit);
+
+        let albedo = check_bxdf(bsdf.into_bxdf().as_ref(), vec3(0.0, 0.0, 1.0));
+        assert!(albedo.x > 0.5 && albedo.x < 1.0, "{:?}", albedo);
+    }
+}
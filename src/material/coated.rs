@@ -0,0 +1,519 @@
+use super::RoughDielectricBxdf;
+use super::{bxdf_or_black, Material};
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::microfacet::TrowbridgeReitz;
+use crate::sampling::power_heuristic;
+
+use rand::prelude::*;
+
+use std::f32::consts::PI;
+
+/// A dielectric coating over any other material: car paint, varnished wood,
+/// lacquered plastic.
+///
+/// Light bounces around between the coating and the base before it gets out,
+/// which is simulated by random walks between the two layers (Guo et al.,
+/// "Position-Free Monte Carlo Simulation for Arbitrary Layered BSDFs", 2018).
+/// The coating absorbs light along the way, by `absorption` per unit of
+/// `thickness`, but it doesn't scatter it.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::*;
+///
+/// let wood = Lambertian {
+///     albedo: vec3(0.5, 0.3, 0.1),
+/// };
+/// let varnished_wood = Coated {
+///     absorption: vec3(0.5, 1.0, 2.0),
+///     ..Coated::new(&wood, 1.5, 0.1)
+/// };
+/// ```
+pub struct Coated<'a> {
+    pub base: &'a dyn Material,
+    /// Index of refraction of the coating
+    pub ior: Float,
+    /// Perceptual roughness of the coating, as for `RoughDielectric`
+    pub roughness: Float,
+    pub thickness: Float,
+    /// Fraction of light absorbed per unit of distance in the coating
+    pub absorption: Vec3f,
+    /// Maximum number of bounces between the layers
+    pub max_depth: usize,
+    /// Number of random walks to estimate the BSDF with
+    pub samples: usize,
+}
+
+impl<'a> Coated<'a> {
+    /// Clear coating over `base`.
+    pub fn new(base: &'a dyn Material, ior: Float, roughness: Float) -> Coated<'a> {
+        Coated {
+            base,
+            ior,
+            roughness,
+            thickness: 0.01,
+            absorption: Vec3f::default(),
+            max_depth: 10,
+            samples: 1,
+        }
+    }
+}
+
+impl<'a> Material for Coated<'a> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        let bottom = bxdf_or_black(self.base.bsdf(hit), hit);
+        Some(Bsdf::new(
+            hit,
+            LayeredBxdf {
+                top: RoughDielectricBxdf {
+                    eta: self.ior,
+                    distribution: TrowbridgeReitz::from_roughness(self.roughness),
+                },
+                bottom,
+                thickness: self.thickness.max(Float::MIN_POSITIVE),
+                absorption: self.absorption,
+                max_depth: self.max_depth,
+                samples: self.samples.max(1),
+            },
+        ))
+    }
+}
+
+/// Two BxDFs stacked on top of each other, `top` at `z = thickness` and
+/// `bottom` at `z = 0`, with an absorbing medium in between. It's two-sided:
+/// seen from below, it's flipped over.
+///
+/// It's evaluated stochastically: `eval` and `pdf` are estimates, and
+/// samples are only `proportional` to the value and density of the BxDF.
+pub struct LayeredBxdf<'a> {
+    pub top: RoughDielectricBxdf,
+    pub bottom: Box<dyn Bxdf + 'a>,
+    pub thickness: Float,
+    pub absorption: Vec3f,
+    pub max_depth: usize,
+    pub samples: usize,
+}
+
+impl<'a> LayeredBxdf<'a> {
+    /// Fraction of light going through the whole medium along `w`.
+    fn transmittance(&self, w: Vec3f) -> Vec3f {
+        let distance = self.thickness / w.z.abs();
+        let a = self.absorption;
+        vec3(
+            (-a.x * distance).exp(),
+            (-a.y * distance).exp(),
+            (-a.z * distance).exp(),
+        )
+    }
+
+    /// Random numbers for the walks, the same for the same arguments.
+    fn rng(a: Vec3f, b: Vec3f) -> StdRng {
+        let seed = [a.x, a.y, a.z, b.x, b.y, b.z]
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, x| {
+                (hash ^ x.to_bits() as u64).wrapping_mul(0x0100_0000_01b3)
+            });
+        StdRng::seed_from_u64(seed)
+    }
+}
+
+/// Samples `bxdf` from `w`, keeping only samples going through it.
+fn sample_transmission(bxdf: &dyn Bxdf, w: Vec3f, rng: &mut StdRng) -> Option<BsdfSample> {
+    let s = bxdf.sample(w, rng.gen(), Point2f::new(rng.gen(), rng.gen()))?;
+    if s.pdf == 0.0 || s.wi.z == 0.0 || s.f == Vec3f::default() || same_hemisphere(w, s.wi) {
+        return None;
+    }
+    Some(s)
+}
+
+/// Samples `bxdf` from `w`, keeping only samples bouncing back.
+fn sample_reflection(bxdf: &dyn Bxdf, w: Vec3f, rng: &mut StdRng) -> Option<BsdfSample> {
+    let s = bxdf.sample(w, rng.gen(), Point2f::new(rng.gen(), rng.gen()))?;
+    if s.pdf == 0.0 || s.wi.z == 0.0 || s.f == Vec3f::default() || !same_hemisphere(w, s.wi) {
+        return None;
+    }
+    Some(s)
+}
+
+fn max_component(v: Vec3f) -> Float {
+    v.x.max(v.y).max(v.z)
+}
+
+impl<'a> Bxdf for LayeredBxdf<'a> {
+    fn flags(&self) -> BxdfFlags {
+        let (top, bottom) = (self.top.flags(), self.bottom.flags());
+        let mut flags = BxdfFlags::REFLECTION;
+        if top.is_specular() {
+            flags = flags | BxdfFlags::SPECULAR;
+        }
+        if top.contains(BxdfFlags::DIFFUSE) || bottom.contains(BxdfFlags::DIFFUSE) {
+            flags = flags | BxdfFlags::DIFFUSE;
+        } else if top.contains(BxdfFlags::GLOSSY) || bottom.contains(BxdfFlags::GLOSSY) {
+            flags = flags | BxdfFlags::GLOSSY;
+        }
+        if top.contains(BxdfFlags::TRANSMISSION) && bottom.contains(BxdfFlags::TRANSMISSION) {
+            flags = flags | BxdfFlags::TRANSMISSION;
+        }
+        flags
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+        let top: &dyn Bxdf = &self.top;
+        let bottom: &dyn Bxdf = self.bottom.as_ref();
+
+        // Light enters from the top, and exits through the top if it's
+        // reflected, or through the bottom if it's transmitted
+        let reflected = same_hemisphere(wo, wi);
+        let (exit, non_exit, exit_z) = if reflected {
+            (top, bottom, self.thickness)
+        } else {
+            (bottom, top, 0.0)
+        };
+        // Light coming out along `wis` spreads over a solid angle larger by
+        // eta², which sampling the exit from `wi` doesn't account for
+        let exit_eta2 = if reflected {
+            self.top.eta * self.top.eta
+        } else {
+            1.0
+        };
+
+        let mut rng = LayeredBxdf::rng(wo, wi);
+        let mut f = if reflected {
+            top.eval(wo, wi) * self.samples as Float
+        } else {
+            Vec3f::default()
+        };
+
+        for _ in 0..self.samples {
+            // Into the coating, and out of it towards `wi`, by reciprocity
+            let wos = match sample_transmission(top, wo, &mut rng) {
+                Some(s) => s,
+                None => continue,
+            };
+            let wis = match sample_transmission(exit, wi, &mut rng) {
+                Some(s) => s,
+                None => continue,
+            };
+
+            let mut beta = wos.f * (wos.wi.z.abs() / wos.pdf);
+            let mut z = self.thickness;
+            let mut w = wos.wi;
+
+            for depth in 0..self.max_depth {
+                // Russian roulette
+                if depth > 3 && max_component(beta) < 0.25 {
+                    let q = (1.0 - max_component(beta)).max(0.0);
+                    if rng.gen::<Float>() < q {
+                        break;
+                    }
+                    beta = beta * (1.0 - q).recip();
+                }
+
+                // Across the coating
+                z = if z == self.thickness {
+                    0.0
+                } else {
+                    self.thickness
+                };
+                beta = beta * self.transmittance(w);
+
+                if z == exit_z {
+                    // Back into the coating from the exit
+                    let s = match sample_reflection(exit, -w, &mut rng) {
+                        Some(s) => s,
+                        None => break,
+                    };
+                    beta = beta * s.f * (s.wi.z.abs() / s.pdf);
+                    w = s.wi;
+                    continue;
+                }
+
+                // Out through the exit along `wis`
+                if !non_exit.flags().is_specular() {
+                    let weight = if exit.flags().is_specular() {
+                        1.0
+                    } else {
+                        power_heuristic(wis.pdf, non_exit.pdf(-w, -wis.wi))
+                    };
+                    f += beta
+                        * non_exit.eval(-w, -wis.wi)
+                        * self.transmittance(wis.wi)
+                        * wis.f
+                        * (wis.wi.z.abs() * weight / (wis.pdf * exit_eta2));
+                }
+
+                // Bouncing off the other layer
+                let s = match sample_reflection(non_exit, -w, &mut rng) {
+                    Some(s) => s,
+                    None => break,
+                };
+                beta = beta * s.f * (s.wi.z.abs() / s.pdf);
+                w = s.wi;
+
+                // Out through the exit along the bounce
+                if !exit.flags().is_specular() {
+                    let f_exit = exit.eval(-w, wi);
+                    if f_exit != Vec3f::default() {
+                        let weight = if non_exit.flags().is_specular() {
+                            1.0
+                        } else {
+                            // Against sampling the same direction from `wi`
+                            power_heuristic(s.pdf, exit.pdf(wi, -w))
+                        };
+                        f += beta * self.transmittance(s.wi) * f_exit * weight;
+                    }
+                }
+            }
+        }
+        f * (self.samples as Float).recip()
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let flip = wo.z < 0.0;
+        let wo = if flip { -wo } else { wo };
+        let flip_wi = |w: Vec3f| if flip { -w } else { w };
+
+        let s = self.top.sample(wo, uc, u)?;
+        if s.pdf == 0.0 || s.wi.z == 0.0 {
+            return None;
+        }
+        if same_hemisphere(wo, s.wi) {
+            // Reflected by the coating
+            return Some(BsdfSample {
+                wi: flip_wi(s.wi),
+                proportional: true,
+                ..s
+            });
+        }
+
+        let mut rng = LayeredBxdf::rng(wo, vec3(uc, u.x, u.y));
+        let mut specular = s.flags.contains(BxdfFlags::SPECULAR);
+        let mut f = s.f * s.wi.z.abs();
+        let mut pdf = s.pdf;
+        let mut z = self.thickness;
+        let mut w = s.wi;
+
+        for depth in 0..self.max_depth {
+            // Russian roulette
+            let rr_beta = max_component(f) / pdf;
+            if depth > 3 && rr_beta < 0.25 {
+                let q = (1.0 - rr_beta).max(0.0);
+                if rng.gen::<Float>() < q {
+                    return None;
+                }
+                pdf *= 1.0 - q;
+            }
+            if w.z == 0.0 {
+                return None;
+            }
+
+            // Across the coating, and off the layer there
+            z = if z == self.thickness {
+                0.0
+            } else {
+                self.thickness
+            };
+            f = f * self.transmittance(w);
+            let interface: &dyn Bxdf = if z == 0.0 {
+                self.bottom.as_ref()
+            } else {
+                &self.top
+            };
+            let s = interface.sample(-w, rng.gen(), Point2f::new(rng.gen(), rng.gen()))?;
+            if s.pdf == 0.0 || s.wi.z == 0.0 || s.f == Vec3f::default() {
+                return None;
+            }
+            f = f * s.f;
+            pdf *= s.pdf;
+            specular &= s.flags.contains(BxdfFlags::SPECULAR);
+            w = s.wi;
+
+            if s.flags.contains(BxdfFlags::TRANSMISSION) {
+                // Out of the coating
+                let mut flags = if same_hemisphere(wo, w) {
+                    BxdfFlags::REFLECTION
+                } else {
+                    BxdfFlags::TRANSMISSION
+                };
+                flags = flags
+                    | if specular {
+                        BxdfFlags::SPECULAR
+                    } else {
+                        BxdfFlags::GLOSSY
+                    };
+                return Some(BsdfSample {
+                    wi: flip_wi(w),
+                    f,
+                    pdf,
+                    flags,
+                    proportional: true,
+                });
+            }
+            f = f * w.z.abs();
+        }
+        None
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+        let top: &dyn Bxdf = &self.top;
+        let bottom: &dyn Bxdf = self.bottom.as_ref();
+
+        let mut rng = LayeredBxdf::rng(wi, wo);
+        let reflected = same_hemisphere(wo, wi);
+        let mut pdf_sum = if reflected {
+            top.pdf(wo, wi) * self.samples as Float
+        } else {
+            0.0
+        };
+
+        for _ in 0..self.samples {
+            if reflected {
+                // Through the coating, off the base, and back out
+                let wos = sample_transmission(top, wo, &mut rng);
+                let wis = sample_transmission(top, wi, &mut rng);
+                let (wos, wis) = match (wos, wis) {
+                    (Some(wos), Some(wis)) => (wos, wis),
+                    _ => continue,
+                };
+                // Densities inside, as densities of `wi` outside
+                let jacobian = wi.z.abs() / (self.top.eta * self.top.eta * wis.wi.z.abs());
+                if top.flags().is_specular() {
+                    pdf_sum += bottom.pdf(-wos.wi, -wis.wi) * jacobian;
+                    continue;
+                }
+                let rs = match sample_reflection(bottom, -wos.wi, &mut rng) {
+                    Some(rs) => rs,
+                    None => continue,
+                };
+                if bottom.flags().is_specular() {
+                    pdf_sum += top.pdf(-rs.wi, wi);
+                } else {
+                    let r_pdf = bottom.pdf(-wos.wi, -wis.wi) * jacobian;
+                    pdf_sum += power_heuristic(wis.pdf, r_pdf) * r_pdf;
+                    let t_pdf = top.pdf(-rs.wi, wi);
+                    pdf_sum += power_heuristic(rs.pdf, t_pdf) * t_pdf;
+                }
+            } else {
+                // Through both layers
+                let wos = sample_transmission(top, wo, &mut rng);
+                let wis = sample_transmission(bottom, wi, &mut rng);
+                let (wos, wis) = match (wos, wis) {
+                    (Some(wos), Some(wis)) => (wos, wis),
+                    _ => continue,
+                };
+                pdf_sum += if top.flags().is_specular() {
+                    bottom.pdf(-wos.wi, wi)
+                } else if bottom.flags().is_specular() {
+                    top.pdf(wo, -wis.wi)
+                } else {
+                    (top.pdf(wo, -wis.wi) + bottom.pdf(-wos.wi, wi)) * 0.5
+                };
+            }
+        }
+
+        // Mixed with a uniform density, for what the estimate misses
+        let pdf = pdf_sum / self.samples as Float;
+        0.1 * (4.0 * PI).recip() + 0.9 * pdf
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+    use crate::material::{Conductor, Lambertian};
+
+    fn coated<'a>(
+        base: Box<dyn Bxdf + 'a>,
+        roughness: Float,
+        absorption: Vec3f,
+    ) -> LayeredBxdf<'a> {
+        LayeredBxdf {
+            top: RoughDielectricBxdf {
+                eta: 1.5,
+                distribution: TrowbridgeReitz::from_roughness(roughness),
+            },
+            bottom: base,
+            thickness: 0.01,
+            absorption,
+            max_depth: 10,
+            samples: 1,
+        }
+    }
+
+    #[test]
+    fn coated_diffuse_conserves_energy() {
+        let white = Lambertian {
+            albedo: vec3(1.0, 1.0, 1.0),
+        };
+        for &roughness in &[0.0, 0.5] {
+            let bxdf = coated(Box::new(&white), roughness, Vec3f::default());
+            for &wo in &[vec3(0.0, 0.0, 1.0), vec3(0.6, 0.0, 0.8)] {
+                let albedo = check_bxdf(&bxdf, wo);
+                // A white base under a clear coat loses little light, but
+                // to walks longer than the maximum depth, and to scattering
+                // between facets of a rough coat, which isn't modeled
+                assert!(albedo.x > 0.7, "{} {:?}", roughness, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn coating_absorbs_light() {
+        let white = Lambertian {
+            albedo: vec3(1.0, 1.0, 1.0),
+        };
+        let bxdf = coated(Box::new(white), 0.0, vec3(0.0, 20.0, 60.0));
+        let albedo = check_bxdf(&bxdf, vec3(0.0, 0.0, 1.0));
+        assert!(albedo.x > albedo.y && albedo.y > albedo.z, "{:?}", albedo);
+    }
+
+    #[test]
+    fn coated_bxdf_estimates_agree_with_samples() {
+        // Estimates of the value by `eval` and by sampling should agree on
+        // average, for a single direction `wo`
+        let base = Conductor::copper(0.4).bxdf();
+        let bxdf = coated(Box::new(base), 0.3, vec3(1.0, 1.0, 1.0));
+        let wo = vec3(0.0, 0.6, 0.8);
+
+        let mut rng = StdRng::seed_from_u64(111);
+        let n = 20_000;
+        let (mut sampled, mut evaluated) = (Vec3f::default(), Vec3f::default());
+        for _ in 0..n {
+            if let Some(s) = bxdf.sample(wo, rng.gen(), Point2f::new(rng.gen(), rng.gen())) {
+                sampled += s.f * (s.wi.z.abs() / s.pdf);
+            }
+            let wi = crate::sampling::cosine_sample_hemisphere(Point2f::new(rng.gen(), rng.gen()));
+            evaluated += bxdf.eval(wo, wi) * PI;
+        }
+        let (sampled, evaluated) = (
+            sampled * (n as Float).recip(),
+            evaluated * (n as Float).recip(),
+        );
+        assert!(
+            (sampled - evaluated).len() < 0.05 * sampled.len(),
+            "{:?} {:?}",
+            sampled,
+            evaluated
+        );
+    }
+
+    #[test]
+    fn coated_material_is_two_sided() {
+        let base = Lambertian {
+            albedo: vec3(0.5, 0.5, 0.5),
+        };
+        let bxdf = coated(Box::new(base), 0.3, Vec3f::default());
+        let (wo, wi) = (vec3(0.0, 0.6, 0.8), vec3(0.48, 0.0, 0.6).normalized());
+        assert_eq!(bxdf.eval(wo, wi), bxdf.eval(-wo, -wi));
+        assert!(!bxdf.flags().contains(BxdfFlags::TRANSMISSION));
+    }
+}
@@ -0,0 +1,298 @@
+use super::{Iridescence, Material};
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::fresnel::{fresnel_conductor, ThinFilm};
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::microfacet::TrowbridgeReitz;
+
+/// Rough metal: a surface of tiny mirrors with normals distributed by
+/// `TrowbridgeReitz`, reflecting light by the Fresnel equations for a
+/// complex index of refraction `eta + i k`.
+///
+/// Presets are measured indices for common metals, sampled at red, green and
+/// blue wavelengths of 650, 550 and 450nm.
+///
+/// ```
+/// use pbrt::material::Conductor;
+///
+/// let brushed_gold = Conductor::gold(0.3);
+/// assert!(brushed_gold.k.x > brushed_gold.k.z);
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Conductor<'a> {
+    pub eta: Vec3f,
+    pub k: Vec3f,
+    pub distribution: TrowbridgeReitz,
+    /// Thin film over the metal, like a layer of oxide or oil
+    pub iridescence: Option<Iridescence<'a>>,
+}
+
+impl<'a> Conductor<'a> {
+    pub fn new(eta: Vec3f, k: Vec3f, roughness: Float) -> Conductor<'a> {
+        Conductor {
+            eta,
+            k,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            iridescence: None,
+        }
+    }
+
+    pub fn gold(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(0.143, 0.374, 1.442),
+            vec3(3.983, 2.386, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(0.155, 0.117, 0.138),
+            vec3(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(0.200, 0.924, 1.102),
+            vec3(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(1.657, 0.880, 0.521),
+            vec3(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn chrome(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(3.107, 3.181, 2.323),
+            vec3(3.331, 3.329, 3.135),
+            roughness,
+        )
+    }
+
+    pub fn iron(roughness: Float) -> Conductor<'a> {
+        Conductor::new(
+            vec3(2.911, 2.950, 2.585),
+            vec3(3.089, 2.932, 2.767),
+            roughness,
+        )
+    }
+
+    /// BxDF of the bare metal, without a film over it.
+    pub fn bxdf(&self) -> ConductorBxdf {
+        ConductorBxdf {
+            eta: self.eta,
+            k: self.k,
+            distribution: self.distribution,
+            film: None,
+        }
+    }
+}
+
+impl Material for Conductor<'_> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        let film = self
+            .iridescence
+            .map(|iridescence| iridescence.film(hit, 1.0));
+        Some(Bsdf::new(
+            hit,
+            ConductorBxdf {
+                film,
+                ..self.bxdf()
+            },
+        ))
+    }
+}
+
+/// Microfacet reflection by a conductor, possibly under a thin film.
+#[derive(Copy, Clone, Debug)]
+pub struct ConductorBxdf {
+    pub eta: Vec3f,
+    pub k: Vec3f,
+    pub distribution: TrowbridgeReitz,
+    pub film: Option<ThinFilm>,
+}
+
+impl ConductorBxdf {
+    /// Reflectance for light arriving at an angle with cosine `cos_i` to the
+    /// normal of a microfacet.
+    fn fresnel(&self, cos_i: Float) -> Vec3f {
+        match self.film {
+            Some(film) => film.reflectance(cos_i, self.eta, self.k),
+            None => fresnel_conductor(cos_i, self.eta, self.k),
+        }
+    }
+}
+
+impl Bxdf for ConductorBxdf {
+    fn flags(&self) -> BxdfFlags {
+        if self.distribution.is_smooth() {
+            BxdfFlags::REFLECTION | BxdfFlags::SPECULAR
+        } else {
+            BxdfFlags::REFLECTION | BxdfFlags::GLOSSY
+        }
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        if self.distribution.is_smooth() || !same_hemisphere(wo, wi) {
+            return Vec3f::default();
+        }
+        let (cos_o, cos_i) = (wo.z.abs(), wi.z.abs());
+        let wm = wo + wi;
+        if cos_o == 0.0 || cos_i == 0.0 || wm.len_squared() == 0.0 {
+            return Vec3f::default();
+        }
+        let wm = wm.normalized();
+
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        self.fresnel(wo.dot(wm)) * (d * g / (4.0 * cos_o * cos_i))
+    }
+
+    fn sample(&self, wo: Vec3f, _uc: Float, u: Point2f) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            let wi = reflect(wo);
+            return Some(BsdfSample {
+                wi,
+                f: self.fresnel(wi.z) * wi.z.abs().recip(),
+                pdf: 1.0,
+                flags: self.flags(),
+                proportional: false,
+            });
+        }
+
+        if wo.z == 0.0 {
+            return None;
+        }
+        let wm = self.distribution.sample_wm(wo, u);
+        let wi = wm * (2.0 * wo.dot(wm)) - wo;
+        if !same_hemisphere(wo, wi) {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: self.pdf(wo, wi),
+            flags: self.flags(),
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        if self.distribution.is_smooth() || !same_hemisphere(wo, wi) {
+            return 0.0;
+        }
+        let wm = wo + wi;
+        if wm.len_squared() == 0.0 {
+            return 0.0;
+        }
+        // Facing the same way as the macro-surface normal
+        let wm = wm.normalized();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+        self.distribution.d_visible(wo, wm) / (4.0 * wo.dot(wm).abs())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn polished_conductor_is_a_mirror() {
+        let gold = Conductor::gold(0.0).bxdf();
+        let wo = vec3(0.0, 0.6, 0.8);
+        let s = gold.sample(wo, 0.5, Point2f::new(0.3, 0.3)).unwrap();
+        assert!((s.wi - vec3(0.0, -0.6, 0.8)).len() < 1.0e-6);
+        assert!(s.flags.contains(BxdfFlags::SPECULAR));
+        assert_eq!(gold.eval(wo, s.wi), Vec3f::default());
+
+        // Gold is yellow
+        let albedo = check_bxdf(&gold, wo);
+        assert!(
+            albedo.x > 0.9 && albedo.y > 0.7 && albedo.z < 0.5,
+            "{:?}",
+            albedo
+        );
+    }
+
+    #[test]
+    fn rough_conductor_conserves_energy() {
+        for &roughness in &[0.4, 0.7] {
+            let silver = Conductor::silver(roughness).bxdf();
+            for &wo in &[
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.6, 0.0, 0.8),
+                vec3(0.0, 0.96, 0.28),
+            ] {
+                let albedo = check_bxdf(&silver, wo);
+                // Light is only lost to multiple scattering between facets,
+                // which isn't modeled
+                assert!(albedo.x > 0.5, "{} {:?} {:?}", roughness, wo, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn oxide_film_tints_conductors() {
+        let thickness = 250.0;
+        let steel = Conductor {
+            iridescence: Some(Iridescence {
+                thickness: &thickness,
+                refraction_index: 2.3,
+            }),
+            ..Conductor::iron(0.4)
+        };
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &steel,
+        );
+        let bxdf = steel.bsdf(&hit).unwrap().into_bxdf();
+
+        let wo = vec3(0.0, 0.6, 0.8);
+        let bare = check_bxdf(&steel.bxdf(), wo);
+        let tinted = check_bxdf(bxdf.as_ref(), wo);
+        // Iron is grey, and blue under a film of its oxide
+        assert!(
+            bare.max_component() < 1.2 * bare.min_component(),
+            "{:?}",
+            bare
+        );
+        assert!(
+            tinted.max_component() > 1.2 * tinted.min_component(),
+            "{:?}",
+            tinted
+        );
+    }
+
+    #[test]
+    fn presets_are_plausible() {
+        let wo = vec3(0.0, 0.0, 1.0);
+        for conductor in &[
+            Conductor::gold(0.0),
+            Conductor::silver(0.0),
+            Conductor::copper(0.0),
+            Conductor::aluminium(0.0),
+            Conductor::chrome(0.0),
+            Conductor::iron(0.0),
+        ] {
+            let r = conductor.bxdf().fresnel(wo.z);
+            assert!(r.max_component() < 1.0 && r.x > 0.5, "{:?}", r);
+        }
+    }
+}
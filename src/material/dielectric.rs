@@ -1,48 +1,288 @@
-use super::Material;
+use super::{Iridescence, Material};
 
 use crate::prelude::*;
 
+use crate::bsdf::*;
+use crate::fresnel::{fresnel_dielectric, ThinFilm};
 use crate::geo::*;
 use crate::hit::HitStruct;
-
-pub struct Dielectric {
+use crate::medium::{relative_index, Medium};
+
+/// Smooth boundary between two transparent media, such as air and glass.
+///
+/// Light going through is absorbed along the way, by the Beer-Lambert law:
+/// coloured glass and liquids get darker the thicker they are. Thin-walled
+/// ones, like window panes or bubbles, are a single surface that light goes
+/// through without bending.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::Dielectric;
+///
+/// let glass = Dielectric::new(1.5);
+/// let green_glass = Dielectric {
+///     absorption: vec3(0.8, 0.1, 0.6),
+///     ..glass
+/// };
+/// let window_pane = Dielectric {
+///     thin: true,
+///     ..glass
+/// };
+/// ```
+///
+/// Thin-walled dielectrics with `iridescence` are all film, like soap
+/// bubbles.
+///
+/// ```
+/// use pbrt::material::*;
+///
+/// let bubble = Dielectric {
+///     thin: true,
+///     iridescence: Some(Iridescence {
+///         thickness: &400.0,
+///         refraction_index: 1.33,
+///     }),
+///     ..Dielectric::new(1.33)
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Dielectric<'a> {
     pub refraction_index: Float, // TODO: should `refraction_index` be a f64?
+    /// Fraction of light absorbed per unit of distance inside, for each
+    /// channel. Thin-walled dielectrics have no inside.
+    pub absorption: Vec3f,
+    /// Thin-walled: the surface is both boundaries of a thin slab.
+    pub thin: bool,
+    /// Priority of the inside over other media overlapping it. See
+    /// `MediumStack`.
+    pub priority: u32,
+    /// Thin film over the surface, like the coating of a lens
+    pub iridescence: Option<Iridescence<'a>>,
 }
 
-impl Material for Dielectric {
-    fn scatter(&self, r_in: &Ray, rec: &HitStruct<'_>, attenuation: &mut Vec3f) -> Option<Ray> {
-        use rand::prelude::*;
-        let mut rng = rand::thread_rng();
+impl<'a> Dielectric<'a> {
+    /// Clear and solid dielectric.
+    pub fn new(refraction_index: Float) -> Dielectric<'a> {
+        Dielectric {
+            refraction_index,
+            absorption: Vec3f::default(),
+            thin: false,
+            priority: 0,
+            iridescence: None,
+        }
+    }
+}
 
-        *attenuation = vec3(1.0, 1.0, 1.0);
+impl Material for Dielectric<'_> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.bsdf_between(hit, 1.0)
+    }
 
-        // incoming ray direction, normalized
-        let d = r_in.direction().normalized();
-        let n = rec.n;
+    fn interior(&self) -> Option<Medium> {
+        if self.thin {
+            return None;
+        }
+        Some(Medium {
+            refraction_index: self.refraction_index,
+            absorption: self.absorption,
+            scattering: Vec3f::default(),
+            priority: self.priority,
+        })
+    }
 
-        let etai_over_etat = if rec.front_face {
-            self.refraction_index.recip()
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        if self.thin {
+            // The film is the wall
+            let film = self
+                .iridescence
+                .map(|iridescence| iridescence.film(hit, outside_index));
+            return Some(Bsdf::new(
+                hit,
+                ThinDielectricBxdf {
+                    eta: self.refraction_index / outside_index,
+                    film,
+                },
+            ));
+        }
+        let eta = relative_index(hit, self.refraction_index, outside_index);
+        let above = if hit.front_face {
+            outside_index
         } else {
             self.refraction_index
         };
+        let film = self
+            .iridescence
+            .map(|iridescence| iridescence.film(hit, above));
+        Some(Bsdf::new(hit, DielectricBxdf { eta, film }))
+    }
+}
+
+/// Specular reflection and transmission, with Fresnel reflectance by
+/// Schlick's approximation.
+pub struct DielectricBxdf {
+    /// Refraction index below the surface relative to the one above it
+    pub eta: Float,
+    /// Thin film between both media, relative to the one above
+    pub film: Option<ThinFilm>,
+}
+
+impl DielectricBxdf {
+    /// Reflectance for light arriving at an angle with cosine `cos_i` to the
+    /// normal, from above if positive, and below if negative.
+    fn reflectance(&self, cos_i: Float) -> Vec3f {
+        let film = match self.film {
+            Some(film) => film,
+            None => {
+                let (cos_theta, etai_over_etat) = if cos_i > 0.0 {
+                    (cos_i.min(1.0), self.eta.recip())
+                } else {
+                    ((-cos_i).min(1.0), self.eta)
+                };
+                let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+                let r = if etai_over_etat * sin_theta > 1.0 {
+                    // Total internal reflection
+                    1.0
+                } else {
+                    schlick(cos_theta, etai_over_etat)
+                };
+                return vec3(r, r, r);
+            }
+        };
+        if cos_i > 0.0 {
+            if (1.0 - cos_i * cos_i) >= self.eta * self.eta {
+                // Total internal reflection, light doesn't get through
+                return vec3(1.0, 1.0, 1.0);
+            }
+            return film.reflectance(cos_i, vec3(self.eta, self.eta, self.eta), Vec3f::default());
+        }
+        // Films over lossless media reflect the same from both sides, so
+        // from above at the angle of the refracted light
+        let sin2_above = (1.0 - cos_i * cos_i) * self.eta * self.eta;
+        if sin2_above >= 1.0 {
+            return vec3(1.0, 1.0, 1.0);
+        }
+        let cos_above = (1.0 - sin2_above).sqrt();
+        film.reflectance(
+            cos_above,
+            vec3(self.eta, self.eta, self.eta),
+            Vec3f::default(),
+        )
+    }
+}
+
+impl Bxdf for DielectricBxdf {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::TRANSMISSION | BxdfFlags::SPECULAR
+    }
 
-        let cos_theta = Vec3f::dot(-d, n).min(1.0).max(-1.0);
+    fn eval(&self, _wo: Vec3f, _wi: Vec3f) -> Vec3f {
+        Vec3f::default()
+    }
 
-        let sin_theta = (1.0 - (cos_theta * cos_theta)).sqrt();
+    fn sample(&self, wo: Vec3f, uc: Float, _u: Point2f) -> Option<BsdfSample> {
+        // Normal on the side of `wo`
+        let (n, etai_over_etat) = if wo.z > 0.0 {
+            (vec3(0.0, 0.0, 1.0), self.eta.recip())
+        } else {
+            (vec3(0.0, 0.0, -1.0), self.eta)
+        };
 
-        let scattered = if etai_over_etat * sin_theta > 1.0 {
-            // Total internal reflection
-            reflect(d, n)
+        // Reflected in proportion to the average reflectance
+        let reflectance = self.reflectance(wo.z);
+        let r = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+
+        let (wi, f, pdf, flags) = if uc < r {
+            (
+                reflect(-wo, n),
+                reflectance,
+                r,
+                BxdfFlags::REFLECTION | BxdfFlags::SPECULAR,
+            )
         } else {
-            let reflect_prob = schlick(cos_theta, etai_over_etat);
-            if rng.gen::<Float>() < reflect_prob {
-                reflect(d, n)
-            } else {
-                refract(d, n, etai_over_etat)
+            (
+                refract(-wo, n, etai_over_etat),
+                vec3(1.0, 1.0, 1.0) - reflectance,
+                1.0 - r,
+                BxdfFlags::TRANSMISSION | BxdfFlags::SPECULAR,
+            )
+        };
+        Some(BsdfSample {
+            wi,
+            f: f * wi.z.abs().recip(),
+            pdf,
+            flags,
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, _wo: Vec3f, _wi: Vec3f) -> Float {
+        0.0
+    }
+}
+
+/// Specular reflection and transmission by a thin slab, bouncing between
+/// both sides of it. Transmitted light goes straight through.
+#[derive(Copy, Clone, Debug)]
+pub struct ThinDielectricBxdf {
+    /// Refraction index of the slab relative to the media around it
+    pub eta: Float,
+    /// Thin film making up the slab, instead of `eta`, relative to the
+    /// media around it
+    pub film: Option<ThinFilm>,
+}
+
+impl Bxdf for ThinDielectricBxdf {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::TRANSMISSION | BxdfFlags::SPECULAR
+    }
+
+    fn eval(&self, _wo: Vec3f, _wi: Vec3f) -> Vec3f {
+        Vec3f::default()
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, _u: Point2f) -> Option<BsdfSample> {
+        let reflectance = match self.film {
+            // Bounces inside the film interfere
+            Some(film) => film.reflectance(wo.z.abs(), vec3(1.0, 1.0, 1.0), Vec3f::default()),
+            None => {
+                let r = fresnel_dielectric(wo.z.abs(), self.eta);
+                // Sum of the geometric series of bounces inside the slab
+                let r = if r < 1.0 {
+                    r + (1.0 - r) * (1.0 - r) * r / (1.0 - r * r)
+                } else {
+                    r
+                };
+                vec3(r, r, r)
             }
         };
+        let r = (reflectance.x + reflectance.y + reflectance.z) / 3.0;
+
+        let (wi, f, pdf, flags) = if uc < r {
+            (
+                vec3(-wo.x, -wo.y, wo.z),
+                reflectance,
+                r,
+                BxdfFlags::REFLECTION | BxdfFlags::SPECULAR,
+            )
+        } else {
+            (
+                -wo,
+                vec3(1.0, 1.0, 1.0) - reflectance,
+                1.0 - r,
+                BxdfFlags::TRANSMISSION | BxdfFlags::SPECULAR,
+            )
+        };
+        Some(BsdfSample {
+            wi,
+            f: f * wi.z.abs().recip(),
+            pdf,
+            flags,
+            proportional: false,
+        })
+    }
 
-        Some(Ray::new(rec.p, scattered))
+    fn pdf(&self, _wo: Vec3f, _wi: Vec3f) -> Float {
+        0.0
     }
 }
 
@@ -78,7 +318,7 @@ fn refract(uv: Vec3f, n: Vec3f, etai_over_etat: Float) -> Vec3f {
 
     let cos_theta = (-uv).dot(n).min(1.0);
     assert!(
-        cos_theta >= 0.0 && cos_theta <= 1.0,
+        (0.0..=1.0).contains(&cos_theta),
         "refract: cos_theta = {}",
         cos_theta
     );
@@ -103,14 +343,194 @@ fn refract(uv: Vec3f, n: Vec3f, etai_over_etat: Float) -> Vec3f {
         r_out_parallel.len_squared(),
     );
 
-    let r_out = r_out_parallel + r_out_perp;
-
-    r_out
+    r_out_parallel + r_out_perp
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn dielectric_conserves_energy() {
+        for &eta in &[1.5, 1.5f32.recip()] {
+            let bxdf = DielectricBxdf { eta, film: None };
+            for &wo in &[
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.6, 0.0, 0.8),
+                vec3(0.0, 0.8, -0.6),
+            ] {
+                let albedo = check_bxdf(&bxdf, wo);
+                assert!(
+                    (albedo - vec3(1.0, 1.0, 1.0)).len() < 1.0e-4,
+                    "{:?}",
+                    albedo
+                );
+            }
+        }
+
+        // Mostly transmits at normal incidence
+        let bxdf = DielectricBxdf {
+            eta: 1.5,
+            film: None,
+        };
+        let s = bxdf
+            .sample(vec3(0.0, 0.0, 1.0), 0.5, Point2f::new(0.5, 0.5))
+            .unwrap();
+        assert!(s.flags.contains(BxdfFlags::TRANSMISSION));
+        assert!((s.wi - vec3(0.0, 0.0, -1.0)).len() < 1.0e-6);
+    }
+
+    #[test]
+    fn thin_dielectric_goes_straight_through() {
+        let bxdf = ThinDielectricBxdf {
+            eta: 1.5,
+            film: None,
+        };
+        for &wo in &[
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.6, 0.0, 0.8),
+            vec3(0.0, 0.8, -0.6),
+        ] {
+            let albedo = check_bxdf(&bxdf, wo);
+            assert!(
+                (albedo - vec3(1.0, 1.0, 1.0)).len() < 1.0e-4,
+                "{:?}",
+                albedo
+            );
+
+            let s = bxdf.sample(wo, 0.99, Point2f::new(0.5, 0.5)).unwrap();
+            assert!(s.flags.contains(BxdfFlags::TRANSMISSION));
+            assert!((s.wi + wo).len() < 1.0e-6);
+        }
+
+        // Both sides of the slab reflect
+        let r = fresnel_dielectric(1.0, 1.5);
+        let s = bxdf
+            .sample(vec3(0.0, 0.0, 1.0), 0.0, Point2f::new(0.5, 0.5))
+            .unwrap();
+        assert!((s.pdf - 2.0 * r / (1.0 + r)).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn dielectric_absorbs_along_the_inside() {
+        let glass = Dielectric {
+            absorption: vec3(0.0, 0.5, 2.0),
+            ..Dielectric::new(1.5)
+        };
+        let inside = glass.interior().unwrap();
+        assert_eq!(inside.refraction_index, 1.5);
+        let t = inside.transmittance(2.0);
+        assert!((t - vec3(1.0, (-1.0f32).exp(), (-4.0f32).exp())).len() < 1.0e-6);
+
+        // Thin walls have no inside, and absorb nothing
+        let pane = Dielectric {
+            thin: true,
+            ..glass
+        };
+        assert_eq!(pane.interior(), None);
+    }
+
+    #[test]
+    fn dielectric_refracts_relative_to_the_outside() {
+        let glass = Dielectric::new(1.5);
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let front = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &glass,
+        );
+        let back = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, -1.0),
+            &glass,
+        );
+
+        // In the frame of the side the ray comes from
+        let wo = vec3(0.6, 0.0, 0.8);
+        let sin_t = |bsdf: Option<Bsdf>| {
+            let bxdf = bsdf.unwrap().into_bxdf();
+            let s = bxdf.sample(wo, 0.99, Point2f::new(0.5, 0.5)).unwrap();
+            (1.0 - s.wi.z * s.wi.z).sqrt()
+        };
+        // From water into glass, and from glass into water
+        assert!((sin_t(glass.bsdf_between(&front, 1.33)) * 1.5 - 0.6 * 1.33).abs() < 1.0e-5);
+        assert!((sin_t(glass.bsdf_between(&back, 1.33)) * 1.33 - 0.6 * 1.5).abs() < 1.0e-5);
+        // Vacuum by default
+        assert!((sin_t(glass.bsdf(&front)) * 1.5 - 0.6).abs() < 1.0e-5);
+    }
+
+    #[test]
+    fn coated_dielectric_is_iridescent_and_lossless() {
+        let film = ThinFilm::new(300.0, 1.38, 1.0);
+        for &eta in &[1.5, 1.5f32.recip()] {
+            let bxdf = DielectricBxdf {
+                eta,
+                film: Some(film),
+            };
+            for &wo in &[
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.6, 0.0, 0.8),
+                vec3(0.0, 0.8, -0.6),
+            ] {
+                let albedo = check_bxdf(&bxdf, wo);
+                assert!(
+                    (albedo - vec3(1.0, 1.0, 1.0)).len() < 1.0e-2,
+                    "{:?}",
+                    albedo
+                );
+            }
+        }
+
+        // Reflections are coloured, and transmission the complementary colour
+        let bxdf = DielectricBxdf {
+            eta: 1.5,
+            film: Some(film),
+        };
+        let wo = vec3(0.0, 0.0, 1.0);
+        let r = bxdf.sample(wo, 0.0, Point2f::new(0.5, 0.5)).unwrap();
+        let t = bxdf.sample(wo, 0.99, Point2f::new(0.5, 0.5)).unwrap();
+        assert!(r.flags.contains(BxdfFlags::REFLECTION));
+        assert!(r.f.max_dimension() != t.f.max_dimension());
+        assert!((r.f + t.f - vec3(1.0, 1.0, 1.0)).len() < 1.0e-5);
+    }
+
+    #[test]
+    fn soap_bubble_reflects_colours() {
+        let thickness = 400.0;
+        let bubble = Dielectric {
+            thin: true,
+            iridescence: Some(Iridescence {
+                thickness: &thickness,
+                refraction_index: 1.33,
+            }),
+            ..Dielectric::new(1.33)
+        };
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &bubble,
+        );
+        let bxdf = bubble.bsdf(&hit).unwrap().into_bxdf();
+
+        let wo = vec3(0.0, 0.0, 1.0);
+        let albedo = check_bxdf(bxdf.as_ref(), wo);
+        assert!(
+            (albedo - vec3(1.0, 1.0, 1.0)).len() < 1.0e-2,
+            "{:?}",
+            albedo
+        );
+        let r = bxdf.sample(wo, 0.0, Point2f::new(0.5, 0.5)).unwrap();
+        assert!(r.flags.contains(BxdfFlags::REFLECTION));
+        assert!(r.f.max_component() > 2.0 * r.f.min_component(), "{:?}", r.f);
+    }
 
     #[test]
     fn test_refract_front() {
@@ -0,0 +1,39 @@
+use super::Material;
+
+use crate::bsdf::Bsdf;
+
+use crate::geo::*;
+use crate::hit::*;
+
+/// Emits light evenly in all directions from the front face of a surface, or
+/// from both faces if it's two-sided. Doesn't reflect anything.
+///
+/// Shapes with this material become area lights; see `light::AreaLight`.
+#[derive(Copy, Clone, Debug)]
+pub struct DiffuseLight {
+    /// Radiance emitted in every direction
+    pub radiance: Vec3f,
+    /// Emit from the back face too
+    pub two_sided: bool,
+}
+
+impl DiffuseLight {
+    /// Radiance leaving the front or the back face of the surface.
+    pub fn radiance(&self, front_face: bool) -> Vec3f {
+        if front_face || self.two_sided {
+            self.radiance
+        } else {
+            Vec3f::default()
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn bsdf(&self, _hit: &HitStruct) -> Option<Bsdf<'_>> {
+        None
+    }
+
+    fn emitted(&self, _ray: &Ray, hit: &HitStruct<'_>) -> Vec3f {
+        self.radiance(hit.front_face)
+    }
+}
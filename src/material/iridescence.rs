@@ -0,0 +1,49 @@
+use crate::prelude::*;
+
+use crate::fresnel::ThinFilm;
+use crate::hit::HitStruct;
+use crate::texture::Texture;
+
+/// Thin film over the surface of a `Dielectric` or a `Conductor`, like soap,
+/// oil or the coating of a lens, making it iridescent. See `ThinFilm`.
+///
+/// ```
+/// use pbrt::material::*;
+/// use pbrt::texture::Checkerboard;
+///
+/// let oil_slick = Iridescence {
+///     thickness: &Checkerboard {
+///         even: 300.0,
+///         odd: 450.0,
+///         frequency: 4.0,
+///     },
+///     refraction_index: 1.47,
+/// };
+/// let tarnished_steel = Conductor {
+///     iridescence: Some(oil_slick),
+///     ..Conductor::iron(0.1)
+/// };
+/// ```
+#[derive(Copy, Clone)]
+pub struct Iridescence<'a> {
+    /// Thickness of the film, in nanometers
+    pub thickness: &'a dyn Texture<Float>,
+    pub refraction_index: Float,
+}
+
+impl Iridescence<'_> {
+    /// The film at the point of hit, under a medium of refraction index
+    /// `above`.
+    pub fn film(&self, hit: &HitStruct, above: Float) -> ThinFilm {
+        let thickness = self.thickness.evaluate(hit).max(0.0);
+        ThinFilm::new(thickness, self.refraction_index, above)
+    }
+}
+
+impl std::fmt::Debug for Iridescence<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Iridescence")
+            .field("refraction_index", &self.refraction_index)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,32 +1,80 @@
 use super::Material;
 
-use crate::geo::{Ray, Vec3f};
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
 use crate::hit::HitStruct;
+use crate::sampling::*;
+
+use std::f32::consts::FRAC_1_PI;
 
+/// Ideal diffuse reflector: scatters light evenly in all directions.
 pub struct Lambertian {
     // TODO: albedo is a spectrum, not a vector.
     pub albedo: Vec3f,
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit: &HitStruct, attenuation: &mut Vec3f) -> Option<Ray> {
-        *attenuation = self.albedo;
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(hit, self))
+    }
+}
 
-        let HitStruct { p, n, .. } = *hit;
-        let n = n.normalized();
-        let d = n + random_in_unit_sphere();
-        Some(Ray::new(p, d))
+impl Bxdf for Lambertian {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        if same_hemisphere(wo, wi) {
+            self.albedo * FRAC_1_PI
+        } else {
+            Vec3f::default()
+        }
+    }
+
+    fn sample(&self, wo: Vec3f, _uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(u);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.albedo * FRAC_1_PI,
+            pdf: cosine_hemisphere_pdf(wi.z.abs()),
+            flags: self.flags(),
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        if same_hemisphere(wo, wi) {
+            cosine_hemisphere_pdf(wi.z.abs())
+        } else {
+            0.0
+        }
     }
 }
 
-/// Unbiased random direction
-fn random_in_unit_sphere() -> Vec3f {
-    use rand::prelude::*;
-    let mut rng = rand::thread_rng();
-    loop {
-        let v = Vec3f::new(rng.gen(), rng.gen(), rng.gen()) * 2.0 + Vec3f::new(-1.0, -1.0, -1.0);
-        if v.len_squared() <= 1.0 {
-            return v;
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn lambertian_reflects_its_albedo() {
+        let m = Lambertian {
+            albedo: vec3(0.2, 0.5, 0.8),
+        };
+        for &wo in &[
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.6, 0.0, 0.8),
+            vec3(0.0, 0.6, -0.8),
+        ] {
+            let albedo = check_bxdf(&m, wo);
+            assert!((albedo - m.albedo).len() < 1.0e-3, "{:?}", albedo);
         }
     }
 }
@@ -2,9 +2,17 @@ use super::Material;
 
 use crate::prelude::*;
 
-use crate::geo::{Ray, Vec3f};
+use crate::bsdf::*;
+use crate::geo::*;
 use crate::hit::HitStruct;
+use crate::sampling::*;
 
+/// Mirror, blurred by perturbing the reflected direction by a random vector
+/// of length `roughness`.
+///
+/// The blurry reflection has no density to evaluate, so it's treated as
+/// specular: lights are only seen in it by chance. See `Conductor` for
+/// physically-based metals.
 pub struct Metal {
     // TODO: albedo is a spectrum, not a vector.
     pub albedo: Vec3f,
@@ -12,30 +20,63 @@ pub struct Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit: &HitStruct, attenuation: &mut Vec3f) -> Option<Ray> {
-        let reflected = reflect(ray.direction().normalized(), hit.n);
-        let scattered = Ray::new(hit.p, reflected + random_in_unit_sphere() * self.roughness);
-        *attenuation = self.albedo;
-        if reflected.dot(hit.n) > 0.0 {
-            Some(scattered)
-        } else {
-            None
-        }
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(hit, self))
     }
 }
 
-fn reflect(v: Vec3f, n: Vec3f) -> Vec3f {
-    v - n * v.dot(n) * 2.0
-}
+impl Bxdf for Metal {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::SPECULAR
+    }
 
-/// Unbiased random direction
-fn random_in_unit_sphere() -> Vec3f {
-    use rand::prelude::*;
-    let mut rng = rand::thread_rng();
-    loop {
-        let v = Vec3f::new(rng.gen(), rng.gen(), rng.gen()) * 2.0 + Vec3f::new(-1.0, -1.0, -1.0);
-        if v.len_squared() <= 1.0 {
-            return v;
+    fn eval(&self, _wo: Vec3f, _wi: Vec3f) -> Vec3f {
+        Vec3f::default()
+    }
+
+    fn sample(&self, wo: Vec3f, _uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let wi = (reflect(wo) + uniform_sample_sphere(u) * self.roughness).normalized();
+        // Perturbed below the surface, absorbed
+        if !same_hemisphere(wo, wi) {
+            return None;
         }
+        Some(BsdfSample {
+            wi,
+            f: self.albedo * wi.z.abs().recip(),
+            pdf: 1.0,
+            flags: self.flags(),
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, _wo: Vec3f, _wi: Vec3f) -> Float {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn polished_metal_is_a_mirror() {
+        let m = Metal {
+            albedo: vec3(0.9, 0.8, 0.5),
+            roughness: 0.0,
+        };
+        let wo = vec3(0.6, 0.0, 0.8);
+        let s = m.sample(wo, 0.5, Point2f::new(0.3, 0.3)).unwrap();
+        assert!((s.wi - vec3(-0.6, 0.0, 0.8)).len() < 1.0e-6);
+        assert!((check_bxdf(&m, wo) - m.albedo).len() < 1.0e-3);
+
+        // Rough metal loses some light below the surface at grazing angles
+        let m = Metal {
+            roughness: 0.5,
+            ..m
+        };
+        let albedo = check_bxdf(&m, vec3(0.96, 0.0, 0.28));
+        assert!(albedo.x < m.albedo.x && albedo.x > 0.5 * m.albedo.x);
     }
 }
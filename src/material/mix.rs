@@ -0,0 +1,313 @@
+use super::{bxdf_or_black, Material};
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::Medium;
+use crate::texture::Texture;
+
+/// Blend of two materials, such as rusty metal or dirty plastic: `b` in
+/// proportion to `weight`, and `a` for the rest.
+///
+/// Light transmitted by either goes into its `interior`, if any. Both
+/// should have the same one, or only one of them: where they differ, the
+/// inside is that of `a`.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::*;
+/// use pbrt::texture::Checkerboard;
+///
+/// let metal = Conductor::iron(0.2);
+/// let rust = Lambertian {
+///     albedo: vec3(0.4, 0.15, 0.05),
+/// };
+/// let rusty_metal = MixMaterial {
+///     a: &metal,
+///     b: &rust,
+///     weight: &0.3,
+/// };
+/// let patches = Checkerboard {
+///     even: 0.1,
+///     odd: 0.8,
+///     frequency: 10.0,
+/// };
+/// let patchy_rust = MixMaterial {
+///     weight: &patches,
+///     ..rusty_metal
+/// };
+/// ```
+#[derive(Copy, Clone)]
+pub struct MixMaterial<'a> {
+    pub a: &'a dyn Material,
+    pub b: &'a dyn Material,
+    /// Proportion of `b`, in `[0, 1]`
+    pub weight: &'a dyn Texture<Float>,
+}
+
+impl<'a> Material for MixMaterial<'a> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.bsdf_between(hit, 1.0)
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitStruct) -> Vec3f {
+        let weight = self.weight.evaluate(hit).clamp(0.0, 1.0);
+        self.a.emitted(ray, hit) * (1.0 - weight) + self.b.emitted(ray, hit) * weight
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.a.interior().or_else(|| self.b.interior())
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        let weight = self.weight.evaluate(hit).clamp(0.0, 1.0);
+        if weight == 0.0 {
+            return self.a.bsdf_between(hit, outside_index);
+        } else if weight == 1.0 {
+            return self.b.bsdf_between(hit, outside_index);
+        }
+        Some(Bsdf::new(
+            hit,
+            MixBxdf {
+                a: bxdf_or_black(self.a.bsdf_between(hit, outside_index), hit),
+                b: bxdf_or_black(self.b.bsdf_between(hit, outside_index), hit),
+                weight,
+            },
+        ))
+    }
+}
+
+/// Blend of two BxDFs, `b` in proportion to `weight`. Both are evaluated,
+/// and either is sampled.
+pub struct MixBxdf<'a> {
+    pub a: Box<dyn Bxdf + 'a>,
+    pub b: Box<dyn Bxdf + 'a>,
+    pub weight: Float,
+}
+
+impl<'a> Bxdf for MixBxdf<'a> {
+    fn flags(&self) -> BxdfFlags {
+        self.a.flags() | self.b.flags()
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        self.a.eval(wo, wi) * (1.0 - self.weight) + self.b.eval(wo, wi) * self.weight
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        // Pick one, and reuse `uc` for it
+        let (chosen, p, uc) = if uc < self.weight {
+            (&self.b, self.weight, uc / self.weight)
+        } else {
+            (
+                &self.a,
+                1.0 - self.weight,
+                (uc - self.weight) / (1.0 - self.weight),
+            )
+        };
+        let s = chosen.sample(wo, uc.min(1.0 - Float::EPSILON), u)?;
+
+        if s.flags.contains(BxdfFlags::SPECULAR) || s.proportional {
+            // The other one can't have sampled this direction
+            return Some(BsdfSample {
+                f: s.f * p,
+                pdf: s.pdf * p,
+                ..s
+            });
+        }
+        let pdf = self.pdf(wo, s.wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            f: self.eval(wo, s.wi),
+            pdf,
+            ..s
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        self.a.pdf(wo, wi) * (1.0 - self.weight) + self.b.pdf(wo, wi) * self.weight
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+    use crate::material::*;
+    use crate::medium::MediumStack;
+    use crate::texture::Checkerboard;
+
+    #[test]
+    fn mix_blends_albedos() {
+        let red = Lambertian {
+            albedo: vec3(0.8, 0.0, 0.0),
+        };
+        let blue = Lambertian {
+            albedo: vec3(0.0, 0.0, 0.8),
+        };
+        let bxdf = MixBxdf {
+            a: Box::new(&red),
+            b: Box::new(&blue),
+            weight: 0.25,
+        };
+        let albedo = check_bxdf(&bxdf, vec3(0.0, 0.6, 0.8));
+        assert!(
+            (albedo - vec3(0.6, 0.0, 0.2)).len() < 1.0e-3,
+            "{:?}",
+            albedo
+        );
+    }
+
+    #[test]
+    fn mix_works_with_specular_materials() {
+        let glass = DielectricBxdf {
+            eta: 1.5,
+            film: None,
+        };
+        let gold = Conductor::gold(0.4).bxdf();
+        let bxdf = MixBxdf {
+            a: Box::new(glass),
+            b: Box::new(&gold),
+            weight: 0.5,
+        };
+        assert!(!bxdf.flags().is_specular());
+        let glass_albedo = check_bxdf(
+            &DielectricBxdf {
+                eta: 1.5,
+                film: None,
+            },
+            vec3(0.0, 0.6, 0.8),
+        );
+        let gold_albedo = check_bxdf(&gold, vec3(0.0, 0.6, 0.8));
+        let albedo = check_bxdf(&bxdf, vec3(0.0, 0.6, 0.8));
+        let expected = (glass_albedo + gold_albedo) * 0.5;
+        assert!(
+            (albedo - expected).len() < 0.02,
+            "{:?} {:?}",
+            albedo,
+            expected
+        );
+    }
+
+    #[test]
+    fn mix_follows_its_weight_texture() {
+        let red = Lambertian {
+            albedo: vec3(0.8, 0.0, 0.0),
+        };
+        let checkerboard = Checkerboard {
+            even: 0.0,
+            odd: 1.0,
+            frequency: 2.0,
+        };
+        let mix = MixMaterial {
+            a: &red,
+            b: &NullMaterial,
+            weight: &checkerboard,
+        };
+
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let zero = Vec3f::default();
+        let hit = |u, v| {
+            HitStruct::new(1.0, point3(0.0, 0.0, 0.0), &ray, vec3(0.0, 0.0, 1.0), &mix)
+                .with_parameterization(Point2f::new(u, v), zero, zero, zero, zero)
+        };
+        assert!(mix.bsdf(&hit(0.25, 0.25)).is_some());
+        assert!(mix.bsdf(&hit(0.75, 0.25)).is_none());
+    }
+
+    #[test]
+    fn mix_keeps_perturbed_normals() {
+        let white = Lambertian {
+            albedo: vec3(0.8, 0.8, 0.8),
+        };
+        let mapped = NormalMap {
+            material: &white,
+            normals: &vec3(0.8, 0.5, 0.9),
+        };
+        let mix = MixMaterial {
+            a: &mapped,
+            b: &white,
+            weight: &0.5,
+        };
+
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let zero = Vec3f::default();
+        let hit = HitStruct::new(1.0, point3(0.0, 0.0, 0.0), &ray, vec3(0.0, 0.0, 1.0), &mix)
+            .with_parameterization(
+                Point2f::new(0.5, 0.5),
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+                zero,
+                zero,
+            );
+        let (a, b) = (mapped.bsdf(&hit).unwrap(), white.bsdf(&hit).unwrap());
+        let bsdf = mix.bsdf(&hit).unwrap();
+        let wo = vec3(0.0, 0.0, 1.0);
+        for &wi in &[
+            vec3(0.6, 0.0, 0.8),
+            vec3(-0.6, 0.0, 0.8),
+            vec3(0.0, 0.8, 0.6),
+        ] {
+            let f = bsdf.eval(wo, wi) * bsdf.abs_cos(wi);
+            let expected = (a.eval(wo, wi) * a.abs_cos(wi) + b.eval(wo, wi) * b.abs_cos(wi)) * 0.5;
+            assert!((f - expected).len() < 1.0e-5, "{:?} {:?}", f, expected);
+        }
+    }
+
+    #[test]
+    fn mixed_glass_holds_water() {
+        let clear = Dielectric {
+            priority: 2,
+            ..Dielectric::new(1.5)
+        };
+        let tinted = Dielectric {
+            absorption: vec3(0.0, 0.5, 2.0),
+            ..clear
+        };
+        let glass = MixMaterial {
+            a: &clear,
+            b: &tinted,
+            weight: &0.5,
+        };
+        let water = Dielectric {
+            priority: 1,
+            ..Dielectric::new(1.33)
+        };
+        let (g, w) = (glass.interior().unwrap(), water.interior().unwrap());
+        assert_eq!(g, clear.interior().unwrap());
+
+        // Into the glass from the water it's in
+        let mut media = MediumStack::new();
+        media.enter(&water, w);
+        let outside_index = media.outside_index(&glass, g, true);
+        assert_eq!(outside_index, Some(1.33));
+        media.enter(&glass, g);
+        assert_eq!(media.current(), Some(g));
+
+        // Refracting relative to the water, whichever glass is sampled
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let front = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &glass,
+        );
+        let bxdf = glass
+            .bsdf_between(&front, outside_index.unwrap())
+            .unwrap()
+            .into_bxdf();
+        let wo = vec3(0.6, 0.0, 0.8);
+        for &uc in &[0.2, 0.99] {
+            let s = bxdf.sample(wo, uc, Point2f::new(0.5, 0.5)).unwrap();
+            assert!(s.flags.contains(BxdfFlags::TRANSMISSION));
+            let sin_t = (1.0 - s.wi.z * s.wi.z).sqrt();
+            assert!((sin_t * 1.5 - 0.6 * 1.33).abs() < 1.0e-5);
+        }
+    }
+}
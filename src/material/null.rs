@@ -1,12 +1,13 @@
 use super::Material;
 
-use crate::geo::*;
+use crate::bsdf::Bsdf;
 use crate::hit::*;
 
+/// Absorbs all light.
 pub struct NullMaterial;
 
 impl Material for NullMaterial {
-    fn scatter(&self, _ray: &Ray, _hit: &HitStruct<'_>, _attenuation: &mut Vec3f) -> Option<Ray> {
+    fn bsdf(&self, _hit: &HitStruct) -> Option<Bsdf<'_>> {
         None
     }
 }
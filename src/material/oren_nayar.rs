@@ -0,0 +1,141 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::sampling::*;
+
+use std::f32::consts::FRAC_1_PI;
+
+/// Rough diffuse reflector, made of tiny Lambertian facets: clay, concrete,
+/// the moon (Oren and Nayar, "Generalization of Lambert's Reflectance
+/// Model", 1994).
+///
+/// Facets shadow each other, so it's darker than `Lambertian` seen from the
+/// light, and brighter seen from the side opposite to it.
+pub struct OrenNayar {
+    // TODO: albedo is a spectrum, not a vector.
+    pub albedo: Vec3f,
+    /// Standard deviation of the angle of facets, in degrees. A `sigma` of
+    /// zero is `Lambertian`.
+    pub sigma: Float,
+}
+
+impl OrenNayar {
+    /// Coefficients of the qualitative model, `A` and `B`.
+    fn coefficients(&self) -> (Float, Float) {
+        let sigma = self.sigma.to_radians();
+        let sigma2 = sigma * sigma;
+        (
+            1.0 - sigma2 / (2.0 * (sigma2 + 0.33)),
+            0.45 * sigma2 / (sigma2 + 0.09),
+        )
+    }
+}
+
+impl Material for OrenNayar {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(hit, self))
+    }
+}
+
+impl Bxdf for OrenNayar {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        if !same_hemisphere(wo, wi) {
+            return Vec3f::default();
+        }
+        let (a, b) = self.coefficients();
+
+        let sin_theta_i = (1.0 - wi.z * wi.z).max(0.0).sqrt();
+        let sin_theta_o = (1.0 - wo.z * wo.z).max(0.0).sqrt();
+
+        // cos(φi - φo), from the projections of the directions on the plane
+        let max_cos = if sin_theta_i > 1.0e-4 && sin_theta_o > 1.0e-4 {
+            ((wi.x * wo.x + wi.y * wo.y) / (sin_theta_i * sin_theta_o)).max(0.0)
+        } else {
+            0.0
+        };
+
+        // sin α tan β, with α the larger angle to the normal and β the
+        // smaller one
+        let (sin_alpha, tan_beta) = if wi.z.abs() > wo.z.abs() {
+            (sin_theta_o, sin_theta_i / wi.z.abs())
+        } else {
+            (sin_theta_i, sin_theta_o / wo.z.abs())
+        };
+
+        self.albedo * (FRAC_1_PI * (a + b * max_cos * sin_alpha * tan_beta))
+    }
+
+    fn sample(&self, wo: Vec3f, _uc: Float, u: Point2f) -> Option<BsdfSample> {
+        let mut wi = cosine_sample_hemisphere(u);
+        if wo.z < 0.0 {
+            wi.z = -wi.z;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf: cosine_hemisphere_pdf(wi.z.abs()),
+            flags: self.flags(),
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        if same_hemisphere(wo, wi) {
+            cosine_hemisphere_pdf(wi.z.abs())
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+    use crate::material::Lambertian;
+
+    use rand::prelude::*;
+
+    #[test]
+    fn smooth_oren_nayar_is_lambertian() {
+        let albedo = vec3(0.2, 0.5, 0.8);
+        let m = OrenNayar { albedo, sigma: 0.0 };
+        let lambertian = Lambertian { albedo };
+
+        let mut rng = StdRng::seed_from_u64(101);
+        for _ in 0..100 {
+            let wo = cosine_sample_hemisphere(Point2f::new(rng.gen(), rng.gen()));
+            let wi = cosine_sample_hemisphere(Point2f::new(rng.gen(), rng.gen()));
+            assert!((m.eval(wo, wi) - lambertian.eval(wo, wi)).len() < 1.0e-6);
+            assert_eq!(m.pdf(wo, wi), lambertian.pdf(wo, wi));
+        }
+    }
+
+    #[test]
+    fn rough_oren_nayar_loses_energy() {
+        let albedo = vec3(0.8, 0.8, 0.8);
+        let mut previous = albedo.x;
+        for &sigma in &[10.0, 20.0, 40.0] {
+            let m = OrenNayar { albedo, sigma };
+            let reflected = check_bxdf(&m, vec3(0.0, 0.0, 1.0));
+            // Facets shadow each other more and more
+            assert!(reflected.x < previous, "{} {:?}", sigma, reflected);
+            assert!(reflected.x > 0.7 * albedo.x, "{} {:?}", sigma, reflected);
+            previous = reflected.x;
+
+            // And it's bright back towards a grazing light
+            let back = m.eval(vec3(0.96, 0.0, 0.28), vec3(0.96, 0.0, 0.28));
+            assert!(back.x > albedo.x * FRAC_1_PI);
+            check_bxdf(&m, vec3(0.96, 0.0, 0.28));
+        }
+    }
+}
@@ -0,0 +1,510 @@
+use super::Material;
+use super::RoughDielectricBxdf;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::vec3::lerp;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::{relative_index, Medium};
+use crate::microfacet::TrowbridgeReitz;
+use crate::sampling::*;
+
+use std::f32::consts::{FRAC_1_PI, PI};
+
+/// Disney's principled "uber" material, the one most assets are authored
+/// for (Burley, "Physically Based Shading at Disney", 2012, and "Extending
+/// the Disney BRDF to a BSDF with Integrated Subsurface Scattering", 2015).
+///
+/// All parameters but `base_color` and `ior` are in `[0, 1]`. It's a mix of
+/// lobes:
+///
+/// -   diffuse with retro-reflection at grazing angles, plus `sheen`, for
+///     dielectrics;
+/// -   GGX specular reflection, colored by `base_color` for metals;
+/// -   rough glass, for `transmission`;
+/// -   a second, weaker specular layer for `clearcoat`.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::Principled;
+///
+/// let car_paint = Principled {
+///     base_color: vec3(0.6, 0.05, 0.05),
+///     roughness: 0.4,
+///     clearcoat: 1.0,
+///     ..Principled::default()
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Principled {
+    pub base_color: Vec3f,
+    /// Conductor rather than dielectric
+    pub metallic: Float,
+    pub roughness: Float,
+    /// Strength of the specular reflection of dielectrics; 0.5 is that of an
+    /// index of refraction of 1.5
+    pub specular: Float,
+    /// Tints the specular reflection of dielectrics with `base_color`
+    pub specular_tint: Float,
+    /// Extra grazing reflection, for cloth
+    pub sheen: Float,
+    pub clearcoat: Float,
+    /// Smoothness of the clearcoat
+    pub clearcoat_gloss: Float,
+    /// Transparent rather than opaque, for dielectrics
+    pub transmission: Float,
+    /// Index of refraction, for `transmission`
+    pub ior: Float,
+    /// Stretches specular highlights along the tangent
+    pub anisotropy: Float,
+    /// Priority of the inside over other media overlapping it, for
+    /// `transmission`. See `MediumStack`.
+    pub priority: u32,
+}
+
+impl Default for Principled {
+    fn default() -> Principled {
+        Principled {
+            base_color: vec3(0.8, 0.8, 0.8),
+            metallic: 0.0,
+            roughness: 0.5,
+            specular: 0.5,
+            specular_tint: 0.0,
+            sheen: 0.0,
+            clearcoat: 0.0,
+            clearcoat_gloss: 1.0,
+            transmission: 0.0,
+            ior: 1.5,
+            anisotropy: 0.0,
+            priority: 0,
+        }
+    }
+}
+
+impl Material for Principled {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.bsdf_between(hit, 1.0)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        // Opaque unless some light goes through
+        if self.transmission == 0.0 || self.metallic == 1.0 {
+            return None;
+        }
+        Some(Medium {
+            refraction_index: self.ior,
+            absorption: Vec3f::default(),
+            scattering: Vec3f::default(),
+            priority: self.priority,
+        })
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        let eta = relative_index(hit, self.ior, outside_index);
+        Some(Bsdf::new(hit, PrincipledBxdf::new(self, eta)))
+    }
+}
+
+/// Lobes of `Principled`, with their weights and the probabilities of
+/// sampling them.
+pub struct PrincipledBxdf<'a> {
+    material: &'a Principled,
+    distribution: TrowbridgeReitz,
+    /// Color of the specular reflection at normal incidence
+    specular_color: Vec3f,
+    glass: RoughDielectricBxdf,
+    /// Weights of the diffuse, specular, glass and clearcoat lobes
+    weights: [Float; 4],
+    /// Probabilities of sampling each lobe
+    probabilities: [Float; 4],
+}
+
+const DIFFUSE: usize = 0;
+const SPECULAR: usize = 1;
+const GLASS: usize = 2;
+const CLEARCOAT: usize = 3;
+
+impl<'a> PrincipledBxdf<'a> {
+    /// Lobes of `material`, where the index of refraction below the surface
+    /// relative to the one above it is `eta`.
+    pub fn new(material: &'a Principled, eta: Float) -> PrincipledBxdf<'a> {
+        let m = material;
+        let distribution = TrowbridgeReitz::from_anisotropic_roughness(m.roughness, m.anisotropy);
+        // Too smooth surfaces would need specular lobes
+        let distribution = TrowbridgeReitz::new(
+            distribution.alpha_x.max(1.0e-3),
+            distribution.alpha_y.max(1.0e-3),
+        );
+
+        let tint = tint(m.base_color);
+        let dielectric_specular =
+            lerp(vec3(1.0, 1.0, 1.0), tint, m.specular_tint) * (0.08 * m.specular);
+        let specular_color = lerp(dielectric_specular, m.base_color, m.metallic);
+
+        let dielectric = 1.0 - m.metallic;
+        let weights = [
+            dielectric * (1.0 - m.transmission),
+            1.0 - dielectric * m.transmission,
+            dielectric * m.transmission,
+            0.25 * m.clearcoat,
+        ];
+        let total: Float = weights.iter().sum();
+        let mut probabilities = [0.0; 4];
+        if total > 0.0 {
+            for (p, w) in probabilities.iter_mut().zip(&weights) {
+                *p = w / total;
+            }
+        }
+
+        PrincipledBxdf {
+            material,
+            distribution,
+            specular_color,
+            glass: RoughDielectricBxdf { eta, distribution },
+            weights,
+            probabilities,
+        }
+    }
+
+    /// Diffuse and sheen, for `wo` and `wi` in the same hemisphere.
+    fn eval_diffuse(&self, wo: Vec3f, wi: Vec3f, cos_d: Float) -> Vec3f {
+        let m = self.material;
+        let (fo, fi) = (schlick_weight(wo.z.abs()), schlick_weight(wi.z.abs()));
+        let fd = cos_d * cos_d;
+
+        // Lambertian, darker at grazing angles, but reflecting back towards
+        // the light on rough surfaces
+        let lambert = (1.0 - 0.5 * fo) * (1.0 - 0.5 * fi);
+        let rr = 2.0 * m.roughness * fd;
+        let retro = rr * (fo + fi + fo * fi * (rr - 1.0));
+        let diffuse = m.base_color * ((lambert + retro) * FRAC_1_PI);
+
+        let sheen = tint(m.base_color) * (m.sheen * schlick_weight(cos_d));
+        diffuse + sheen
+    }
+
+    /// Specular reflection, for `wo` and `wi` in the same hemisphere with
+    /// half vector `wh`.
+    fn eval_specular(&self, wo: Vec3f, wi: Vec3f, wh: Vec3f) -> Vec3f {
+        let d = self.distribution.d(wh);
+        let g = self.distribution.g(wo, wi);
+        let fh = schlick_weight(wi.dot(wh).abs());
+        let f = lerp(self.specular_color, vec3(1.0, 1.0, 1.0), fh);
+        f * (d * g / (4.0 * wo.z * wi.z).abs())
+    }
+
+    fn clearcoat_alpha(&self) -> Float {
+        let gloss = self.material.clearcoat_gloss;
+        (1.0 - gloss) * 0.1 + gloss * 0.001
+    }
+
+    /// Clearcoat reflection, for `wo` and `wi` in the same hemisphere with
+    /// half vector `wh`.
+    fn eval_clearcoat(&self, wo: Vec3f, wi: Vec3f, wh: Vec3f) -> Float {
+        let d = gtr1(wh.z.abs(), self.clearcoat_alpha());
+        let f = 0.04 + 0.96 * schlick_weight(wi.dot(wh).abs());
+        let g = smith_g_ggx(wo.z.abs(), 0.25) * smith_g_ggx(wi.z.abs(), 0.25);
+        d * f * g * 0.25
+    }
+
+    fn pdf_clearcoat(&self, wo: Vec3f, wh: Vec3f) -> Float {
+        gtr1(wh.z.abs(), self.clearcoat_alpha()) * wh.z.abs() / (4.0 * wo.dot(wh).abs())
+    }
+}
+
+impl<'a> Bxdf for PrincipledBxdf<'a> {
+    fn flags(&self) -> BxdfFlags {
+        let mut flags = BxdfFlags::REFLECTION | BxdfFlags::GLOSSY;
+        if self.weights[DIFFUSE] > 0.0 {
+            flags = flags | BxdfFlags::DIFFUSE;
+        }
+        if self.weights[GLASS] > 0.0 {
+            flags = flags | BxdfFlags::TRANSMISSION;
+        }
+        flags
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        let mut f = Vec3f::default();
+        if self.weights[GLASS] > 0.0 {
+            f += self.glass.eval(wo, wi) * self.material.base_color * self.weights[GLASS];
+        }
+        if !same_hemisphere(wo, wi) {
+            return f;
+        }
+        let wh = wo + wi;
+        if wh.len_squared() == 0.0 {
+            return f;
+        }
+        let wh = wh.normalized();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+
+        if self.weights[DIFFUSE] > 0.0 {
+            f += self.eval_diffuse(wo, wi, wi.dot(wh)) * self.weights[DIFFUSE];
+        }
+        if self.weights[SPECULAR] > 0.0 {
+            f += self.eval_specular(wo, wi, wh) * self.weights[SPECULAR];
+        }
+        if self.weights[CLEARCOAT] > 0.0 {
+            let c = self.eval_clearcoat(wo, wi, wh) * self.material.clearcoat;
+            f += vec3(c, c, c);
+        }
+        f
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        // Pick a lobe, and reuse `uc` for it
+        let mut lobe = DIFFUSE;
+        let mut uc = uc;
+        for (i, &p) in self.probabilities.iter().enumerate() {
+            lobe = i;
+            if uc < p || i == self.probabilities.len() - 1 {
+                uc = (uc / p).min(1.0 - Float::EPSILON);
+                break;
+            }
+            uc -= p;
+        }
+        if self.probabilities[lobe] == 0.0 {
+            return None;
+        }
+
+        let wi = match lobe {
+            DIFFUSE => {
+                let wi = cosine_sample_hemisphere(u);
+                if wo.z < 0.0 {
+                    -wi
+                } else {
+                    wi
+                }
+            }
+            SPECULAR => {
+                let wh = self.distribution.sample_wm(wo, u);
+                wh * (2.0 * wo.dot(wh)) - wo
+            }
+            GLASS => self.glass.sample(wo, uc, u)?.wi,
+            _ => {
+                let alpha2 = self.clearcoat_alpha() * self.clearcoat_alpha();
+                let cos_theta = ((1.0 - alpha2.powf(1.0 - u.x)) / (1.0 - alpha2))
+                    .max(0.0)
+                    .sqrt();
+                let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+                let (sin_phi, cos_phi) = (2.0 * PI * u.y).sin_cos();
+                let wh = vec3(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+                let wh = if wo.z < 0.0 { -wh } else { wh };
+                wh * (2.0 * wo.dot(wh)) - wo
+            }
+        };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        let flags = if !same_hemisphere(wo, wi) {
+            BxdfFlags::TRANSMISSION | BxdfFlags::GLOSSY
+        } else if lobe == DIFFUSE {
+            BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE
+        } else {
+            BxdfFlags::REFLECTION | BxdfFlags::GLOSSY
+        };
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            flags,
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        let p = &self.probabilities;
+        let mut pdf = 0.0;
+        if p[GLASS] > 0.0 {
+            pdf += p[GLASS] * self.glass.pdf(wo, wi);
+        }
+        if !same_hemisphere(wo, wi) {
+            return pdf;
+        }
+        let wh = wo + wi;
+        if wh.len_squared() == 0.0 {
+            return pdf;
+        }
+        let wh = wh.normalized();
+        let wh = if wh.z < 0.0 { -wh } else { wh };
+
+        if p[DIFFUSE] > 0.0 {
+            pdf += p[DIFFUSE] * cosine_hemisphere_pdf(wi.z.abs());
+        }
+        if p[SPECULAR] > 0.0 {
+            pdf += p[SPECULAR] * self.distribution.d_visible(wo, wh) / (4.0 * wo.dot(wh).abs());
+        }
+        if p[CLEARCOAT] > 0.0 {
+            pdf += p[CLEARCOAT] * self.pdf_clearcoat(wo, wh);
+        }
+        pdf
+    }
+}
+
+/// `(1 - cos θ)⁵`, the angular part of Schlick's approximation of Fresnel
+/// reflectance.
+fn schlick_weight(cos_theta: Float) -> Float {
+    let m = (1.0 - cos_theta).clamp(0.0, 1.0);
+    let m2 = m * m;
+    m2 * m2 * m
+}
+
+/// Hue and saturation of `color`, at unit luminance.
+fn tint(color: Vec3f) -> Vec3f {
+    let luminance = color.dot(vec3(0.2126, 0.7152, 0.0722));
+    if luminance > 0.0 {
+        color * luminance.recip()
+    } else {
+        vec3(1.0, 1.0, 1.0)
+    }
+}
+
+/// Generalized Trowbridge-Reitz distribution with `γ = 1`, with the longer
+/// tail used for the clearcoat.
+fn gtr1(cos_theta: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    (alpha2 - 1.0) / (PI * alpha2.ln() * (1.0 + (alpha2 - 1.0) * cos_theta * cos_theta))
+}
+
+/// Smith masking for GGX, divided by `cos θ`.
+fn smith_g_ggx(cos_theta: Float, alpha: Float) -> Float {
+    let alpha2 = alpha * alpha;
+    let cos2 = cos_theta * cos_theta;
+    (cos_theta + (alpha2 + cos2 - alpha2 * cos2).sqrt()).recip()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    fn directions() -> [Vec3f; 3] {
+        [
+            vec3(0.0, 0.0, 1.0),
+            vec3(0.6, 0.0, 0.8),
+            vec3(0.0, 0.96, 0.28),
+        ]
+    }
+
+    #[test]
+    fn principled_lobes_agree_with_sampling() {
+        let materials = [
+            Principled::default(),
+            Principled {
+                metallic: 1.0,
+                roughness: 0.6,
+                anisotropy: 0.5,
+                ..Principled::default()
+            },
+            Principled {
+                base_color: vec3(0.2, 0.3, 0.7),
+                roughness: 0.8,
+                sheen: 1.0,
+                clearcoat: 1.0,
+                clearcoat_gloss: 0.3,
+                ..Principled::default()
+            },
+            Principled {
+                base_color: vec3(0.9, 0.9, 0.9),
+                roughness: 0.7,
+                transmission: 1.0,
+                ..Principled::default()
+            },
+        ];
+        for m in &materials {
+            for &eta in &[m.ior, m.ior.recip()] {
+                let bxdf = PrincipledBxdf::new(m, eta);
+                for &wo in &directions() {
+                    check_bxdf(&bxdf, wo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn principled_metal_takes_base_color() {
+        let gold = Principled {
+            base_color: vec3(1.0, 0.8, 0.3),
+            metallic: 1.0,
+            roughness: 0.4,
+            ..Principled::default()
+        };
+        let bxdf = PrincipledBxdf::new(&gold, gold.ior);
+        assert!(!bxdf.flags().contains(BxdfFlags::DIFFUSE));
+        let albedo = check_bxdf(&bxdf, vec3(0.0, 0.0, 1.0));
+        assert!(albedo.x > albedo.y && albedo.y > albedo.z, "{:?}", albedo);
+        assert!(albedo.x > 0.8, "{:?}", albedo);
+    }
+
+    #[test]
+    fn principled_plastic_is_mostly_diffuse() {
+        let plastic = Principled {
+            base_color: vec3(0.5, 0.5, 0.5),
+            roughness: 1.0,
+            ..Principled::default()
+        };
+        let bxdf = PrincipledBxdf::new(&plastic, plastic.ior);
+        let albedo = check_bxdf(&bxdf, vec3(0.0, 0.0, 1.0));
+        // Diffuse, plus some 4% of specular reflection
+        assert!((albedo.x - 0.5).abs() < 0.1, "{:?}", albedo);
+
+        // Glass doesn't reflect diffusely
+        let glass = Principled {
+            transmission: 1.0,
+            ..plastic
+        };
+        let bxdf = PrincipledBxdf::new(&glass, glass.ior);
+        assert!(bxdf.flags().contains(BxdfFlags::TRANSMISSION));
+        assert!(!bxdf.flags().contains(BxdfFlags::DIFFUSE));
+    }
+
+    #[test]
+    fn principled_glass_refracts_relative_to_the_outside() {
+        let glass = Principled {
+            roughness: 0.3,
+            transmission: 1.0,
+            priority: 2,
+            ..Principled::default()
+        };
+        let inside = glass.interior().unwrap();
+        assert_eq!(inside.refraction_index, glass.ior);
+        assert_eq!(inside.priority, 2);
+        // Opaque ones have no inside
+        assert_eq!(Principled::default().interior(), None);
+
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let front = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, 1.0),
+            &glass,
+        );
+        let back = HitStruct::new(
+            1.0,
+            point3(0.0, 0.0, 0.0),
+            &ray,
+            vec3(0.0, 0.0, -1.0),
+            &glass,
+        );
+        let wo = vec3(0.6, 0.0, 0.8);
+        let wi = vec3(-0.3, 0.1, -0.9).normalized();
+        // From water into glass, and from glass into water
+        for &(hit, eta) in &[(&front, 1.5 / 1.33), (&back, 1.33 / 1.5)] {
+            let bsdf = glass.bsdf_between(hit, 1.33).unwrap();
+            let expected = Bsdf::new(hit, PrincipledBxdf::new(&glass, eta));
+            assert_eq!(bsdf.eval(wo, wi), expected.eval(wo, wi));
+            assert_eq!(bsdf.pdf(wo, wi), expected.pdf(wo, wi));
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::Bsdf;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::Medium;
+use crate::texture::Texture;
+
+/// Another material, with its tangent turned around the normal by `angle`
+/// degrees: the direction anisotropic materials are stretched along, like
+/// the grooves of brushed metal.
+///
+/// ```
+/// use pbrt::material::*;
+/// use pbrt::microfacet::TrowbridgeReitz;
+///
+/// let brushed = Conductor {
+///     distribution: TrowbridgeReitz::from_anisotropic_roughness(0.4, 0.9),
+///     ..Conductor::aluminium(0.4)
+/// };
+/// let brushed_across = Rotated {
+///     material: &brushed,
+///     angle: &90.0,
+/// };
+/// ```
+#[derive(Copy, Clone)]
+pub struct Rotated<'a> {
+    pub material: &'a dyn Material,
+    /// Angle from the tangent of the surface, counter-clockwise around the
+    /// shading normal, in degrees
+    pub angle: &'a dyn Texture<Float>,
+}
+
+impl<'a> Rotated<'a> {
+    /// The hit, with its shading tangents rotated.
+    fn rotate<'h>(&self, hit: &HitStruct<'h>) -> HitStruct<'h> {
+        let (sin, cos) = self.angle.evaluate(hit).to_radians().sin_cos();
+        let n = hit.shading.n;
+        // Rodrigues' rotation formula
+        let rotate = |v: Vec3f| v * cos + n.cross(&v) * sin + n * (n.dot(v) * (1.0 - cos));
+
+        let mut rotated = hit.clone();
+        let shading = hit.shading;
+        rotated.set_shading_geometry(
+            n,
+            rotate(shading.dpdu),
+            rotate(shading.dpdv),
+            shading.dndu,
+            shading.dndv,
+        );
+        rotated
+    }
+}
+
+impl<'a> Material for Rotated<'a> {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.material.bsdf(&self.rotate(hit))
+    }
+
+    fn emitted(&self, ray: &Ray, hit: &HitStruct) -> Vec3f {
+        self.material.emitted(ray, hit)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        self.material.interior()
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        self.material.bsdf_between(&self.rotate(hit), outside_index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::hit::Hit;
+    use crate::material::{Conductor, MixMaterial, Ward};
+    use crate::microfacet::TrowbridgeReitz;
+    use crate::shape::sphere::Sphere;
+
+    #[test]
+    fn rotated_material_turns_highlights() {
+        let ward = Ward {
+            diffuse: Vec3f::default(),
+            specular: vec3(1.0, 1.0, 1.0),
+            alpha_x: 0.4,
+            alpha_y: 0.1,
+        };
+        let brushed = Conductor {
+            distribution: TrowbridgeReitz::from_anisotropic_roughness(0.5, 0.9),
+            ..Conductor::silver(0.5)
+        };
+        for &material in &[&ward as &dyn Material, &brushed] {
+            let rotated = Rotated {
+                material,
+                angle: &90.0,
+            };
+
+            // On the side of a sphere, where the tangent goes around the pole
+            let sphere = Sphere {
+                center: Point3f::new(0.0, 0.0, 0.0),
+                radius: 1.0,
+                material,
+            };
+            let ray = Ray::new(point3(3.0, 0.0, 0.5), vec3(-1.0, 0.0, 0.0));
+            let hit = sphere.hit(&ray, 0.0, Float::INFINITY).unwrap();
+            let frame = hit.shading_frame();
+            let (s, t, n) = (frame.s, frame.t, frame.n);
+
+            let wo = n;
+            let along = (n + s * 0.7).normalized();
+            let across = (n + t * 0.7).normalized();
+            let bsdf = material.bsdf(&hit).unwrap();
+            assert!(bsdf.eval(wo, along).x > bsdf.eval(wo, across).x);
+            // Turned alone, or in a mix of materials using the frame of the hit
+            let mix = MixMaterial {
+                a: &rotated,
+                b: &rotated,
+                weight: &0.5,
+            };
+            for rotated in &[rotated.bsdf(&hit).unwrap(), mix.bsdf(&hit).unwrap()] {
+                assert!((rotated.eval(wo, across) - bsdf.eval(wo, along)).len() < 1.0e-4);
+                assert!((rotated.eval(wo, along) - bsdf.eval(wo, across)).len() < 1.0e-4);
+            }
+        }
+    }
+}
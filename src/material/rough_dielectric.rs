@@ -0,0 +1,315 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::fresnel::fresnel_dielectric;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::{relative_index, Medium};
+use crate::microfacet::TrowbridgeReitz;
+
+/// Rough boundary between two transparent media, such as frosted glass or
+/// etched acrylic: microfacets distributed by `TrowbridgeReitz` reflect and
+/// refract light, in proportions given by the Fresnel equations.
+///
+/// ```
+/// use pbrt::material::RoughDielectric;
+/// use pbrt::microfacet::TrowbridgeReitz;
+///
+/// let frosted_glass = RoughDielectric::new(1.5, 0.4);
+/// let brushed_acrylic = RoughDielectric {
+///     refraction_index: 1.49,
+///     distribution: TrowbridgeReitz::from_anisotropic_roughness(0.3, 0.8),
+///     priority: 0,
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct RoughDielectric {
+    pub refraction_index: Float,
+    pub distribution: TrowbridgeReitz,
+    /// Priority of the inside over other media overlapping it. See
+    /// `MediumStack`.
+    pub priority: u32,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: Float, roughness: Float) -> RoughDielectric {
+        RoughDielectric {
+            refraction_index,
+            distribution: TrowbridgeReitz::from_roughness(roughness),
+            priority: 0,
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.bsdf_between(hit, 1.0)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        Some(Medium {
+            refraction_index: self.refraction_index,
+            absorption: Vec3f::default(),
+            scattering: Vec3f::default(),
+            priority: self.priority,
+        })
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(
+            hit,
+            RoughDielectricBxdf::between(
+                hit,
+                self.refraction_index,
+                outside_index,
+                self.distribution,
+            ),
+        ))
+    }
+}
+
+/// Microfacet reflection and transmission, with exact Fresnel reflectance.
+///
+/// Like `DielectricBxdf`, transmitted radiance isn't scaled by the squared
+/// relative index of refraction: the scaling cancels out on paths that go
+/// in and out again.
+#[derive(Copy, Clone, Debug)]
+pub struct RoughDielectricBxdf {
+    /// Refraction index below the surface relative to the one above it
+    pub eta: Float,
+    pub distribution: TrowbridgeReitz,
+}
+
+impl RoughDielectricBxdf {
+    /// BxDF of the boundary of a medium with `refraction_index` at the point
+    /// of hit, with `outside_index` on the other side of it.
+    pub(crate) fn between(
+        hit: &HitStruct,
+        refraction_index: Float,
+        outside_index: Float,
+        distribution: TrowbridgeReitz,
+    ) -> RoughDielectricBxdf {
+        RoughDielectricBxdf {
+            eta: relative_index(hit, refraction_index, outside_index),
+            distribution,
+        }
+    }
+
+    /// Microfacet normal taking `wo` to `wi`, facing `+z`, with the relative
+    /// index of refraction along the way. `None` if there's no such
+    /// microfacet, or it faces away from either direction.
+    fn half_vector(&self, wo: Vec3f, wi: Vec3f) -> Option<(Vec3f, Float)> {
+        let (cos_o, cos_i) = (wo.z, wi.z);
+        if cos_o == 0.0 || cos_i == 0.0 {
+            return None;
+        }
+        let etap = if cos_o * cos_i > 0.0 {
+            1.0
+        } else if cos_o > 0.0 {
+            self.eta
+        } else {
+            self.eta.recip()
+        };
+
+        let wm = wi * etap + wo;
+        if wm.len_squared() == 0.0 {
+            return None;
+        }
+        let wm = wm.normalized();
+        let wm = if wm.z < 0.0 { -wm } else { wm };
+
+        // Microfacets seen from behind
+        if wm.dot(wi) * cos_i < 0.0 || wm.dot(wo) * cos_o < 0.0 {
+            return None;
+        }
+        Some((wm, etap))
+    }
+
+    /// Specular reflection and transmission, for surfaces too smooth to be
+    /// treated as rough.
+    fn sample_specular(&self, wo: Vec3f, uc: Float) -> Option<BsdfSample> {
+        let r = fresnel_dielectric(wo.z, self.eta);
+        let t = 1.0 - r;
+        let (wi, pdf, flags) = if uc < r / (r + t) {
+            (reflect(wo), r, BxdfFlags::REFLECTION | BxdfFlags::SPECULAR)
+        } else {
+            let (wi, _) = refract(wo, vec3(0.0, 0.0, 1.0), self.eta)?;
+            (wi, t, BxdfFlags::TRANSMISSION | BxdfFlags::SPECULAR)
+        };
+        Some(BsdfSample {
+            wi,
+            f: vec3(pdf, pdf, pdf) * wi.z.abs().recip(),
+            pdf,
+            flags,
+            proportional: false,
+        })
+    }
+}
+
+impl Bxdf for RoughDielectricBxdf {
+    fn flags(&self) -> BxdfFlags {
+        let flags = BxdfFlags::REFLECTION | BxdfFlags::TRANSMISSION;
+        if self.distribution.is_smooth() {
+            flags | BxdfFlags::SPECULAR
+        } else {
+            flags | BxdfFlags::GLOSSY
+        }
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        if self.distribution.is_smooth() {
+            return Vec3f::default();
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return Vec3f::default(),
+        };
+
+        let d = self.distribution.d(wm);
+        let g = self.distribution.g(wo, wi);
+        let r = fresnel_dielectric(wo.dot(wm), self.eta);
+        let f = if etap == 1.0 {
+            d * g * r / (4.0 * wi.z * wo.z).abs()
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) / etap;
+            let denom = denom * denom * wi.z * wo.z;
+            d * g * (1.0 - r) * (wi.dot(wm) * wo.dot(wm) / denom).abs()
+        };
+        vec3(f, f, f)
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        if self.distribution.is_smooth() {
+            return self.sample_specular(wo, uc);
+        }
+        if wo.z == 0.0 {
+            return None;
+        }
+
+        let wm = self.distribution.sample_wm(wo, u);
+        let r = fresnel_dielectric(wo.dot(wm), self.eta);
+        let wi = if uc < r {
+            let wi = wm * (2.0 * wo.dot(wm)) - wo;
+            if !same_hemisphere(wo, wi) {
+                return None;
+            }
+            wi
+        } else {
+            let (wi, _) = refract(wo, wm, self.eta)?;
+            if same_hemisphere(wo, wi) || wi.z == 0.0 {
+                return None;
+            }
+            wi
+        };
+        // Facing away from either direction, by rounding
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        let flags = if same_hemisphere(wo, wi) {
+            BxdfFlags::REFLECTION | BxdfFlags::GLOSSY
+        } else {
+            BxdfFlags::TRANSMISSION | BxdfFlags::GLOSSY
+        };
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            flags,
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (wm, etap) = match self.half_vector(wo, wi) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+
+        let r = fresnel_dielectric(wo.dot(wm), self.eta);
+        let d = self.distribution.d_visible(wo, wm);
+        if etap == 1.0 {
+            d / (4.0 * wo.dot(wm).abs()) * r
+        } else {
+            let denom = wi.dot(wm) + wo.dot(wm) / etap;
+            let dwm_dwi = wi.dot(wm).abs() / (denom * denom);
+            d * dwm_dwi * (1.0 - r)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn rough_dielectric_conserves_energy() {
+        for &distribution in &[
+            TrowbridgeReitz::from_roughness(0.5),
+            TrowbridgeReitz::from_roughness(0.8),
+            TrowbridgeReitz::from_anisotropic_roughness(0.7, 0.6),
+        ] {
+            for &eta in &[1.5, 1.5f32.recip()] {
+                let bxdf = RoughDielectricBxdf { eta, distribution };
+                for &wo in &[
+                    vec3(0.0, 0.0, 1.0),
+                    vec3(0.6, 0.0, 0.8),
+                    vec3(0.0, 0.8, -0.6),
+                ] {
+                    let albedo = check_bxdf(&bxdf, wo);
+                    // Some light is lost to multiple scattering between
+                    // facets, which isn't modeled
+                    assert!(albedo.x > 0.6, "{:?} {} {:?}", distribution, eta, albedo);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn smooth_rough_dielectric_is_a_dielectric() {
+        let bxdf = RoughDielectricBxdf {
+            eta: 1.5,
+            distribution: TrowbridgeReitz::from_roughness(0.0),
+        };
+        assert!(bxdf.flags().is_specular());
+        let wo = vec3(0.0, 0.6, 0.8);
+        let albedo = check_bxdf(&bxdf, wo);
+        assert!((albedo - vec3(1.0, 1.0, 1.0)).len() < 1.0e-4);
+
+        let s = bxdf.sample(wo, 0.99, Point2f::new(0.5, 0.5)).unwrap();
+        assert!(s.flags.contains(BxdfFlags::TRANSMISSION));
+        // Snell's law
+        let sin_t = (1.0 - s.wi.z * s.wi.z).sqrt();
+        assert!((sin_t * 1.5 - 0.6).abs() < 1.0e-5);
+        assert!(s.wi.y < 0.0 && s.wi.z < 0.0);
+
+        let s = bxdf.sample(wo, 0.01, Point2f::new(0.5, 0.5)).unwrap();
+        assert!((s.wi - vec3(0.0, -0.6, 0.8)).len() < 1.0e-6);
+    }
+
+    #[test]
+    fn rough_dielectric_transmits_more_than_it_reflects() {
+        let bxdf = RoughDielectricBxdf {
+            eta: 1.5,
+            distribution: TrowbridgeReitz::from_roughness(0.5),
+        };
+        let wo = vec3(0.0, 0.0, 1.0);
+        let mut transmitted = 0;
+        for i in 0..100 {
+            let uc = (i as Float + 0.5) / 100.0;
+            if let Some(s) = bxdf.sample(wo, uc, Point2f::new(0.3, 0.7)) {
+                if s.flags.contains(BxdfFlags::TRANSMISSION) {
+                    transmitted += 1;
+                }
+            }
+        }
+        assert!(transmitted > 90, "{}", transmitted);
+    }
+}
@@ -0,0 +1,131 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::medium::Medium;
+use crate::microfacet::TrowbridgeReitz;
+
+use super::RoughDielectricBxdf;
+
+/// Translucent material, such as skin, wax, marble or milk: light goes in
+/// through a dielectric boundary, scatters many times inside, and comes out
+/// somewhere else.
+///
+/// Paths inside walk randomly through the `interior` medium, from one
+/// scattering event to the next, until they hit the surface again. Shapes
+/// with it must be closed.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::Subsurface;
+///
+/// let marble = Subsurface::new(vec3(0.83, 0.79, 0.75), vec3(0.05, 0.04, 0.03), 1.5);
+/// let skin = Subsurface {
+///     roughness: 0.3,
+///     ..Subsurface::new(vec3(0.8, 0.55, 0.45), vec3(0.04, 0.015, 0.01), 1.4)
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Subsurface {
+    // TODO: albedo is a spectrum, not a vector.
+    /// Color of the material seen from afar, after light scattered many
+    /// times inside it
+    pub albedo: Vec3f,
+    /// Typical distance light goes inside between scattering events, for
+    /// each channel: the larger, the more translucent the material looks
+    pub mean_free_path: Vec3f,
+    pub refraction_index: Float,
+    /// Roughness of the boundary, zero for a smooth one
+    pub roughness: Float,
+    /// Priority of the inside over other media overlapping it. See
+    /// `MediumStack`.
+    pub priority: u32,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Vec3f, mean_free_path: Vec3f, refraction_index: Float) -> Subsurface {
+        Subsurface {
+            albedo,
+            mean_free_path,
+            refraction_index,
+            roughness: 0.0,
+            priority: 0,
+        }
+    }
+
+    /// Scattering and absorption coefficients of the medium inside, such
+    /// that it looks like `albedo` from afar, by the fit of Chiang et al.,
+    /// "Practical and Controllable Subsurface Scattering for Production Path
+    /// Tracing", 2016.
+    fn coefficients(&self) -> (Vec3f, Vec3f) {
+        let channel = |a: Float, mfp: Float| {
+            let a = a.clamp(0.0, 1.0);
+            let single_scattering_albedo =
+                1.0 - (a * (-5.09406 + a * (2.61188 - a * 4.31805))).exp();
+            let s = 1.9 - a + 3.5 * (a - 0.8) * (a - 0.8);
+            let extinction = (mfp * s).max(1.0e-6).recip();
+            let scattering = extinction * single_scattering_albedo;
+            (scattering, extinction - scattering)
+        };
+        let (sx, ax) = channel(self.albedo.x, self.mean_free_path.x);
+        let (sy, ay) = channel(self.albedo.y, self.mean_free_path.y);
+        let (sz, az) = channel(self.albedo.z, self.mean_free_path.z);
+        (vec3(sx, sy, sz), vec3(ax, ay, az))
+    }
+}
+
+impl Material for Subsurface {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        self.bsdf_between(hit, 1.0)
+    }
+
+    fn interior(&self) -> Option<Medium> {
+        let (scattering, absorption) = self.coefficients();
+        Some(Medium {
+            refraction_index: self.refraction_index,
+            absorption,
+            scattering,
+            priority: self.priority,
+        })
+    }
+
+    fn bsdf_between(&self, hit: &HitStruct, outside_index: Float) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(
+            hit,
+            RoughDielectricBxdf::between(
+                hit,
+                self.refraction_index,
+                outside_index,
+                TrowbridgeReitz::from_roughness(self.roughness),
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn albedo_maps_to_coefficients_of_the_inside() {
+        let m = Subsurface::new(vec3(0.0, 0.5, 1.0), vec3(0.1, 0.1, 0.2), 1.3);
+        let medium = m.interior().unwrap();
+        let extinction = medium.absorption + medium.scattering;
+
+        // Black absorbs everything, white almost nothing
+        assert_eq!(medium.scattering.x, 0.0);
+        assert!(medium.absorption.z.abs() < 2.0e-3 * extinction.z);
+        // It takes a brighter inside to look grey from afar, as light
+        // scatters many times
+        let single_scattering_albedo = medium.scattering.y / extinction.y;
+        assert!(single_scattering_albedo > 0.8 && single_scattering_albedo < 1.0);
+
+        // Longer mean free paths scatter less often
+        assert!(extinction.z < extinction.y);
+        assert_eq!(medium.refraction_index, 1.3);
+    }
+}
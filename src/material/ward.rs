@@ -0,0 +1,182 @@
+use super::Material;
+
+use crate::prelude::*;
+
+use crate::bsdf::*;
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::sampling::*;
+
+use std::f32::consts::{FRAC_1_PI, PI};
+
+/// Ward's anisotropic model of glossy surfaces such as brushed metal, satin
+/// or varnished wood: a diffuse base under an elliptical highlight, stretched
+/// along the tangent when `alpha_x > alpha_y` (Ward, "Measuring and Modeling
+/// Anisotropic Reflection", 1992). The highlight is sampled after Walter,
+/// "Notes on the Ward BRDF", 2005.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::material::Ward;
+///
+/// let satin = Ward {
+///     diffuse: vec3(0.4, 0.05, 0.1),
+///     specular: vec3(0.2, 0.2, 0.2),
+///     alpha_x: 0.4,
+///     alpha_y: 0.1,
+/// };
+/// ```
+#[derive(Copy, Clone, Debug)]
+pub struct Ward {
+    // TODO: reflectances are spectra, not vectors.
+    pub diffuse: Vec3f,
+    pub specular: Vec3f,
+    /// Standard deviation of the slope of the surface along the tangent
+    pub alpha_x: Float,
+    /// Standard deviation of the slope of the surface across the tangent
+    pub alpha_y: Float,
+}
+
+impl Ward {
+    /// Probability of sampling the highlight rather than the diffuse base.
+    fn specular_probability(&self) -> Float {
+        let d = self.diffuse.x + self.diffuse.y + self.diffuse.z;
+        let s = self.specular.x + self.specular.y + self.specular.z;
+        if d + s > 0.0 {
+            s / (d + s)
+        } else {
+            0.0
+        }
+    }
+
+    /// Density of half vectors `wh`, in the upper hemisphere, per unit of
+    /// solid angle.
+    fn d(&self, wh: Vec3f) -> Float {
+        let (ax, ay) = (self.alpha_x, self.alpha_y);
+        let cos_theta = wh.z;
+        let exponent = -((wh.x / ax).powi(2) + (wh.y / ay).powi(2)) / (cos_theta * cos_theta);
+        exponent.exp() / (PI * ax * ay * cos_theta.powi(3))
+    }
+}
+
+impl Material for Ward {
+    fn bsdf(&self, hit: &HitStruct) -> Option<Bsdf<'_>> {
+        Some(Bsdf::new(hit, self))
+    }
+}
+
+impl Bxdf for Ward {
+    fn flags(&self) -> BxdfFlags {
+        BxdfFlags::REFLECTION | BxdfFlags::DIFFUSE | BxdfFlags::GLOSSY
+    }
+
+    fn eval(&self, wo: Vec3f, wi: Vec3f) -> Vec3f {
+        if !same_hemisphere(wo, wi) || wo.z == 0.0 {
+            return Vec3f::default();
+        }
+        // Both sides look the same
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+
+        let wh = (wo + wi).normalized();
+        let specular = self.d(wh) * wh.z.powi(3) / (4.0 * (wi.z * wo.z).sqrt());
+        self.diffuse * FRAC_1_PI + self.specular * specular
+    }
+
+    fn sample(&self, wo: Vec3f, uc: Float, u: Point2f) -> Option<BsdfSample> {
+        if wo.z == 0.0 {
+            return None;
+        }
+        let flip = wo.z < 0.0;
+        let wo_up = if flip { -wo } else { wo };
+
+        let wi = if uc < self.specular_probability() {
+            // Half vector, after Walter
+            let (ax, ay) = (self.alpha_x, self.alpha_y);
+            let phi = (ay * (2.0 * PI * u.x).sin()).atan2(ax * (2.0 * PI * u.x).cos());
+            let (sin_phi, cos_phi) = phi.sin_cos();
+            let tan2_theta =
+                -(1.0 - u.y).ln() / (cos_phi * cos_phi / (ax * ax) + sin_phi * sin_phi / (ay * ay));
+            let cos_theta = (1.0 + tan2_theta).sqrt().recip();
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let wh = vec3(sin_theta * cos_phi, sin_theta * sin_phi, cos_theta);
+            let wi = wh * (2.0 * wo_up.dot(wh)) - wo_up;
+            if wi.z <= 0.0 {
+                return None;
+            }
+            wi
+        } else {
+            cosine_sample_hemisphere(u)
+        };
+        let wi = if flip { -wi } else { wi };
+
+        let pdf = self.pdf(wo, wi);
+        if pdf == 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi),
+            pdf,
+            flags: BxdfFlags::REFLECTION | BxdfFlags::GLOSSY,
+            proportional: false,
+        })
+    }
+
+    fn pdf(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        if !same_hemisphere(wo, wi) || wo.z == 0.0 {
+            return 0.0;
+        }
+        let (wo, wi) = if wo.z < 0.0 { (-wo, -wi) } else { (wo, wi) };
+
+        let wh = (wo + wi).normalized();
+        let specular = self.d(wh) / (4.0 * wo.dot(wh));
+        let p = self.specular_probability();
+        p * specular + (1.0 - p) * cosine_hemisphere_pdf(wi.z)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bsdf::test::check_bxdf;
+
+    #[test]
+    fn ward_conserves_energy() {
+        for &(alpha_x, alpha_y) in &[(0.3, 0.3), (0.4, 0.1), (0.1, 0.5)] {
+            let m = Ward {
+                diffuse: vec3(0.5, 0.3, 0.1),
+                specular: vec3(0.3, 0.3, 0.3),
+                alpha_x,
+                alpha_y,
+            };
+            for &wo in &[
+                vec3(0.0, 0.0, 1.0),
+                vec3(0.6, 0.0, 0.8),
+                vec3(0.0, 0.6, -0.8),
+            ] {
+                let albedo = check_bxdf(&m, wo);
+                assert!(albedo.x > 0.6, "{} {} {:?}", alpha_x, alpha_y, albedo);
+            }
+        }
+    }
+
+    #[test]
+    fn ward_highlight_is_stretched_along_the_tangent() {
+        let m = Ward {
+            diffuse: Vec3f::default(),
+            specular: vec3(1.0, 1.0, 1.0),
+            alpha_x: 0.4,
+            alpha_y: 0.1,
+        };
+        let wo = vec3(0.0, 0.0, 1.0);
+        let along = m.eval(wo, vec3(0.6, 0.0, 0.8));
+        let across = m.eval(wo, vec3(0.0, 0.6, 0.8));
+        assert!(along.x > 10.0 * across.x, "{:?} {:?}", along, across);
+
+        let isotropic = Ward { alpha_y: 0.4, ..m };
+        let along = isotropic.eval(wo, vec3(0.6, 0.0, 0.8));
+        let across = isotropic.eval(wo, vec3(0.0, 0.6, 0.8));
+        assert!((along - across).len() < 1.0e-6);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::HitStruct;
+use crate::material::Material;
+
+/// Transparent or translucent medium filling the inside of a closed surface,
+/// such as glass, water or wax.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Medium {
+    pub refraction_index: Float,
+    /// Fraction of light absorbed per unit of distance, for each channel
+    pub absorption: Vec3f,
+    /// Fraction of light scattered in other directions per unit of
+    /// distance, for each channel
+    pub scattering: Vec3f,
+    /// Where media overlap, the one with the highest priority fills the
+    /// overlap, and the surfaces of the others are ignored there
+    pub priority: u32,
+}
+
+impl Medium {
+    /// Fraction of light going straight through `distance` of the medium,
+    /// by the Beer-Lambert law.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    /// use pbrt::medium::Medium;
+    ///
+    /// let ink = Medium {
+    ///     refraction_index: 1.33,
+    ///     absorption: vec3(2.0, 2.0, 0.1),
+    ///     scattering: Vec3f::default(),
+    ///     priority: 0,
+    /// };
+    /// let t = ink.transmittance(1.0);
+    /// assert!(t.z > 0.9 && t.x < 0.2);
+    /// ```
+    pub fn transmittance(&self, distance: Float) -> Vec3f {
+        let a = (self.absorption + self.scattering) * -distance;
+        vec3(a.x.exp(), a.y.exp(), a.z.exp())
+    }
+
+    /// True if light scatters inside, rather than only being absorbed.
+    pub fn scatters(&self) -> bool {
+        self.scattering != Vec3f::default()
+    }
+
+    /// Samples how far light goes through the medium before it scatters,
+    /// until `t_max` where it leaves the medium, for the channel picked by
+    /// `uc`. Returns the distance, if it scatters before leaving, and the
+    /// weight of the sample: transmittance over the density of the
+    /// distance, averaged over all channels.
+    pub fn sample_distance(&self, t_max: Float, uc: Float, u: Float) -> (Option<Float>, Vec3f) {
+        let extinction = self.absorption + self.scattering;
+        let channel = ((uc * 3.0) as usize).min(2);
+        let distance = if extinction[channel] > 0.0 {
+            -(1.0 - u).ln() / extinction[channel]
+        } else {
+            Float::INFINITY
+        };
+
+        let scattered = distance < t_max;
+        let tr = self.transmittance(distance.min(t_max));
+        let density = if scattered { extinction * tr } else { tr };
+        let pdf = (density.x + density.y + density.z) / 3.0;
+        if pdf == 0.0 {
+            return (None, Vec3f::default());
+        }
+        if scattered {
+            (Some(distance), tr * self.scattering * pdf.recip())
+        } else {
+            (None, tr * pdf.recip())
+        }
+    }
+}
+
+/// Media a path is inside of, from the outermost to the innermost.
+///
+/// Overlapping media are resolved by priority (Schmidt and Budge, "Simple
+/// Nested Dielectrics in Ray Traced Images", 2002): a glass of water is
+/// modeled by water slightly larger than the inside of the glass, with a
+/// lower priority than the glass.
+#[derive(Clone, Default)]
+pub struct MediumStack<'a> {
+    media: Vec<(&'a dyn Material, Medium)>,
+}
+
+impl<'a> MediumStack<'a> {
+    pub fn new() -> MediumStack<'a> {
+        MediumStack { media: Vec::new() }
+    }
+
+    /// Medium the path is in, if not vacuum: the innermost one with the
+    /// highest priority.
+    pub fn current(&self) -> Option<Medium> {
+        Self::highest(self.media.iter())
+    }
+
+    /// Refraction index on the other side of the surface of `material`,
+    /// bounding `medium`, when the path enters or exits it. `None` if the
+    /// surface is inside a medium with a higher priority, and should be
+    /// ignored.
+    pub fn outside_index(
+        &self,
+        material: &dyn Material,
+        medium: Medium,
+        entering: bool,
+    ) -> Option<Float> {
+        let others = self
+            .media
+            .iter()
+            .filter(|(m, _)| entering || !same(*m, material));
+        let outside = Self::highest(others);
+        match outside {
+            Some(outside) if outside.priority > medium.priority => None,
+            Some(outside) => Some(outside.refraction_index),
+            None => Some(1.0),
+        }
+    }
+
+    /// The path went through the surface of `material` into `medium`.
+    pub fn enter(&mut self, material: &'a dyn Material, medium: Medium) {
+        self.media.push((material, medium));
+    }
+
+    /// The path went through the surface of `material`, out of its medium.
+    pub fn exit(&mut self, material: &dyn Material) {
+        if let Some(i) = self.media.iter().rposition(|(m, _)| same(*m, material)) {
+            self.media.remove(i);
+        }
+    }
+
+    fn highest<'b>(media: impl Iterator<Item = &'b (&'a dyn Material, Medium)>) -> Option<Medium>
+    where
+        'a: 'b,
+    {
+        media
+            .map(|(_, medium)| *medium)
+            // Last of the highest: the innermost
+            .fold(None, |highest: Option<Medium>, medium| match highest {
+                Some(h) if h.priority > medium.priority => Some(h),
+                _ => Some(medium),
+            })
+    }
+}
+
+/// Refraction index below the surface at the point of hit relative to the
+/// one above it, from the side the ray comes from, where the medium inside
+/// has `refraction_index` and the one outside `outside_index`.
+pub(crate) fn relative_index(
+    hit: &HitStruct,
+    refraction_index: Float,
+    outside_index: Float,
+) -> Float {
+    if hit.front_face {
+        refraction_index / outside_index
+    } else {
+        outside_index / refraction_index
+    }
+}
+
+fn same(a: &dyn Material, b: &dyn Material) -> bool {
+    std::ptr::eq(
+        a as *const dyn Material as *const u8,
+        b as *const dyn Material as *const u8,
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::material::Dielectric;
+
+    use rand::prelude::*;
+
+    #[test]
+    fn sampled_distances_are_exponential() {
+        let milk = Medium {
+            refraction_index: 1.35,
+            absorption: vec3(0.1, 0.2, 0.4),
+            scattering: vec3(4.0, 3.0, 2.0),
+            priority: 0,
+        };
+
+        // Light leaves a slab, on average, in proportion to transmittance
+        let t_max = 0.3;
+        let n = 100_000;
+        let mut rng = StdRng::seed_from_u64(47);
+        let mut transmitted = Vec3f::default();
+        let mut scattered = Vec3f::default();
+        for _ in 0..n {
+            match milk.sample_distance(t_max, rng.gen(), rng.gen()) {
+                (None, weight) => transmitted += weight,
+                (Some(t), weight) => {
+                    assert!(t < t_max);
+                    scattered += weight;
+                }
+            }
+        }
+        let transmitted = transmitted * (n as Float).recip();
+        let expected = milk.transmittance(t_max);
+        assert!(
+            (transmitted - expected).len() < 1.0e-2,
+            "{:?} {:?}",
+            transmitted,
+            expected
+        );
+
+        // And the rest of it scatters, or is absorbed
+        let scattered = scattered * (n as Float).recip();
+        let extinction = milk.absorption + milk.scattering;
+        for i in 0..3 {
+            let expected = milk.scattering[i] / extinction[i] * (1.0 - expected[i]);
+            assert!((scattered[i] - expected).abs() < 1.0e-2, "{:?}", scattered);
+        }
+    }
+
+    #[test]
+    fn water_in_glass() {
+        let glass = Dielectric {
+            priority: 2,
+            ..Dielectric::new(1.5)
+        };
+        let water = Dielectric {
+            priority: 1,
+            ..Dielectric::new(1.33)
+        };
+        let ice = Dielectric {
+            priority: 3,
+            ..Dielectric::new(1.31)
+        };
+        let (g, w, i) = (
+            glass.interior().unwrap(),
+            water.interior().unwrap(),
+            ice.interior().unwrap(),
+        );
+
+        let mut media = MediumStack::new();
+        assert_eq!(media.current(), None);
+
+        // Into the glass from the air
+        assert_eq!(media.outside_index(&glass, g, true), Some(1.0));
+        media.enter(&glass, g);
+        // The water overlaps the glass, and its surface there isn't seen
+        assert_eq!(media.outside_index(&water, w, true), None);
+        media.enter(&water, w);
+        assert_eq!(media.current(), Some(g));
+        // Out of the glass into the water
+        assert_eq!(media.outside_index(&glass, g, false), Some(1.33));
+        media.exit(&glass);
+        assert_eq!(media.current(), Some(w));
+        // Ice floating in the water
+        assert_eq!(media.outside_index(&ice, i, true), Some(1.33));
+        media.enter(&ice, i);
+        assert_eq!(media.outside_index(&ice, i, false), Some(1.33));
+        media.exit(&ice);
+        // And out of the water into the air
+        assert_eq!(media.outside_index(&water, w, false), Some(1.0));
+        media.exit(&water);
+        assert_eq!(media.current(), None);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+
+use std::f32::consts::PI;
+
+/// Trowbridge-Reitz, a.k.a. GGX, distribution of microfacet normals, for
+/// rough surfaces made of tiny mirrors (Walter et al., "Microfacet Models
+/// for Refraction through Rough Surfaces", 2007).
+///
+/// Works in the local shading frame, where the macro-surface normal is `+z`.
+/// `alpha_x` and `alpha_y` are the roughness along `x` and `y`; they're
+/// equal for isotropic surfaces.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrowbridgeReitz {
+    pub alpha_x: Float,
+    pub alpha_y: Float,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(alpha_x: Float, alpha_y: Float) -> TrowbridgeReitz {
+        TrowbridgeReitz { alpha_x, alpha_y }
+    }
+
+    /// Isotropic distribution for a perceptual `roughness` in `[0, 1]`,
+    /// which is squared so that it looks linear.
+    ///
+    /// ```
+    /// use pbrt::microfacet::TrowbridgeReitz;
+    ///
+    /// let distribution = TrowbridgeReitz::from_roughness(0.5);
+    /// assert_eq!(distribution.alpha_x, 0.25);
+    /// assert!(TrowbridgeReitz::from_roughness(0.0).is_smooth());
+    /// ```
+    pub fn from_roughness(roughness: Float) -> TrowbridgeReitz {
+        let alpha = roughness * roughness;
+        TrowbridgeReitz::new(alpha, alpha)
+    }
+
+    /// Distribution for a perceptual `roughness`, stretched along `x` by
+    /// `anisotropy` in `[0, 1]`, the way Disney's principled BRDF does it.
+    ///
+    /// ```
+    /// use pbrt::microfacet::TrowbridgeReitz;
+    ///
+    /// let distribution = TrowbridgeReitz::from_anisotropic_roughness(0.5, 0.8);
+    /// assert!(distribution.alpha_x > distribution.alpha_y);
+    /// assert_eq!(
+    ///     TrowbridgeReitz::from_anisotropic_roughness(0.5, 0.0),
+    ///     TrowbridgeReitz::from_roughness(0.5),
+    /// );
+    /// ```
+    pub fn from_anisotropic_roughness(roughness: Float, anisotropy: Float) -> TrowbridgeReitz {
+        let alpha = roughness * roughness;
+        let aspect = (1.0 - 0.9 * anisotropy).sqrt();
+        TrowbridgeReitz::new((alpha / aspect).max(1.0e-4), (alpha * aspect).max(1.0e-4))
+    }
+
+    /// True if the surface is so smooth it's better treated as a perfect
+    /// specular one: the distribution is too peaked to be sampled well.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < 1.0e-3
+    }
+
+    /// Density of microfacets with normal `wm`, per unit of solid angle and
+    /// of macro-surface area.
+    pub fn d(&self, wm: Vec3f) -> Float {
+        let cos2 = wm.z * wm.z;
+        let sin2 = (1.0 - cos2).max(0.0);
+        let cos4 = cos2 * cos2;
+        if cos4 < 1.0e-16 {
+            return 0.0;
+        }
+        let tan2 = sin2 / cos2;
+        let (cos_phi, sin_phi) = cos_sin_phi(wm);
+        let e = tan2
+            * (cos_phi * cos_phi / (self.alpha_x * self.alpha_x)
+                + sin_phi * sin_phi / (self.alpha_y * self.alpha_y));
+        (PI * self.alpha_x * self.alpha_y * cos4 * (1.0 + e) * (1.0 + e)).recip()
+    }
+
+    /// Smith's auxiliary function: the area of microfacets hidden from `w`,
+    /// per unit of visible microfacet area.
+    pub fn lambda(&self, w: Vec3f) -> Float {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return 0.0;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        let (cos_phi, sin_phi) = cos_sin_phi(w);
+        let alpha2 = cos_phi * cos_phi * self.alpha_x * self.alpha_x
+            + sin_phi * sin_phi * self.alpha_y * self.alpha_y;
+        ((1.0 + alpha2 * tan2).sqrt() - 1.0) * 0.5
+    }
+
+    /// Fraction of microfacets visible from `w`.
+    pub fn g1(&self, w: Vec3f) -> Float {
+        (1.0 + self.lambda(w)).recip()
+    }
+
+    /// Fraction of microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3f, wi: Vec3f) -> Float {
+        (1.0 + self.lambda(wo) + self.lambda(wi)).recip()
+    }
+
+    /// Density of microfacets with normal `wm` as seen from `w`, per unit of
+    /// solid angle and of projected macro-surface area.
+    pub fn d_visible(&self, w: Vec3f, wm: Vec3f) -> Float {
+        if w.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(w) / w.z.abs() * self.d(wm) * w.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `w`, with density
+    /// `d_visible(w, wm)`, for a uniform `u` in `[0, 1)²` (Heitz, "Sampling
+    /// the GGX Distribution of Visible Normals", 2018).
+    pub fn sample_wm(&self, w: Vec3f, u: Point2f) -> Vec3f {
+        // Stretch to the hemisphere configuration
+        let mut wh = vec3(self.alpha_x * w.x, self.alpha_y * w.y, w.z).normalized();
+        if wh.z < 0.0 {
+            wh = -wh;
+        }
+        let t1 = if wh.z < 0.99999 {
+            vec3(0.0, 0.0, 1.0).cross(&wh).normalized()
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        let t2 = wh.cross(&t1);
+
+        // Uniform point on the disk, warped to the projection of the
+        // visible hemisphere
+        let r = u.x.sqrt();
+        let (sin, cos) = (2.0 * PI * u.y).sin_cos();
+        let (px, py) = (r * cos, r * sin);
+        let h = (1.0 - px * px).max(0.0).sqrt();
+        let t = (1.0 + wh.z) * 0.5;
+        let py = (1.0 - t) * h + t * py;
+        let pz = (1.0 - px * px - py * py).max(0.0).sqrt();
+
+        // Back to the ellipsoid configuration
+        let nh = t1 * px + t2 * py + wh * pz;
+        vec3(self.alpha_x * nh.x, self.alpha_y * nh.y, nh.z.max(1.0e-6)).normalized()
+    }
+}
+
+/// Cosine and sine of the azimuth of `w`.
+fn cos_sin_phi(w: Vec3f) -> (Float, Float) {
+    let sin_theta = (1.0 - w.z * w.z).max(0.0).sqrt();
+    if sin_theta == 0.0 {
+        (1.0, 0.0)
+    } else {
+        (
+            (w.x / sin_theta).clamp(-1.0, 1.0),
+            (w.y / sin_theta).clamp(-1.0, 1.0),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    use rand::prelude::*;
+
+    /// Uniform random directions over the sphere.
+    fn uniform_directions(n: usize) -> impl Iterator<Item = Vec3f> {
+        let mut rng = StdRng::seed_from_u64(91);
+        (0..n).map(move |_| {
+            let z = 1.0 - 2.0 * rng.gen::<Float>();
+            let r = (1.0 - z * z).max(0.0).sqrt();
+            let phi = 2.0 * PI * rng.gen::<Float>();
+            vec3(r * phi.cos(), r * phi.sin(), z)
+        })
+    }
+
+    #[test]
+    fn projected_microfacet_area_is_macro_surface_area() {
+        for &(alpha_x, alpha_y) in &[(0.3, 0.3), (0.6, 0.6), (0.2, 0.7)] {
+            let distribution = TrowbridgeReitz::new(alpha_x, alpha_y);
+            let n = 200_000;
+            let integral: Float = uniform_directions(n)
+                .map(|wm| distribution.d(wm) * wm.z.max(0.0))
+                .sum::<Float>()
+                * 4.0
+                * PI
+                / n as Float;
+            assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+
+            // Visible normals, seen from any direction, too
+            let w = vec3(0.6, 0.0, 0.8);
+            let integral: Float = uniform_directions(n)
+                .filter(|&wm| wm.z > 0.0 && w.dot(wm) > 0.0)
+                .map(|wm| distribution.d_visible(w, wm))
+                .sum::<Float>()
+                * 4.0
+                * PI
+                / n as Float;
+            assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+        }
+    }
+
+    #[test]
+    fn sampled_normals_are_visible() {
+        let distribution = TrowbridgeReitz::new(0.5, 0.2);
+        let w = vec3(-0.48, 0.6, 0.64);
+        let mut rng = StdRng::seed_from_u64(92);
+        for _ in 0..1000 {
+            let wm = distribution.sample_wm(w, Point2f::new(rng.gen(), rng.gen()));
+            assert!((wm.len() - 1.0).abs() < 1.0e-4);
+            assert!(wm.z > 0.0);
+            assert!(w.dot(wm) >= -1.0e-4, "{:?}", wm);
+        }
+    }
+}
@@ -0,0 +1,65 @@
+/// Shared geometry placed in the scene with a transformation.
+pub mod instance;
+
+pub use instance::Instance;
+
+use crate::num_traits::Float;
+
+use crate::geo::{Bounds3, Bounds3f, Ray};
+use crate::hit::{Hit, HitStruct};
+
+/// Something that can be hit by rays, and knows how much space it takes.
+///
+/// Acceleration structures are built over primitives.
+pub trait Primitive: Hit {
+    /// Bounding box of the primitive, in world space.
+    fn world_bound(&self) -> Bounds3f;
+
+    /// Bounding box of the part of the primitive inside of given bounds.
+    ///
+    /// Spatial splits in BVH construction rely on it. The default is
+    /// conservative, shapes that can do better should.
+    fn clipped_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        Bounds3::intersect(&self.world_bound(), bounds)
+    }
+}
+
+impl<T: Hit + ?Sized> Hit for &T {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        (**self).occluded(ray, t_min, t_max)
+    }
+}
+
+impl<T: Primitive + ?Sized> Primitive for &T {
+    fn world_bound(&self) -> Bounds3f {
+        (**self).world_bound()
+    }
+
+    fn clipped_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        (**self).clipped_bound(bounds)
+    }
+}
+
+impl<T: Hit + ?Sized> Hit for Box<T> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        (**self).occluded(ray, t_min, t_max)
+    }
+}
+
+impl<T: Primitive + ?Sized> Primitive for Box<T> {
+    fn world_bound(&self) -> Bounds3f {
+        (**self).world_bound()
+    }
+
+    fn clipped_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        (**self).clipped_bound(bounds)
+    }
+}
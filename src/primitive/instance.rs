@@ -0,0 +1,160 @@
+use crate::num_traits::Float;
+
+use crate::geo::{Bounds3f, Ray, Transform};
+use crate::hit::{Hit, HitStruct};
+use crate::primitive::Primitive;
+
+/// A primitive (usually, an acceleration structure over a mesh) placed in
+/// the scene with a transformation.
+///
+/// The geometry is borrowed, not copied, so any number of instances can
+/// share it. Rays are transformed into the object space of the geometry, and
+/// hits are transformed back into world space.
+///
+/// An acceleration structure over instances makes for a two-level
+/// hierarchy: `Bvh<Instance<Bvh<Triangle>>>`.
+pub struct Instance<'a, P: ?Sized> {
+    object: &'a P,
+    object_to_world: Transform,
+    world_to_object: Transform,
+    world_bound: Bounds3f,
+}
+
+impl<'a, P: Primitive + ?Sized> Instance<'a, P> {
+    pub fn new(object: &'a P, object_to_world: Transform) -> Instance<'a, P> {
+        Instance {
+            object,
+            object_to_world,
+            world_to_object: object_to_world.inverse(),
+            world_bound: object_to_world.transform_bounds(&object.world_bound()),
+        }
+    }
+
+    pub fn object_to_world(&self) -> &Transform {
+        &self.object_to_world
+    }
+}
+
+impl<P: Primitive + ?Sized> Hit for Instance<'_, P> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        let ray = self.world_to_object.transform_ray(ray);
+        self.object
+            .hit(&ray, t_min, t_max)
+            .map(|hit| self.object_to_world.transform_hit(hit))
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        let ray = self.world_to_object.transform_ray(ray);
+        self.object.occluded(&ray, t_min, t_max)
+    }
+}
+
+impl<P: Primitive + ?Sized> Primitive for Instance<'_, P> {
+    fn world_bound(&self) -> Bounds3f {
+        self.world_bound
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::accel::bvh::test::*;
+    use crate::accel::Bvh;
+    use crate::geo::*;
+    use crate::material::null::NullMaterial;
+    use crate::shape::sphere::Sphere;
+    use crate::shape::triangle::Triangle;
+
+    #[test]
+    fn instanced_sphere() {
+        let unit = Sphere {
+            center: point3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: &NullMaterial,
+        };
+        let t = Transform::translate(vec3(1.0, 2.0, 3.0)) * Transform::scale(vec3(2.0, 2.0, 2.0));
+        let instance = Instance::new(&unit, t);
+        let explicit = Sphere {
+            center: point3(1.0, 2.0, 3.0),
+            radius: 2.0,
+            material: &NullMaterial,
+        };
+
+        for ray in random_rays(200, 11).iter() {
+            let a = instance.hit(ray, 0.0, Float::INFINITY);
+            let b = explicit.hit(ray, 0.0, Float::INFINITY);
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert!((a.t - b.t).abs() < 1.0e-4);
+                assert!((a.p - b.p).len() < 1.0e-4);
+                assert!((a.n - b.n).len() < 1.0e-4);
+                assert_eq!(a.front_face, b.front_face);
+                assert!((a.dpdu - b.dpdu).len() < 1.0e-3 * b.dpdu.len());
+            }
+        }
+
+        let b = instance.world_bound();
+        assert_eq!(b.min, point3(-1.0, 0.0, 1.0));
+        assert_eq!(b.max, point3(3.0, 4.0, 5.0));
+    }
+
+    #[test]
+    fn two_level_hierarchy() {
+        let mesh = Bvh::new(random_triangles(200, 12));
+        let transforms: Vec<_> = (0..50)
+            .map(|i| {
+                let i = i as Float;
+                Transform::translate(vec3(i * 0.7 - 17.0, (i * 1.3).sin() * 4.0, i * 0.2))
+                    * Transform::rotate(i * 37.0, vec3(1.0, i, 0.5))
+                    * Transform::scale(vec3(0.3, 0.3, 0.3))
+            })
+            .collect();
+
+        let scene = Bvh::new(
+            transforms
+                .iter()
+                .map(|&t| Instance::new(&mesh, t))
+                .collect::<Vec<_>>(),
+        );
+
+        // Same geometry, transformed explicitly
+        let triangles: Vec<_> = transforms
+            .iter()
+            .flat_map(|t| {
+                mesh.primitives().iter().map(move |tri| {
+                    let [p0, p1, p2] = tri.positions;
+                    Triangle {
+                        positions: [
+                            t.transform_point(p0),
+                            t.transform_point(p1),
+                            t.transform_point(p2),
+                        ],
+                        material: &NullMaterial,
+                        backface_culling: false,
+                        uvs: None,
+                        tangents: None,
+                    }
+                })
+            })
+            .collect();
+
+        let mut n_hits = 0;
+        for ray in random_rays(500, 13).iter() {
+            let expected = brute_force(&triangles, ray);
+            let hit = scene.hit(ray, 0.0, Float::INFINITY);
+            assert_eq!(hit.is_some(), expected.is_some());
+            assert_eq!(
+                scene.occluded(ray, 0.0, Float::INFINITY),
+                expected.is_some()
+            );
+            if let (Some(hit), Some(t)) = (hit, expected) {
+                assert!((hit.t - t).abs() < 1.0e-3 * t);
+                assert!((hit.p - ray.eval(hit.t)).len() < 1.0e-3);
+                assert!(ray.direction().dot(hit.n) < 0.0);
+                n_hits += 1;
+            }
+        }
+        assert!(n_hits > 0);
+    }
+}
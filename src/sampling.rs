@@ -0,0 +1,89 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+
+use std::f32::consts::{FRAC_1_PI, FRAC_PI_2, FRAC_PI_4, PI};
+
+/// Maps a uniform sample in `[0, 1)²` to a uniform point on the unit disk,
+/// keeping nearby samples nearby (Shirley and Chiu, "A Low Distortion Map
+/// Between Disk and Square", 1997).
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::sampling::concentric_sample_disk;
+///
+/// assert_eq!(concentric_sample_disk(Point2f::new(0.5, 0.5)), Point2f::new(0.0, 0.0));
+/// assert!((concentric_sample_disk(Point2f::new(1.0, 0.5)).x - 1.0).abs() < 1.0e-6);
+/// ```
+pub fn concentric_sample_disk(u: Point2f) -> Point2f {
+    let (x, y) = (2.0 * u.x - 1.0, 2.0 * u.y - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Point2f::new(0.0, 0.0);
+    }
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, FRAC_PI_4 * (y / x))
+    } else {
+        (y, FRAC_PI_2 - FRAC_PI_4 * (x / y))
+    };
+    let (sin, cos) = theta.sin_cos();
+    Point2f::new(r * cos, r * sin)
+}
+
+/// Maps a uniform sample in `[0, 1)²` to a direction in the `+z`
+/// hemisphere, distributed by the cosine of its angle to `z`.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::sampling::cosine_sample_hemisphere;
+///
+/// let w = cosine_sample_hemisphere(Point2f::new(0.3, 0.8));
+/// assert!(w.z > 0.0);
+/// assert!((w.len() - 1.0).abs() < 1.0e-6);
+/// ```
+pub fn cosine_sample_hemisphere(u: Point2f) -> Vec3f {
+    let d = concentric_sample_disk(u);
+    let z = (1.0 - d.x * d.x - d.y * d.y).max(0.0).sqrt();
+    vec3(d.x, d.y, z)
+}
+
+/// Density of `cosine_sample_hemisphere`, with respect to solid angle.
+pub fn cosine_hemisphere_pdf(cos_theta: Float) -> Float {
+    cos_theta * FRAC_1_PI
+}
+
+/// Maps a uniform sample in `[0, 1)²` to a uniform direction.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::sampling::uniform_sample_sphere;
+///
+/// let w = uniform_sample_sphere(Point2f::new(0.3, 0.8));
+/// assert!((w.len() - 1.0).abs() < 1.0e-6);
+/// ```
+pub fn uniform_sample_sphere(u: Point2f) -> Vec3f {
+    let z = 1.0 - 2.0 * u.x;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let (sin, cos) = (2.0 * PI * u.y).sin_cos();
+    vec3(r * cos, r * sin, z)
+}
+
+/// Multiple importance sampling weight of a sample from a strategy with
+/// density `f_pdf`, against another strategy with density `g_pdf`, by the
+/// power heuristic (Veach, 1997).
+///
+/// ```
+/// use pbrt::sampling::power_heuristic;
+///
+/// assert_eq!(power_heuristic(1.0, 1.0), 0.5);
+/// assert_eq!(power_heuristic(3.0, 1.0), 0.9);
+/// ```
+pub fn power_heuristic(f_pdf: Float, g_pdf: Float) -> Float {
+    let (f, g) = (f_pdf * f_pdf, g_pdf * g_pdf);
+    if f.is_infinite() {
+        1.0
+    } else if f + g == 0.0 {
+        0.0
+    } else {
+        f / (f + g)
+    }
+}
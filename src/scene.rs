@@ -1,8 +1,10 @@
+#[allow(dead_code)]
 trait Node: std::marker::Sync {
     /// Returns a `Scene` that it belongs to.
     fn scene(&self) -> &Scene;
 }
 
+#[allow(dead_code)]
 pub struct Scene {
     nodes: Vec<Box<dyn Node>>,
 }
@@ -3,4 +3,22 @@ pub mod sphere;
 /// Triangle, the simplest primitive.
 pub mod triangle;
 
-pub trait Shape {}
+/// Parallelogram, for walls, windows and panel lights.
+pub mod quad;
+
+use crate::prelude::*;
+
+use crate::geo::{Point2f, Point3f, Vec3f};
+use crate::primitive::Primitive;
+
+/// Surface with a known area, which can be sampled uniformly. Lights are
+/// attached to shapes.
+pub trait Shape: Primitive {
+    /// Surface area.
+    fn area(&self) -> Float;
+
+    /// Point on the surface, and the outward unit normal there, for a
+    /// uniformly distributed `u` in `[0, 1)²`. Points are distributed
+    /// uniformly over the area.
+    fn sample(&self, u: Point2f) -> (Point3f, Vec3f);
+}
@@ -0,0 +1,131 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::*;
+use crate::material::*;
+use crate::primitive::Primitive;
+use crate::shape::Shape;
+
+/// Parallelogram with a corner at `origin`, spanned by edges `u` and `v`.
+///
+/// The front face is the one `u × v` points from. (u, v) coordinates of
+/// the surface go from 0 to 1 along the edges.
+pub struct Quad<'a> {
+    pub origin: Point3f,
+    pub u: Vec3f,
+    pub v: Vec3f,
+    pub material: &'a dyn Material,
+}
+
+impl Hit for Quad<'_> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.intersection(ray, t_min, t_max).map(|(t, uv)| {
+            let n = self.u.cross(&self.v).normalized();
+            let zero = Vec3f::default();
+            HitStruct::new(t, ray.eval(t), ray, n, self.material)
+                .with_parameterization(uv, self.u, self.v, zero, zero)
+        })
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.intersection(ray, t_min, t_max).is_some()
+    }
+}
+
+impl Primitive for Quad<'_> {
+    fn world_bound(&self) -> Bounds3f {
+        let o = self.origin;
+        let b = Bounds3::from_corners(o, o + self.u + self.v);
+        Bounds3::union_point(&Bounds3::union_point(&b, o + self.u), o + self.v)
+    }
+}
+
+impl Shape for Quad<'_> {
+    fn area(&self) -> Float {
+        self.u.cross(&self.v).len()
+    }
+
+    fn sample(&self, u: Point2f) -> (Point3f, Vec3f) {
+        let p = self.origin + self.u * u.x + self.v * u.y;
+        (p, self.u.cross(&self.v).normalized())
+    }
+}
+
+impl Quad<'_> {
+    /// Time and (u, v) coordinates of the hit between `t_min` and `t_max`.
+    fn intersection(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<(Float, Point2f)> {
+        let (o, d) = ray.origin_and_direction();
+        let n = self.u.cross(&self.v);
+        let denominator = n.dot(d);
+        if denominator == 0.0 {
+            return None;
+        }
+        let t = n.dot(self.origin - o) / denominator;
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+
+        // Coordinates of the point in the plane, along the edges
+        let w = n * n.len_squared().recip();
+        let op = ray.eval(t) - self.origin;
+        let (a, b) = (w.dot(op.cross(&self.v)), w.dot(self.u.cross(&op)));
+        if (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b) {
+            Some((t, Point2f::new(a, b)))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::material::null::NullMaterial;
+
+    fn quad() -> Quad<'static> {
+        Quad {
+            origin: point3(-1.0, 0.0, -2.0),
+            u: vec3(2.0, 0.0, 0.0),
+            v: vec3(0.0, 0.0, 4.0),
+            material: &NullMaterial,
+        }
+    }
+
+    #[test]
+    fn quad_ray_intersection() {
+        let q = quad();
+
+        // The front face looks down
+        let ray = Ray::new(point3(0.5, 1.0, 1.0), vec3(0.0, -1.0, 0.0));
+        let hit = q.hit(&ray, 0.0, Float::INFINITY).unwrap();
+        assert_eq!(hit.t, 1.0);
+        assert!(!hit.front_face);
+        assert_eq!(hit.n, vec3(0.0, 1.0, 0.0));
+        assert!((hit.uv.x - 0.75).abs() < 1.0e-6);
+        assert!((hit.uv.y - 0.75).abs() < 1.0e-6);
+        assert_eq!(hit.dpdu, q.u);
+        assert!(q.occluded(&ray, 0.0, 2.0));
+        assert!(!q.occluded(&ray, 0.0, 0.5));
+
+        // Outside of the edges, and parallel to the plane
+        let ray = Ray::new(point3(1.5, 1.0, 1.0), vec3(0.0, -1.0, 0.0));
+        assert!(q.hit(&ray, 0.0, Float::INFINITY).is_none());
+        let ray = Ray::new(point3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0));
+        assert!(q.hit(&ray, 0.0, Float::INFINITY).is_none());
+    }
+
+    #[test]
+    fn quad_samples_are_on_the_surface() {
+        let q = quad();
+        assert_eq!(q.area(), 8.0);
+
+        let bounds = q.world_bound();
+        for &(x, y) in &[(0.0, 0.0), (0.25, 0.5), (0.9, 0.1)] {
+            let (p, n) = q.sample(Point2f::new(x, y));
+            assert_eq!(n, vec3(0.0, -1.0, 0.0));
+            assert_eq!(Bounds3::union_point(&bounds, p).min, bounds.min);
+            assert_eq!(Bounds3::union_point(&bounds, p).max, bounds.max);
+        }
+    }
+}
@@ -3,6 +3,10 @@ use crate::prelude::*;
 use crate::geo::*;
 use crate::hit::*;
 use crate::material::*;
+use crate::primitive::Primitive;
+use crate::shape::Shape;
+
+use std::f32::consts::PI;
 
 pub struct Sphere<'a> {
     pub center: Point3f,
@@ -11,7 +15,40 @@ pub struct Sphere<'a> {
 }
 
 impl Hit for Sphere<'_> {
-    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct> {
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        self.intersection(ray, t_min, t_max)
+            .map(|t| self.hit_struct(t, ray))
+    }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        self.intersection(ray, t_min, t_max).is_some()
+    }
+}
+
+impl Primitive for Sphere<'_> {
+    fn world_bound(&self) -> Bounds3f {
+        let r = vec3(self.radius, self.radius, self.radius);
+        Bounds3::from_corners(self.center + -r, self.center + r)
+    }
+}
+
+impl Shape for Sphere<'_> {
+    fn area(&self) -> Float {
+        4.0 * PI * self.radius * self.radius
+    }
+
+    fn sample(&self, u: Point2f) -> (Point3f, Vec3f) {
+        let y = 1.0 - 2.0 * u.x;
+        let r = (1.0 - y * y).max(0.0).sqrt();
+        let (sin_phi, cos_phi) = (2.0 * PI * u.y).sin_cos();
+        let n = vec3(r * cos_phi, y, r * sin_phi);
+        (self.center + n * self.radius, n)
+    }
+}
+
+impl Sphere<'_> {
+    /// Time of the closest hit between `t_min` and `t_max`.
+    fn intersection(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<Float> {
         let oc = ray.origin() - self.center;
 
         let a = ray.direction().len_squared();
@@ -22,19 +59,128 @@ impl Hit for Sphere<'_> {
         if discriminant > 0.0 {
             let t = (-b - discriminant.sqrt()) / a;
             if t > t_min && t < t_max {
-                let p = ray.eval(t);
-                let n = (p - self.center) * self.radius.recip();
-                return Some(HitStruct::new(t, p, ray, n, self.material));
+                return Some(t);
             }
 
             let t = (-b + discriminant.sqrt()) / a;
             if t > t_min && t < t_max {
-                let p = ray.eval(t);
-                let n = (p - self.center) * self.radius.recip();
-                return Some(HitStruct::new(t, p, ray, n, self.material));
+                return Some(t);
             }
         }
 
-        return None;
+        None
+    }
+
+    fn hit_struct(&self, t: Float, ray: &Ray) -> HitStruct<'_> {
+        let p = ray.eval(t);
+        let n = (p - self.center) * self.radius.recip();
+        let (uv, dpdu, dpdv) = self.parameterization(p);
+        // The normal is just the scaled position on a sphere
+        let (dndu, dndv) = (dpdu * self.radius.recip(), dpdv * self.radius.recip());
+        HitStruct::new(t, p, ray, n, self.material)
+            .with_parameterization(uv, dpdu, dpdv, dndu, dndv)
+    }
+
+    /// Spherical (u, v) coordinates of a point on the sphere, and partial
+    /// derivatives of the position.
+    ///
+    /// `u` goes around the `y` axis, starting from `+x` towards `+z`; `v`
+    /// goes from the north pole (`+y`) to the south pole.
+    fn parameterization(&self, p: Point3f) -> (Point2f, Vec3f, Vec3f) {
+        let Vec3 { x, y, z } = p - self.center;
+        let r = self.radius;
+
+        let phi = z.atan2(x);
+        let phi = if phi < 0.0 { phi + 2.0 * PI } else { phi };
+        let cos_theta = (y / r).clamp(-1.0, 1.0);
+        let theta = cos_theta.acos();
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let (sin_phi, cos_phi) = phi.sin_cos();
+
+        let uv = Point2f::new(phi / (2.0 * PI), theta / PI);
+        let dpdu = vec3(-z, 0.0, x) * (2.0 * PI);
+        let dpdv = vec3(y * cos_phi, -r * sin_theta, y * sin_phi) * PI;
+        (uv, dpdu, dpdv)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::material::null::NullMaterial;
+
+    fn point_at(s: &Sphere, (u, v): (Float, Float)) -> Point3f {
+        let (phi, theta) = (u * 2.0 * PI, v * PI);
+        let dir = vec3(
+            theta.sin() * phi.cos(),
+            theta.cos(),
+            theta.sin() * phi.sin(),
+        );
+        s.center + dir * s.radius
+    }
+
+    #[test]
+    fn sphere_parameterization_matches_finite_differences() {
+        let s = Sphere {
+            center: point3(1.0, -2.0, 0.5),
+            radius: 2.0,
+            material: &NullMaterial,
+        };
+
+        let h = 1.0e-3;
+        for &(u, v) in &[(0.1, 0.3), (0.4, 0.5), (0.8, 0.7)] {
+            let p = point_at(&s, (u, v));
+            let (uv, dpdu, dpdv) = s.parameterization(p);
+            assert!((uv.x - u).abs() < 1.0e-4, "u = {} != {}", uv.x, u);
+            assert!((uv.y - v).abs() < 1.0e-4, "v = {} != {}", uv.y, v);
+
+            let fd_dpdu = (point_at(&s, (u + h, v)) - point_at(&s, (u - h, v))) * (0.5 / h);
+            let fd_dpdv = (point_at(&s, (u, v + h)) - point_at(&s, (u, v - h))) * (0.5 / h);
+            assert!((fd_dpdu - dpdu).len() < 1.0e-2 * dpdu.len());
+            assert!((fd_dpdv - dpdv).len() < 1.0e-2 * dpdv.len());
+        }
+    }
+
+    #[test]
+    fn sphere_samples_are_on_the_surface() {
+        let s = Sphere {
+            center: point3(1.0, -2.0, 0.5),
+            radius: 2.0,
+            material: &NullMaterial,
+        };
+        assert!((s.area() - 16.0 * PI).abs() < 1.0e-4);
+
+        let mut mean = vec3(0.0, 0.0, 0.0);
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = Point2f::new((i as Float + 0.5) / 16.0, (j as Float + 0.5) / 16.0);
+                let (p, n) = s.sample(u);
+                assert!(((p - s.center).len() - s.radius).abs() < 1.0e-5);
+                assert!((n - (p - s.center) * s.radius.recip()).len() < 1.0e-5);
+                mean += n * (1.0 / 256.0);
+            }
+        }
+        // Spread evenly around the center
+        assert!(mean.len() < 1.0e-3);
+    }
+
+    #[test]
+    fn sphere_hit_is_parameterized() {
+        let s = Sphere {
+            center: point3(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: &NullMaterial,
+        };
+        let ray = Ray::new(point3(3.0, 0.5, 0.25), vec3(-1.0, 0.0, 0.0));
+        let hit = s.hit(&ray, 0.0, Float::INFINITY).unwrap();
+
+        // Outward normal agrees with the orientation of the partials
+        let n = hit.dpdu.cross(&hit.dpdv).normalized();
+        assert!((n - hit.n).len() < 1.0e-4);
+        assert!((hit.dndu - hit.dpdu).len() < 1.0e-6);
+
+        let frame = hit.shading_frame();
+        assert!(frame.s.dot(hit.dpdu.normalized()) > 0.999);
     }
 }
@@ -3,19 +3,41 @@ use crate::prelude::*;
 use crate::geo::*;
 use crate::hit::*;
 use crate::material::Material;
-// use crate::shape::Shape;
+use crate::primitive::Primitive;
+use crate::shape::Shape;
 
 pub struct Triangle<'a> {
     pub positions: [Point3f; 3],
     pub material: &'a dyn Material,
+    /// Ignore hits on the back face, for single-sided geometry. The front
+    /// face is the one the vertices appear counter-clockwise from.
+    pub backface_culling: bool,
+    /// Per-vertex (u, v) coordinates. When missing, vertices are assigned
+    /// (0, 0), (1, 0) and (1, 1).
+    pub uvs: Option<[Point2f; 3]>,
+    /// Per-vertex tangents, the direction anisotropic materials are
+    /// stretched along. When missing, `dpdu` is the tangent.
+    pub tangents: Option<[Vec3f; 3]>,
 }
 
 impl Hit for Triangle<'_> {
-    fn hit(&self, ray: &Ray, t_min: f32, t_max: f32) -> Option<HitStruct<'_>> {
-        if let Some(intersection) = self.intersection(ray) {
-            let Intersection { p, t, n, .. } = intersection;
+    fn hit(&self, ray: &Ray, t_min: Float, t_max: Float) -> Option<HitStruct<'_>> {
+        if let Some(intersection) = self.intersection(ray, t_max) {
+            let Intersection { p, t, n, b } = intersection;
             if t > t_min && t < t_max {
-                Some(HitStruct::new(t, p, ray, n, self.material))
+                let (uv, dpdu, dpdv) = self.parameterization(b);
+                // Flat triangle, the normal doesn't change
+                let zero = Vec3f::default();
+                let mut hit = HitStruct::new(t, p, ray, n, self.material)
+                    .with_parameterization(uv, dpdu, dpdv, zero, zero);
+                if let Some([t0, t1, t2]) = self.tangents {
+                    let tangent = t0 * b[0] + t1 * b[1] + t2 * b[2];
+                    if tangent.len_squared() > 0.0 {
+                        let n = hit.shading.n;
+                        hit.set_shading_geometry(n, tangent, n.cross(&tangent), zero, zero);
+                    }
+                }
+                Some(hit)
             } else {
                 None
             }
@@ -23,11 +45,179 @@ impl Hit for Triangle<'_> {
             None
         }
     }
+
+    fn occluded(&self, ray: &Ray, t_min: Float, t_max: Float) -> bool {
+        !self.culls(ray)
+            && matches!(watertight(ray, t_max, self), Some((t, _)) if t > t_min && t < t_max)
+    }
+}
+
+impl Primitive for Triangle<'_> {
+    fn world_bound(&self) -> Bounds3f {
+        let [p0, p1, p2] = self.positions;
+        Bounds3::union_point(&Bounds3::from_corners(p0, p1), p2)
+    }
+
+    /// Clips the triangle against the six planes of the bounds.
+    ///
+    /// ```
+    /// use pbrt::geo::*;
+    /// use pbrt::material::NullMaterial;
+    /// use pbrt::primitive::Primitive;
+    /// use pbrt::shape::triangle::Triangle;
+    ///
+    /// // A long diagonal triangle
+    /// let t = Triangle {
+    ///     positions: [point3(0.0, 0.0, 0.0), point3(8.0, 8.0, 0.0), point3(8.0, 9.0, 0.0)],
+    ///     material: &NullMaterial,
+    ///     backface_culling: false,
+    ///     uvs: None,
+    ///     tangents: None,
+    /// };
+    /// let b = Bounds3::from_corners(point3(0.0, -10.0, -10.0), point3(1.0, 10.0, 10.0));
+    /// let clipped = t.clipped_bound(&b);
+    ///
+    /// assert_eq!(clipped.min, point3(0.0, 0.0, 0.0));
+    /// assert_eq!(clipped.max, point3(1.0, 1.125, 0.0));
+    /// ```
+    fn clipped_bound(&self, bounds: &Bounds3f) -> Bounds3f {
+        let mut polygon = Polygon {
+            vertices: [Point3f::default(); MAX_CLIPPED_VERTICES],
+            len: 3,
+        };
+        polygon.vertices[..3].copy_from_slice(&self.positions);
+        for axis in 0..3 {
+            polygon = polygon.clip(axis, bounds.min[axis], true);
+            polygon = polygon.clip(axis, bounds.max[axis], false);
+        }
+        let clipped = polygon.vertices[..polygon.len]
+            .iter()
+            .fold(Bounds3f::empty(), |b, &p| Bounds3::union_point(&b, p));
+        Bounds3::intersect(&clipped, bounds)
+    }
+}
+
+impl Shape for Triangle<'_> {
+    fn area(&self) -> Float {
+        let [p0, p1, p2] = self.positions;
+        0.5 * (p1 - p0).cross(&(p2 - p0)).len()
+    }
+
+    fn sample(&self, u: Point2f) -> (Point3f, Vec3f) {
+        // Uniform barycentric coordinates
+        let su0 = u.x.sqrt();
+        let (b1, b2) = (1.0 - su0, u.y * su0);
+        let [p0, p1, p2] = self.positions;
+        let p = p0 + ((p1 - p0) * b1 + (p2 - p0) * b2);
+        (p, (p1 - p0).cross(&(p2 - p0)).normalized())
+    }
+}
+
+/// Each of the six planes of a box can add a vertex to a triangle.
+const MAX_CLIPPED_VERTICES: usize = 9;
+
+/// Convex polygon, what's left of a triangle after clipping.
+#[derive(Copy, Clone)]
+struct Polygon {
+    vertices: [Point3f; MAX_CLIPPED_VERTICES],
+    len: usize,
+}
+
+impl Polygon {
+    /// Clips the polygon against an axis-aligned plane, keeping the part
+    /// above or below it (Sutherland-Hodgman).
+    fn clip(&self, axis: usize, plane: Float, keep_above: bool) -> Polygon {
+        let inside = |p: &Point3f| {
+            if keep_above {
+                p[axis] >= plane
+            } else {
+                p[axis] <= plane
+            }
+        };
+        let vertices = &self.vertices[..self.len];
+        if vertices.iter().all(inside) {
+            return *self;
+        }
+
+        let mut result = Polygon {
+            vertices: [Point3f::default(); MAX_CLIPPED_VERTICES],
+            len: 0,
+        };
+        for (i, a) in vertices.iter().enumerate() {
+            let b = &vertices[(i + 1) % vertices.len()];
+            if inside(a) {
+                result.vertices[result.len] = *a;
+                result.len += 1;
+            }
+            if inside(a) != inside(b) {
+                let t = (plane - a[axis]) / (b[axis] - a[axis]);
+                let p = Point3::lerp(t, *a, *b);
+                // Exactly on the plane, so that both sides of a split meet
+                let mut c = [p.x, p.y, p.z];
+                c[axis] = plane;
+                result.vertices[result.len] = point3(c[0], c[1], c[2]);
+                result.len += 1;
+            }
+        }
+        result
+    }
 }
 
 impl Triangle<'_> {
-    fn intersection(&self, ray: &Ray) -> Option<Intersection> {
-        moller_trumbore(ray, self)
+    fn intersection(&self, ray: &Ray, t_max: Float) -> Option<Intersection> {
+        if self.culls(ray) {
+            return None;
+        }
+        let (t, b) = watertight(ray, t_max, self)?;
+        let [v0, v1, v2] = self.positions;
+        let p = v0 + ((v1 - v0) * b[1] + (v2 - v0) * b[2]);
+        let n = (v1 - v0).cross(&(v2 - v0)).normalized();
+        Some(Intersection { p, t, n, b })
+    }
+
+    /// True if the ray sees the back face, and back faces are culled.
+    fn culls(&self, ray: &Ray) -> bool {
+        if self.backface_culling {
+            let [v0, v1, v2] = self.positions;
+            ray.direction().dot((v1 - v0).cross(&(v2 - v0))) >= 0.0
+        } else {
+            false
+        }
+    }
+
+    fn uvs(&self) -> [Point2f; 3] {
+        self.uvs.unwrap_or_else(|| {
+            [
+                Point2f::new(0.0, 0.0),
+                Point2f::new(1.0, 0.0),
+                Point2f::new(1.0, 1.0),
+            ]
+        })
+    }
+
+    /// (u, v) coordinates at the given barycentric coordinates, and partial
+    /// derivatives of the position.
+    fn parameterization(&self, [b0, b1, b2]: [Float; 3]) -> (Point2f, Vec3f, Vec3f) {
+        let [p0, p1, p2] = self.positions;
+        let [uv0, uv1, uv2] = self.uvs();
+
+        let uv = uv0 * b0 + uv1 * b1 + uv2 * b2;
+
+        // Solve the linear system relating differences of positions to
+        // differences of (u, v) coordinates
+        let (duv02, duv12) = (uv0 - uv2, uv1 - uv2);
+        let (dp02, dp12) = (p0 - p2, p1 - p2);
+        let determinant = duv02.x * duv12.y - duv02.y * duv12.x;
+        if determinant.abs() < 1.0e-8 {
+            // Degenerate (u, v) mapping, any frame around the normal will do
+            let n = (p1 - p0).cross(&(p2 - p0)).normalized();
+            let Frame { s, t, .. } = Frame::from_normal(n);
+            return (uv, s, t);
+        }
+        let inv_det = determinant.recip();
+        let dpdu = (dp02 * duv12.y - dp12 * duv02.y) * inv_det;
+        let dpdv = (dp12 * duv02.x - dp02 * duv12.x) * inv_det;
+        (uv, dpdu, dpdv)
     }
 }
 
@@ -38,51 +228,103 @@ struct Intersection {
     n: Vec3f,
     /// Time of intersection.
     t: Float,
-    /// UV coordinates.
-    uv: (Float, Float),
+    /// Barycentric coordinates.
+    b: [Float; 3],
 }
 
-fn moller_trumbore(ray: &Ray, triangle: &Triangle) -> Option<Intersection> {
+/// Watertight ray-triangle intersection, after Woop, Benthin and Wald,
+/// "Watertight Ray/Triangle Intersection" (JCGT, 2013).
+///
+/// Rays that hit an edge or a vertex shared by several triangles are
+/// guaranteed to hit at least one of them, so closed meshes don't leak.
+///
+/// Returns only the time of intersection and barycentric coordinates, which
+/// is all occlusion queries need.
+fn watertight(ray: &Ray, t_max: Float, triangle: &Triangle) -> Option<(Float, [Float; 3])> {
     let [v0, v1, v2] = triangle.positions;
-    let e1 = v1 - v0;
-    let e2 = v2 - v0;
+    let (o, d) = ray.origin_and_direction();
 
-    let h = ray.direction().cross(&e2);
+    // Translate vertices so that the ray starts at the origin
+    let p0t = v0 - o;
+    let p1t = v1 - o;
+    let p2t = v2 - o;
 
-    let a = e1.dot(h);
-    if a > -EPSILON && a < EPSILON {
-        // ray is parallel to triangle
-        return None;
-    }
+    // Permute dimensions so that the ray direction's largest one is z
+    let kz = d.abs().max_dimension();
+    let kx = (kz + 1) % 3;
+    let ky = (kx + 1) % 3;
+    let d = d.permute(kx, ky, kz);
+    let p0t = p0t.permute(kx, ky, kz);
+    let p1t = p1t.permute(kx, ky, kz);
+    let p2t = p2t.permute(kx, ky, kz);
+
+    // Shear vertices so that the ray direction becomes +z
+    let sx = -d.x / d.z;
+    let sy = -d.y / d.z;
+    let sz = d.z.recip();
+    let shear = |p: Vec3f| vec3(p.x + sx * p.z, p.y + sy * p.z, p.z * sz);
+    let p0t = shear(p0t);
+    let p1t = shear(p1t);
+    let p2t = shear(p2t);
 
-    let f = 1.0 / a;
+    // Edge functions, i.e. scaled barycentric coordinates
+    let mut e0 = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1 = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2 = p0t.x * p1t.y - p0t.y * p1t.x;
 
-    let s = ray.origin() - v0;
-    let u = f * s.dot(h);
-    if u < 0.0 || u > 1.0 {
+    // The ray passes exactly through an edge; fall back to double precision
+    // to decide which side of it we're on.
+    if e0 == 0.0 || e1 == 0.0 || e2 == 0.0 {
+        let edge =
+            |a: Vec3f, b: Vec3f| (a.x as f64 * b.y as f64 - a.y as f64 * b.x as f64) as Float;
+        e0 = edge(p1t, p2t);
+        e1 = edge(p2t, p0t);
+        e2 = edge(p0t, p1t);
+    }
+
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det = e0 + e1 + e2;
+    if det == 0.0 {
         return None;
     }
 
-    let q = s.cross(&e1);
-    let v = f * ray.direction().dot(q);
-    if v < 0.0 || u + v > 1.0 {
+    // Compare scaled hit distance against the ray's interval without dividing
+    let t_scaled = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < t_max * det) {
+        return None;
+    }
+    if det > 0.0 && (t_scaled <= 0.0 || t_scaled > t_max * det) {
         return None;
     }
 
-    let t = f * e2.dot(q);
-    if t > EPSILON {
-        // let p = Some(ray.origin() + ray.direction() * t)
-        let p = v0 + (e1 * u + e2 * v);
-        let n = e1.cross(&e2).normalized();
-        Some(Intersection {
-            p,
-            t,
-            n,
-            uv: (u, v),
-        })
-    } else {
-        None
+    let inv_det = det.recip();
+    let (b0, b1, b2) = (e0 * inv_det, e1 * inv_det, e2 * inv_det);
+    let t = t_scaled * inv_det;
+
+    // Make sure that `t` is conservatively greater than zero, accounting for
+    // the rounding errors in the computations above.
+    let max_zt = vec3(p0t.z, p1t.z, p2t.z).abs().max_component();
+    let max_xt = vec3(p0t.x, p1t.x, p2t.x).abs().max_component();
+    let max_yt = vec3(p0t.y, p1t.y, p2t.y).abs().max_component();
+    let delta_z = gamma(3) * max_zt;
+    let delta_x = gamma(5) * (max_xt + max_zt);
+    let delta_y = gamma(5) * (max_yt + max_zt);
+    let delta_e = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    let max_e = vec3(e0, e1, e2).abs().max_component();
+    let delta_t =
+        3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+    if t <= delta_t {
+        return None;
     }
+    Some((t, [b0, b1, b2]))
+}
+
+/// Conservative bound on the relative error of `n` floating-point operations.
+fn gamma(n: i32) -> Float {
+    let e = n as Float * f32::EPSILON * 0.5;
+    e / (1.0 - e)
 }
 
 #[cfg(test)]
@@ -100,23 +342,340 @@ mod test {
                 point3(0.0, 1.0, 0.0),
             ],
             material: &NullMaterial,
+            backface_culling: false,
+            uvs: None,
+            tangents: None,
         };
 
+        // Its neighbour across the hypotenuse
+        let neighbour = Triangle {
+            positions: [
+                point3(1.0, 0.0, 0.0),
+                point3(1.0, 1.0, 0.0),
+                point3(0.0, 1.0, 0.0),
+            ],
+            ..t
+        };
+
+        // Right on the shared edge, which of the two reports the hit depends
+        // on rounding, but one of them always does
         let r = Ray::new(point3(0.0, 0.0, 1.0), vec3(1.0, 2.0, -3.0));
-        let i = t.intersection(&r);
-        assert!(t.intersection(&r).is_some());
+        let i = t
+            .intersection(&r, f32::INFINITY)
+            .or_else(|| neighbour.intersection(&r, f32::INFINITY));
+        assert!(i.is_some());
         let i = i.unwrap();
         assert!((i.p - point3(1.0 / 3.0, 2.0 / 3.0, 0.0)).len() < EPSILON);
         assert_eq!(i.n, vec3(0.0, 0.0, 1.0));
         assert!(r.direction().dot(i.n) < 0.0);
 
         let r = Ray::new(point3(0.0, 0.0, 1.0), vec3(1.0, 1.0, 3.0));
-        assert!(t.intersection(&r).is_none());
+        assert!(t.intersection(&r, f32::INFINITY).is_none());
 
         let r = Ray::new(point3(0.0, 0.0, 1.0), vec3(1.0, 1.0, 0.0));
-        assert!(t.intersection(&r).is_none());
+        assert!(t.intersection(&r, f32::INFINITY).is_none());
 
         let r = Ray::new(point3(0.0, 0.0, 1.0), vec3(1.0, 1.0, -1.0));
-        assert!(t.intersection(&r).is_none());
+        assert!(t.intersection(&r, f32::INFINITY).is_none());
+    }
+
+    #[test]
+    fn triangle_ray_intersection_respects_t_max() {
+        let t = Triangle {
+            positions: [
+                point3(0.0, 0.0, 0.0),
+                point3(1.0, 0.0, 0.0),
+                point3(0.0, 1.0, 0.0),
+            ],
+            material: &NullMaterial,
+            backface_culling: false,
+            uvs: None,
+            tangents: None,
+        };
+
+        let r = Ray::new(point3(0.25, 0.25, 1.0), vec3(0.0, 0.0, -1.0));
+        assert!(t.intersection(&r, 1.5).is_some());
+        assert!(t.intersection(&r, 0.5).is_none());
+    }
+
+    #[test]
+    fn triangle_samples_are_on_the_surface() {
+        let t = Triangle {
+            positions: [
+                point3(0.0, 0.0, 0.0),
+                point3(2.0, 0.0, 0.0),
+                point3(0.0, 2.0, 0.0),
+            ],
+            material: &NullMaterial,
+            backface_culling: false,
+            uvs: None,
+            tangents: None,
+        };
+        assert_eq!(t.area(), 2.0);
+
+        let mut inside_half = 0;
+        for i in 0..16 {
+            for j in 0..16 {
+                let u = Point2f::new((i as Float + 0.5) / 16.0, (j as Float + 0.5) / 16.0);
+                let (p, n) = t.sample(u);
+                assert_eq!(n, vec3(0.0, 0.0, 1.0));
+                assert_eq!(p.z, 0.0);
+                assert!(p.x >= 0.0 && p.y >= 0.0 && p.x + p.y <= 2.0 + 1.0e-6);
+                inside_half += (p.x >= 2.0 - std::f32::consts::SQRT_2) as usize;
+            }
+        }
+        // The corner at (2, 0, 0) of half the area gets half the samples
+        assert_eq!(inside_half, 128);
+    }
+
+    /// Unit cube made of 12 triangles, all facing outwards.
+    fn cube(backface_culling: bool) -> Vec<Triangle<'static>> {
+        let mut vertices = Vec::with_capacity(8);
+        for x in 0..=1 {
+            for y in 0..=1 {
+                for z in 0..=1 {
+                    let (x, y, z) = (
+                        x as Float * 2.0 - 1.0,
+                        y as Float * 2.0 - 1.0,
+                        z as Float * 2.0 - 1.0,
+                    );
+                    vertices.push(point3(x, y, z));
+                }
+            }
+        }
+        let indices = [
+            (0, 1, 3),
+            (0, 3, 2),
+            (0, 4, 5),
+            (0, 5, 1),
+            (0, 2, 6),
+            (0, 6, 4),
+            (7, 5, 4),
+            (7, 4, 6),
+            (7, 6, 2),
+            (7, 2, 3),
+            (7, 3, 1),
+            (7, 1, 5),
+        ];
+        indices
+            .iter()
+            .map(|&(a, b, c)| Triangle {
+                positions: [vertices[a], vertices[b], vertices[c]],
+                material: &NullMaterial,
+                backface_culling,
+                uvs: None,
+                tangents: None,
+            })
+            .collect()
+    }
+
+    /// Vertices, edge midpoints and points along the edges of the cube.
+    fn edge_and_vertex_targets() -> Vec<Point3f> {
+        let mut targets = Vec::new();
+        let steps = 16;
+        for &a in &[-1.0, 1.0] {
+            for &b in &[-1.0, 1.0] {
+                for i in 0..=steps {
+                    let s = i as Float / steps as Float * 2.0 - 1.0;
+                    targets.push(point3(s, a, b));
+                    targets.push(point3(a, s, b));
+                    targets.push(point3(a, b, s));
+                }
+            }
+        }
+        targets
+    }
+
+    fn hits(triangles: &[Triangle], ray: &Ray) -> usize {
+        triangles
+            .iter()
+            .filter(|t| t.intersection(ray, f32::INFINITY).is_some())
+            .count()
+    }
+
+    #[test]
+    fn rays_from_inside_never_leak_through_edges_and_vertices() {
+        let triangles = cube(false);
+        let origins = [
+            point3(0.0, 0.0, 0.0),
+            point3(0.1, -0.3, 0.7),
+            point3(-0.55, 0.25, -0.125),
+        ];
+        for &o in &origins {
+            for &target in &edge_and_vertex_targets() {
+                let ray = Ray::new(o, target - o);
+                assert!(
+                    hits(&triangles, &ray) > 0,
+                    "leak from {:?} to {:?}",
+                    o,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rays_from_outside_never_leak_through_edges_and_vertices() {
+        let triangles = cube(false);
+        let insides = [
+            point3(0.0, 0.0, 0.0),
+            point3(0.1, -0.3, 0.7),
+            point3(-0.55, 0.25, -0.125),
+        ];
+        for &inside in &insides {
+            for &target in &edge_and_vertex_targets() {
+                // Start outside, and pass through the edge into the cube
+                let o = target + (target - inside) * 3.0;
+                let ray = Ray::new(o, inside - o);
+                assert!(
+                    hits(&triangles, &ray) > 0,
+                    "leak from {:?} to {:?}",
+                    o,
+                    target
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shared_edge_of_a_quad_is_hit() {
+        // Two triangles forming a unit square, sharing the diagonal edge.
+        let quad = [
+            Triangle {
+                positions: [
+                    point3(0.0, 0.0, 0.0),
+                    point3(1.0, 0.0, 0.0),
+                    point3(1.0, 1.0, 0.0),
+                ],
+                material: &NullMaterial,
+                backface_culling: false,
+                uvs: None,
+                tangents: None,
+            },
+            Triangle {
+                positions: [
+                    point3(0.0, 0.0, 0.0),
+                    point3(1.0, 1.0, 0.0),
+                    point3(0.0, 1.0, 0.0),
+                ],
+                material: &NullMaterial,
+                backface_culling: false,
+                uvs: None,
+                tangents: None,
+            },
+        ];
+        let steps = 1000;
+        for i in 0..=steps {
+            let s = i as Float / steps as Float;
+            let target = point3(s, s, 0.0);
+            let o = point3(0.3, 0.7, 1.0);
+            let ray = Ray::new(o, target - o);
+            assert!(hits(&quad, &ray) > 0, "leak at {:?}", target);
+        }
+    }
+
+    #[test]
+    fn backface_culling() {
+        // From the inside, only the back faces of the cube are visible
+        let triangles = cube(true);
+        for &target in &edge_and_vertex_targets() {
+            let o = point3(0.0, 0.0, 0.0);
+            let ray = Ray::new(o, target - o);
+            assert_eq!(hits(&triangles, &ray), 0);
+        }
+
+        // From the outside, front faces are still hit, edges included
+        for &target in &edge_and_vertex_targets() {
+            let inside = point3(0.1, -0.3, 0.7);
+            let o = target + (target - inside) * 3.0;
+            let ray = Ray::new(o, inside - o);
+            let front = triangles
+                .iter()
+                .filter_map(|t| t.intersection(&ray, f32::INFINITY))
+                .collect::<Vec<_>>();
+            assert!(!front.is_empty(), "leak from {:?} to {:?}", o, target);
+            assert!(front.iter().all(|i| ray.direction().dot(i.n) < 0.0));
+        }
+    }
+
+    #[test]
+    fn triangle_occlusion_agrees_with_hits() {
+        for &backface_culling in &[false, true] {
+            let triangles = cube(backface_culling);
+            for &target in &edge_and_vertex_targets() {
+                let inside = point3(0.1, -0.3, 0.7);
+                for &o in &[inside, target + (target - inside) * 3.0] {
+                    let ray = Ray::new(o, target - o);
+                    for &(t_min, t_max) in &[(0.0, Float::INFINITY), (0.0, 0.5), (1.5, 4.0)] {
+                        for t in &triangles {
+                            assert_eq!(
+                                t.occluded(&ray, t_min, t_max),
+                                t.hit(&ray, t_min, t_max).is_some()
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn triangle_hit_is_parameterized() {
+        let t = Triangle {
+            positions: [
+                point3(0.0, 0.0, 0.0),
+                point3(2.0, 0.0, 0.0),
+                point3(0.0, 4.0, 0.0),
+            ],
+            material: &NullMaterial,
+            backface_culling: false,
+            uvs: Some([
+                Point2f::new(0.0, 0.0),
+                Point2f::new(1.0, 0.0),
+                Point2f::new(0.0, 1.0),
+            ]),
+            tangents: None,
+        };
+
+        let r = Ray::new(point3(0.5, 1.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = t.hit(&r, 0.0, f32::INFINITY).unwrap();
+        assert!((hit.uv.x - 0.25).abs() < EPSILON);
+        assert!((hit.uv.y - 0.25).abs() < EPSILON);
+        assert!((hit.dpdu - vec3(2.0, 0.0, 0.0)).len() < EPSILON);
+        assert!((hit.dpdv - vec3(0.0, 4.0, 0.0)).len() < EPSILON);
+        assert_eq!(hit.dndu, Vec3f::default());
+
+        let frame = hit.shading_frame();
+        assert_eq!(frame.n, vec3(0.0, 0.0, 1.0));
+        assert!((frame.s - vec3(1.0, 0.0, 0.0)).len() < EPSILON);
+    }
+
+    #[test]
+    fn triangle_tangents_are_interpolated() {
+        let t = Triangle {
+            positions: [
+                point3(0.0, 0.0, 0.0),
+                point3(2.0, 0.0, 0.0),
+                point3(0.0, 4.0, 0.0),
+            ],
+            material: &NullMaterial,
+            backface_culling: false,
+            uvs: None,
+            tangents: Some([
+                vec3(1.0, 0.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+                vec3(0.0, 1.0, 0.0),
+            ]),
+        };
+
+        // Halfway between the first vertex and the others
+        let r = Ray::new(point3(0.5, 1.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let hit = t.hit(&r, 0.0, f32::INFINITY).unwrap();
+        let frame = hit.shading_frame();
+        assert_eq!(frame.n, vec3(0.0, 0.0, 1.0));
+        let diagonal = vec3(1.0, 1.0, 0.0).normalized();
+        assert!((frame.s - diagonal).len() < EPSILON, "{:?}", frame.s);
+        // The true geometry doesn't change
+        assert!((hit.dpdu - vec3(2.0, 0.0, 0.0)).len() < EPSILON);
     }
 }
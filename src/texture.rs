@@ -0,0 +1,86 @@
+use crate::prelude::*;
+
+use crate::geo::*;
+use crate::hit::HitStruct;
+
+/// Value varying over a surface, such as a color or a weight.
+///
+/// Plain values are constant textures.
+///
+/// ```
+/// use pbrt::geo::*;
+/// use pbrt::prelude::*;
+/// use pbrt::texture::Texture;
+///
+/// let weight: &dyn Texture<Float> = &0.3;
+/// let color: &dyn Texture<Vec3f> = &vec3(0.9, 0.1, 0.1);
+/// ```
+pub trait Texture<T>: std::marker::Sync {
+    /// Value at the point of hit.
+    fn evaluate(&self, hit: &HitStruct) -> T;
+}
+
+impl Texture<Float> for Float {
+    fn evaluate(&self, _hit: &HitStruct) -> Float {
+        *self
+    }
+}
+
+impl Texture<Vec3f> for Vec3f {
+    fn evaluate(&self, _hit: &HitStruct) -> Vec3f {
+        *self
+    }
+}
+
+/// Checkerboard in (u, v) space, alternating between `even` and `odd`
+/// `frequency` times per unit.
+#[derive(Copy, Clone, Debug)]
+pub struct Checkerboard<T> {
+    pub even: T,
+    pub odd: T,
+    pub frequency: Float,
+}
+
+impl<T: Copy + std::marker::Sync> Texture<T> for Checkerboard<T> {
+    fn evaluate(&self, hit: &HitStruct) -> T {
+        let u = (hit.uv.x * self.frequency).floor() as i64;
+        let v = (hit.uv.y * self.frequency).floor() as i64;
+        if (u + v).rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::material::NullMaterial;
+
+    #[test]
+    fn checkerboard_alternates() {
+        let ray = Ray::new(point3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0));
+        let zero = Vec3f::default();
+        let hit = |u, v| {
+            HitStruct::new(
+                1.0,
+                point3(0.0, 0.0, 0.0),
+                &ray,
+                vec3(0.0, 0.0, 1.0),
+                &NullMaterial,
+            )
+            .with_parameterization(Point2f::new(u, v), zero, zero, zero, zero)
+        };
+        let checkerboard = Checkerboard {
+            even: 0.0,
+            odd: 1.0,
+            frequency: 4.0,
+        };
+        assert_eq!(checkerboard.evaluate(&hit(0.1, 0.1)), 0.0);
+        assert_eq!(checkerboard.evaluate(&hit(0.3, 0.1)), 1.0);
+        assert_eq!(checkerboard.evaluate(&hit(0.3, 0.3)), 0.0);
+        assert_eq!(checkerboard.evaluate(&hit(0.6, 0.3)), 1.0);
+    }
+}